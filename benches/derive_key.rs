@@ -0,0 +1,39 @@
+//! Key derivation cost per algorithm and profile
+//!
+//! Run with `cargo bench --bench derive_key`. Unlocking is dominated by
+//! `derive_key`, so these numbers are what `calibrate` trades against.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+// aliaser is a binary crate, so the module is compiled in directly
+#[allow(dead_code)]
+#[path = "../src/crypto.rs"]
+mod crypto;
+
+use crypto::{derive_key, KdfAlgorithm, KdfProfile, DEFAULT_SALT_SIZE};
+
+fn bench_derive_key(c: &mut Criterion) {
+    let salt = [7u8; DEFAULT_SALT_SIZE];
+    let mut group = c.benchmark_group("derive_key");
+    // Each derivation takes tens to hundreds of milliseconds
+    group.sample_size(10);
+
+    let cases = [
+        (KdfAlgorithm::Argon2id, KdfProfile::Interactive),
+        (KdfAlgorithm::Argon2id, KdfProfile::Moderate),
+        (KdfAlgorithm::Argon2id, KdfProfile::Paranoid),
+        (KdfAlgorithm::Scrypt, KdfProfile::Interactive),
+        (KdfAlgorithm::Pbkdf2Sha256, KdfProfile::Interactive),
+    ];
+    for (algorithm, profile) in cases {
+        let params = profile.params(algorithm);
+        group.bench_with_input(BenchmarkId::new(algorithm.to_string(), profile), &params, |b, params| {
+            b.iter(|| derive_key("correct horse battery staple", &salt, params).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_derive_key);
+criterion_main!(benches);
@@ -0,0 +1,186 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Kinds of change the audit log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOp {
+    Add,
+    Replace,
+    Update,
+    Delete,
+    Export,
+    Import,
+}
+
+impl fmt::Display for AuditOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AuditOp::Add => "add",
+            AuditOp::Replace => "replace",
+            AuditOp::Update => "update",
+            AuditOp::Delete => "delete",
+            AuditOp::Export => "export",
+            AuditOp::Import => "import",
+        })
+    }
+}
+
+/// One recorded change: what was done and to which service, never any of
+/// the values involved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub op: AuditOp,
+    /// `None` for operations on the whole vault, such as `export`
+    pub service: Option<String>,
+    /// SHA-256 over the previous entry's hash and this entry's fields
+    hash: Vec<u8>,
+}
+
+impl AuditEntry {
+    fn compute_hash(previous: &[u8], at: DateTime<Utc>, op: AuditOp, service: Option<&str>) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(previous);
+        hasher.update(at.to_rfc3339_opts(SecondsFormat::Nanos, true).as_bytes());
+        hasher.update([0]);
+        hasher.update(op.to_string().as_bytes());
+        hasher.update([0]);
+        // Distinguishes no service from an empty name
+        if let Some(service) = service {
+            hasher.update([1]);
+            hasher.update(service.as_bytes());
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// Where the log ended when it was last written: kept in the vault data,
+/// so a log that was deleted, cut short or rolled back no longer reaches it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub count: usize,
+    hash: Vec<u8>,
+}
+
+/// Append-only record of changes to the vault, each entry chained to the
+/// one before it by hash so removed or reordered entries show
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Records `op` on `service` (or the whole vault) as happening now
+    pub fn append(&mut self, op: AuditOp, service: Option<&str>) {
+        let at = Utc::now();
+        let previous = self.entries.last().map_or(&[][..], |entry| &entry.hash);
+        let hash = AuditEntry::compute_hash(previous, at, op, service);
+        self.entries.push(AuditEntry {
+            at,
+            op,
+            service: service.map(str::to_string),
+            hash,
+        });
+    }
+
+    /// The end of the log, or `None` while it is empty
+    pub fn head(&self) -> Option<AuditHead> {
+        self.entries.last().map(|entry| AuditHead {
+            count: self.entries.len(),
+            hash: entry.hash.clone(),
+        })
+    }
+
+    /// Whether the log still contains `head`'s entry at its position. Later
+    /// entries are allowed: an interrupted change can write the log without
+    /// recording its new head.
+    pub fn reaches(&self, head: &AuditHead) -> bool {
+        head.count
+            .checked_sub(1)
+            .and_then(|last| self.entries.get(last))
+            .is_some_and(|entry| entry.hash == head.hash)
+    }
+
+    /// Position of the first entry whose hash doesn't follow from the
+    /// entries before it, if the chain is broken
+    pub fn first_broken(&self) -> Option<usize> {
+        let mut previous: &[u8] = &[];
+        for (i, entry) in self.entries.iter().enumerate() {
+            let expected = AuditEntry::compute_hash(previous, entry.at, entry.op, entry.service.as_deref());
+            if entry.hash != expected {
+                return Some(i);
+            }
+            previous = &entry.hash;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_log() -> AuditLog {
+        let mut log = AuditLog::default();
+        log.append(AuditOp::Add, Some("github"));
+        log.append(AuditOp::Update, Some("github"));
+        log.append(AuditOp::Export, None);
+        log.append(AuditOp::Delete, Some("github"));
+        log
+    }
+
+    #[test]
+    fn test_chain_verifies() {
+        let log = test_log();
+        assert_eq!(log.entries().len(), 4);
+        assert_eq!(log.first_broken(), None);
+
+        let json = serde_json::to_vec(&log).unwrap();
+        let loaded: AuditLog = serde_json::from_slice(&json).unwrap();
+        assert_eq!(loaded.entries(), log.entries());
+        assert_eq!(loaded.first_broken(), None);
+    }
+
+    #[test]
+    fn test_tampering_breaks_chain() {
+        let mut removed = test_log();
+        removed.entries.remove(1);
+        assert_eq!(removed.first_broken(), Some(1));
+
+        let mut reordered = test_log();
+        reordered.entries.swap(1, 2);
+        assert_eq!(reordered.first_broken(), Some(1));
+
+        let mut edited = test_log();
+        edited.entries[2].service = Some("gitlab".to_string());
+        assert_eq!(edited.first_broken(), Some(2));
+    }
+
+    #[test]
+    fn test_head_detects_truncation() {
+        let mut log = test_log();
+        let head = log.head().unwrap();
+        assert!(log.reaches(&head));
+
+        log.append(AuditOp::Add, Some("gitlab"));
+        assert!(log.reaches(&head));
+
+        let mut truncated = test_log();
+        truncated.entries.pop();
+        assert!(!truncated.reaches(&head));
+        assert!(!AuditLog::default().reaches(&head));
+
+        let mut replaced = AuditLog::default();
+        for _ in 0..head.count {
+            replaced.append(AuditOp::Export, None);
+        }
+        assert!(!replaced.reaches(&head));
+    }
+}
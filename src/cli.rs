@@ -1,79 +1,867 @@
-use crate::identity::{Credentials, Identity, PersonalInfo};
-use crate::storage::Vault;
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use colored::Colorize;
+use crate::clipboard;
+use crate::config::{self, Settings};
+use crate::crypto::{self, ct_eq, Cipher, KdfAlgorithm, KdfProfile, DEFAULT_SALT_SIZE, MAX_SALT_SIZE, MIN_SALT_SIZE};
+use crate::generator::{self, CharClass, PasswordPolicy};
+use crate::identity::{
+    Attachment, Credentials, Identity, IdentityEdit, OtpKind, OtpSecret, PersonalInfo, SecurityQuestion,
+    MAX_ATTACHMENT_SIZE,
+};
+use crate::storage::{self, matches_pattern, IdentitySummary, Vault, VaultDiff, VaultError};
+use crate::templates::Template;
+use crate::totp;
+use anyhow::{Context as _, Result};
+use chrono::Utc;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use colored::{Color, ColoredString, Colorize};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
 use rpassword::read_password;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Fixed-width placeholder so masking doesn't leak the secret's length
+const MASKED_SECRET: &str = "••••••••";
 
 #[derive(Parser)]
 #[command(name = "aliaser")]
 #[command(about = "A secure, local identity and password manager", long_about = None)]
 #[command(version)]
 pub struct Cli {
+    /// Log progress to stderr (-v info, -vv debug, -vvv trace); secrets are never logged
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Refuse any operation that would write to disk
+    #[arg(long, global = true)]
+    pub read_only: bool,
+
+    /// Answer yes to confirmation prompts (dangerous with destructive
+    /// commands; the master password is still required)
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// Apply a named settings profile from ~/.config/aliaser/config.toml
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Use this vault config file (password hash, salt, cipher) instead of
+    /// ~/.aliaser.config; the vault file itself stays in the home directory
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Only print errors, prompts and the output a command exists to produce
+    /// (no success messages or hints)
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+/// A single identity field that can be selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Field {
+    Username,
+    Password,
+    Email,
+    Alias,
+    Notes,
+    /// The current one-time password (advances an HOTP counter)
+    Totp,
+    /// The otpauth:// URI for adding the one-time password to an app
+    Url,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::Username => "username",
+            Field::Password => "password",
+            Field::Email => "email",
+            Field::Alias => "alias",
+            Field::Notes => "notes",
+            Field::Totp => "one-time password",
+            Field::Url => "otpauth URI",
+        }
+    }
+
+    /// The field's value for `service`, treating an empty value as absent
+    fn value(self, vault: &Vault, service: &str, identity: &Identity) -> Result<Option<Zeroizing<String>>> {
+        let value = match self {
+            Field::Username => Some(identity.credentials.username.as_str()),
+            Field::Password => Some(identity.credentials.password.as_str()),
+            Field::Email => identity.credentials.email.as_deref(),
+            Field::Alias => identity.credentials.alias.as_deref(),
+            Field::Notes => identity.notes.as_deref(),
+            Field::Totp if identity.otp.is_some() => return Ok(Some(vault.next_otp(service)?)),
+            Field::Url => return Ok(identity.otpauth_uri().map(Zeroizing::new)),
+            Field::Totp => None,
+        };
+        Ok(value.filter(|v| !v.is_empty()).map(|v| Zeroizing::new(v.to_string())))
+    }
+}
+
+/// Generator options shared by `generate` and `update --generate`; anything
+/// not given falls back to the settings file
+#[derive(Debug, Args)]
+pub struct PolicyArgs {
+    /// Password length (defaults to the vault's policy, then the settings file, else 20)
+    #[arg(short, long)]
+    length: Option<usize>,
+    /// Exclude characters that are easy to confuse (l, 1, I, O, 0, ...)
+    #[arg(long, conflicts_with = "allow_ambiguous")]
+    no_ambiguous: bool,
+    /// Allow easily confused characters, even if the defaults exclude them
+    #[arg(long)]
+    allow_ambiguous: bool,
+    /// Leave out symbols
+    #[arg(long, conflicts_with = "symbols")]
+    no_symbols: bool,
+    /// Include symbols, even if the defaults leave them out
+    #[arg(long)]
+    symbols: bool,
+    /// Leave out digits
+    #[arg(long, conflicts_with = "digits")]
+    no_digits: bool,
+    /// Include digits, even if the defaults leave them out
+    #[arg(long)]
+    digits: bool,
+    /// Classes that must each appear at least once (defaults to every enabled class)
+    #[arg(long, value_name = "CLASSES", value_delimiter = ',')]
+    require: Option<Vec<CharClass>>,
+}
+
+impl PolicyArgs {
+    fn policy(&self, defaults: &PasswordPolicy) -> PasswordPolicy {
+        PasswordPolicy {
+            length: self.length.unwrap_or(defaults.length),
+            exclude_ambiguous: self.exclude_ambiguous().unwrap_or(defaults.exclude_ambiguous),
+            symbols: either(self.symbols, self.no_symbols).unwrap_or(defaults.symbols),
+            digits: either(self.digits, self.no_digits).unwrap_or(defaults.digits),
+            required_classes: self.require.clone().or_else(|| defaults.required_classes.clone()),
+            ..defaults.clone()
+        }
+    }
+
+    fn exclude_ambiguous(&self) -> Option<bool> {
+        either(self.no_ambiguous, self.allow_ambiguous)
+    }
+
+    fn is_set(&self) -> bool {
+        self.length.is_some()
+            || self.exclude_ambiguous().is_some()
+            || either(self.symbols, self.no_symbols).is_some()
+            || either(self.digits, self.no_digits).is_some()
+            || self.require.is_some()
+    }
+}
+
+/// A setting from a pair of opposing flags: `Some(true)` for `yes`,
+/// `Some(false)` for `no`, or `None` to keep the default
+fn either(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Single-field changes for `update`, made without prompting for the rest
+#[derive(Debug, Args)]
+pub struct EditArgs {
+    /// Change the username
+    #[arg(long, value_name = "USERNAME")]
+    set_username: Option<String>,
+    /// Change the email ("" to clear it)
+    #[arg(long, value_name = "EMAIL")]
+    set_email: Option<String>,
+    /// Add or change a custom field (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_field)]
+    set_field: Vec<(String, String)>,
+    /// Remove a custom field (repeatable)
+    #[arg(long, value_name = "KEY")]
+    remove_field: Vec<String>,
+    /// Change the color the service is listed in ("" to clear it)
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    set_color: Option<String>,
+    /// Change the icon shown before the service ("" to clear it)
+    #[arg(long, value_name = "ICON", value_parser = parse_icon)]
+    set_icon: Option<String>,
+}
+
+impl EditArgs {
+    fn edit(&self) -> IdentityEdit {
+        IdentityEdit {
+            username: self.set_username.clone(),
+            email: self.set_email.clone(),
+            set_fields: self.set_field.clone(),
+            remove_fields: self.remove_field.clone(),
+            color: self.set_color.clone(),
+            icon: self.set_icon.clone(),
+        }
+    }
+}
+
+/// Which part of a long listing to print
+#[derive(Debug, Args)]
+pub struct PageArgs {
+    /// Print at most this many entries
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    limit: Option<usize>,
+    /// Skip this many entries first
+    #[arg(long, value_name = "M", default_value_t = 0)]
+    offset: usize,
+}
+
+impl PageArgs {
+    fn is_set(&self) -> bool {
+        self.limit.is_some() || self.offset > 0
+    }
+
+    /// The range of a `total`-long listing to print
+    fn range(&self, total: usize) -> std::ops::Range<usize> {
+        let start = self.offset.min(total);
+        let end = self.limit.map_or(total, |limit| start.saturating_add(limit).min(total));
+        start..end
+    }
+}
+
+/// Service name rewrites for `import`, for exports from other managers that
+/// name services by URL or with inconsistent case
+#[derive(Debug, Args)]
+pub struct MapArgs {
+    /// Import the service FROM as TO instead (repeatable)
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_rename)]
+    map: Vec<(String, String)>,
+    /// Drop a leading "www." from service names not given to --map
+    #[arg(long)]
+    strip_www: bool,
+    /// Lowercase service names not given to --map
+    #[arg(long)]
+    lowercase: bool,
+}
+
+impl MapArgs {
+    fn mapping(&self) -> storage::ServiceMapping {
+        storage::ServiceMapping {
+            renames: self.map.clone(),
+            strip_www: self.strip_www,
+            lowercase: self.lowercase,
+        }
+    }
+}
+
+/// Splits a `FROM=TO` rename at the first `=`; neither side may be empty
+fn parse_rename(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
+        }
+        _ => Err("expected FROM=TO".to_string()),
+    }
+}
+
+/// Splits a `KEY=VALUE` custom field at the first `=`
+fn parse_field(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err("expected KEY=VALUE".to_string()),
+    }
+}
+
+/// Longest age `parse_age` accepts, so subtracting it from now can't
+/// overflow the date range
+const MAX_AGE_DAYS: i64 = 1000 * 365;
+
+/// Reads an age such as `90d`: a whole number of days (`d`), weeks (`w`),
+/// months (`mo`, 30 days) or years (`y`, 365 days), up to 1000 years
+fn parse_age(arg: &str) -> Result<chrono::Duration, String> {
+    let arg = arg.trim();
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (count, unit) = arg.split_at(split);
+    let count: i64 = count.parse().map_err(|_| "expected a number and a unit, such as 90d".to_string())?;
+    let days = match unit.to_ascii_lowercase().as_str() {
+        "d" => 1,
+        "w" => 7,
+        "mo" => 30,
+        "y" => 365,
+        _ => return Err("expected a unit of d, w, mo or y, such as 90d".to_string()),
+    };
+    count
+        .checked_mul(days)
+        .filter(|&days| days <= MAX_AGE_DAYS)
+        .and_then(chrono::Duration::try_days)
+        .ok_or_else(|| "age is too large; the most is 1000y".to_string())
+}
+
+/// Most characters an identity's icon may have; emoji built from several
+/// code points need more than one
+const MAX_ICON_CHARS: usize = 8;
+
+/// Looks up a color by name (`red`, `bright-blue`, `bright blue`) or as
+/// `#rrggbb`
+fn color_named(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let [_, r, g, b] = value.to_be_bytes();
+        return Some(Color::TrueColor { r, g, b });
+    }
+    name.replace(['-', '_'], " ").parse().ok()
+}
+
+/// Accepts a color [`color_named`] knows, or "" to clear it
+fn parse_color(arg: &str) -> Result<String, String> {
+    let color = arg.trim().to_lowercase();
+    if color.is_empty() || color_named(&color).is_some() {
+        Ok(color)
+    } else {
+        Err("expected a color such as red or bright-blue, or #rrggbb".to_string())
+    }
+}
+
+/// Accepts an emoji or short glyph, or "" to clear it
+fn parse_icon(arg: &str) -> Result<String, String> {
+    let icon = arg.trim();
+    if icon.chars().count() > MAX_ICON_CHARS || icon.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("expected an emoji or glyph of at most {} characters", MAX_ICON_CHARS));
+    }
+    Ok(icon.to_string())
+}
+
+/// A service name in the identity's color, or `default` if it has none
+fn colored_service(service: &str, color: Option<&str>, default: Color) -> ColoredString {
+    service.color(color.and_then(color_named).unwrap_or(default))
+}
+
+/// An identity's icon and a space, padded to `width` characters
+fn icon_prefix(icon: Option<&str>, width: usize) -> String {
+    let prefix = icon.map(|icon| format!("{} ", icon)).unwrap_or_default();
+    format!("{:<width$}", prefix, width = width)
+}
+
+/// Which way `sync` copies the vault
+#[cfg(any(feature = "sync", feature = "webdav"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SyncDirection {
+    /// Upload the local vault to the server
+    Push,
+    /// Replace the local vault with the server's copy
+    Pull,
+}
+
+/// File formats for `generate-batch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BatchFormat {
+    /// `label,password` lines under a header
+    Csv,
+    /// An array of `{"label", "password"}` objects
+    Json,
+}
+
+/// Orderings for `list`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListSort {
+    Name,
+    Created,
+    Updated,
+}
+
+/// Options shared by all command handlers
+pub struct Context {
+    pub assume_yes: bool,
+    pub read_only: bool,
+    pub quiet: bool,
+    /// Vault config file from `--config`, if any
+    pub config_path: Option<PathBuf>,
+    pub settings: Settings,
+}
+
+impl Context {
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        Ok(Self {
+            assume_yes: cli.yes,
+            read_only: cli.read_only,
+            quiet: cli.quiet,
+            config_path: cli.config.clone(),
+            settings: Settings::load(cli.profile.as_deref())?,
+        })
+    }
+
+    /// Prints a success message, unless `--quiet`
+    fn success(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", message.green().bold());
+        }
+    }
+
+    /// Prints a hint or note about what just happened, unless `--quiet`
+    fn hint(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", message.dimmed());
+        }
+    }
+
+    /// Prints the blank line that sets off a success message, unless `--quiet`
+    fn gap(&self) {
+        if !self.quiet {
+            println!();
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize a new vault with a master password
-    Init,
+    Init {
+        /// Key derivation function
+        #[arg(long, value_enum, default_value_t = KdfAlgorithm::Argon2id)]
+        kdf: KdfAlgorithm,
+        /// Key derivation hardening profile (slower unlock, stronger against brute
+        /// force); defaults to the settings file, else interactive
+        #[arg(long, value_enum)]
+        kdf_profile: Option<KdfProfile>,
+        /// Cipher used to encrypt the vault
+        #[arg(long, value_enum, default_value_t = Cipher::XChaCha20Poly1305)]
+        cipher: Cipher,
+        /// Salt length in bytes, kept for every later rekey and password change
+        #[arg(
+            long,
+            default_value_t = DEFAULT_SALT_SIZE as u16,
+            value_parser = clap::value_parser!(u16).range(MIN_SALT_SIZE as i64..=MAX_SALT_SIZE as i64)
+        )]
+        salt_size: u16,
+    },
     /// Add a new identity
-    Add,
+    Add {
+        /// Replace an existing identity for the same service, keeping its
+        /// creation date and password history
+        #[arg(long, visible_alias = "overwrite")]
+        force: bool,
+        /// Ask for the usual details of this kind of entry; cards and notes skip the login
+        #[arg(long, value_enum)]
+        template: Option<Template>,
+        /// Color to list the service in, by name or #rrggbb
+        #[arg(long, value_parser = parse_color)]
+        color: Option<String>,
+        /// Emoji or short glyph to show before the service
+        #[arg(long, value_parser = parse_icon)]
+        icon: Option<String>,
+    },
     /// List all stored services
-    List,
+    List {
+        /// Order by service name, creation time or last update (oldest first);
+        /// by name unless --older-than is given, then by last update
+        #[arg(long, value_enum)]
+        sort: Option<ListSort>,
+        /// Reverse the order
+        #[arg(short, long)]
+        reverse: bool,
+        /// Show created/updated dates and attachment counts
+        #[arg(short, long)]
+        long: bool,
+        /// Only list favorites
+        #[arg(long)]
+        favorites: bool,
+        /// Only list identities not updated for this long, such as 90d, 8w,
+        /// 6mo or 1y
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<chrono::Duration>,
+        #[command(flatten)]
+        page: PageArgs,
+    },
     /// Get an identity for a service
     Get {
-        /// Service name to retrieve
+        /// Service name to retrieve, or its number in the last `list`
         service: String,
+        /// Reveal the password, security answers and other secrets instead of masking them
+        #[arg(long)]
+        show: bool,
+        /// With --show, reveal even where `guard_secret_output` would refuse
+        #[arg(long, requires = "show")]
+        force: bool,
+        /// With --show, keep everything else masked and show the password for
+        /// only this many seconds before blanking it (plain --show when the
+        /// output isn't a terminal)
+        #[arg(
+            long,
+            value_name = "N",
+            requires = "show",
+            conflicts_with_all = ["copy", "field"],
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        reveal_seconds: Option<u64>,
+        /// Copy a field to the clipboard instead of printing the identity
+        #[arg(short, long)]
+        copy: bool,
+        /// Print only this field's raw value (for scripts), or with --copy,
+        /// the field to copy (defaults to the password)
+        #[arg(short, long, value_enum)]
+        field: Option<Field>,
     },
     /// Update an existing identity
     Update {
         /// Service name to update
         service: String,
+        /// Only replace the password, with a generated one, without prompting
+        #[arg(long)]
+        generate: bool,
+        #[command(flatten)]
+        policy: PolicyArgs,
+        #[command(flatten)]
+        edit: EditArgs,
+    },
+    /// Mark an identity as reviewed without changing it
+    Touch {
+        /// Service name
+        service: String,
+        /// Also restart the password rotation period, as if it had just been changed
+        #[arg(long)]
+        rotated: bool,
+    },
+    /// Mark an identity as a favorite, listed first
+    Favorite {
+        /// Service name
+        service: String,
+    },
+    /// Stop listing an identity among the favorites
+    Unfavorite {
+        /// Service name
+        service: String,
     },
     /// Delete an identity
     Delete {
-        /// Service name to delete
+        /// Exact service name to delete; close names are only suggested
         service: String,
     },
+    /// Delete every identity whose service name matches a pattern
+    DeleteMany {
+        /// Substring, or a glob using `*` and `?` (e.g. "test-*")
+        pattern: String,
+    },
+    /// Delete identities that have neither a username nor a password
+    Prune {
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Export vault to a file (encrypted backup)
     Export {
-        /// Path to export file
+        /// Path to export file, or - for stdout
+        path: PathBuf,
+        /// Include the vault config so the export can be restored on a
+        /// machine with no vault
+        #[arg(long)]
+        self_contained: bool,
+    },
+    /// Export a single identity, encrypted with its own passphrase, to share it
+    ExportOne {
+        /// Service name to export
+        service: String,
+        /// Path to write the shared identity to
+        path: PathBuf,
+    },
+    /// Import a single identity exported with `export-one`
+    ImportOne {
+        /// Path to the shared identity file
         path: PathBuf,
+        /// Replace an existing identity with the same service name
+        #[arg(long)]
+        overwrite: bool,
     },
     /// Import vault from a file
     Import {
-        /// Path to import file
+        /// Path to import file, or - for stdin
+        path: PathBuf,
+        /// Show what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        map: MapArgs,
+    },
+    /// List identities whose password rotation is due
+    Expiring {
+        /// Also include rotations due within this many days
+        #[arg(long, default_value_t = 14)]
+        within_days: u32,
+    },
+    /// Show the record of adds, updates, deletions, imports and exports
+    Log,
+    /// Show when a service's password was last revealed
+    AccessLog {
+        /// Service name
+        service: String,
+    },
+    /// Turn recording of password reveals on or off
+    TrackAccess {
+        /// Whether `get --show` / `get --copy` should record access times
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Show or change the vault's default password generator policy
+    Policy {
+        /// Store the policy given by the generator options (unset options
+        /// come from the settings file)
+        #[arg(long)]
+        set_generator: bool,
+        /// Forget the stored policy and fall back to the settings file
+        #[arg(long, conflicts_with = "set_generator")]
+        clear_generator: bool,
+        #[command(flatten)]
+        policy: PolicyArgs,
+    },
+    /// Show or change how failed unlock attempts are throttled
+    Lockout {
+        /// Failures allowed before attempts are delayed
+        #[arg(long)]
+        free_attempts: Option<u32>,
+        /// Longest delay between attempts, in seconds
+        #[arg(long)]
+        max_delay: Option<u64>,
+        /// Refuse to unlock after this many consecutive failures (0 = never)
+        #[arg(long)]
+        after: Option<u32>,
+        /// How long a lockout lasts, in minutes
+        #[arg(long)]
+        minutes: Option<u32>,
+    },
+    /// Show or consume 2FA recovery codes for a service
+    RecoveryCode {
+        /// Service name
+        service: String,
+        /// Remove and print the next unused code
+        #[arg(long)]
+        consume: bool,
+    },
+    /// Set up or remove the one-time password (2FA) secret for a service
+    SetOtp {
+        /// Service name
+        service: String,
+        /// Counter-based (HOTP) instead of time-based (TOTP)
+        #[arg(long)]
+        hotp: bool,
+        /// HOTP counter value for the next code
+        #[arg(long, default_value_t = 0, requires = "hotp")]
+        counter: u64,
+        /// Code length
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(6..=8))]
+        digits: u32,
+        /// Issuer authenticator apps show for `otp --uri` (asked for if not
+        /// given; defaults to the service name)
+        #[arg(long)]
+        issuer: Option<String>,
+        /// Account authenticator apps show for `otp --uri` (asked for if not
+        /// given; defaults to the username)
+        #[arg(long)]
+        account: Option<String>,
+        /// Remove the secret instead
+        #[arg(long, conflicts_with_all = ["hotp", "counter", "digits", "issuer", "account"])]
+        remove: bool,
+    },
+    /// Print the current one-time password for a service (advances HOTP counters)
+    Otp {
+        /// Service name
+        service: String,
+        /// Print the otpauth:// URI (including the secret) to set the service
+        /// up in an authenticator app instead
+        #[arg(long)]
+        uri: bool,
+    },
+    /// Show a QR code to set up a service's one-time passwords in an
+    /// authenticator app
+    Qr {
+        /// Service name
+        service: String,
+        /// Also save the QR code as a PNG image
+        #[arg(long, value_name = "PATH")]
+        png: Option<PathBuf>,
+    },
+    /// Attach a small file (SSH key, certificate, ...) to an identity
+    Attach {
+        /// Service name
+        service: String,
+        /// File to attach (at most 64 KiB)
         path: PathBuf,
     },
+    /// List an identity's attachments
+    Attachments {
+        /// Service name
+        service: String,
+    },
+    /// Write an attachment back out to a file
+    Extract {
+        /// Service name
+        service: String,
+        /// Attachment name as shown by `attachments`
+        name: String,
+        /// Path to write the file to (created with owner-only permissions)
+        out: PathBuf,
+    },
+    /// Encrypt any file with a passphrase (independent of the vault)
+    EncryptFile {
+        /// File to encrypt
+        #[arg(value_name = "IN")]
+        input: PathBuf,
+        /// Where to write the encrypted file
+        #[arg(value_name = "OUT")]
+        output: PathBuf,
+    },
+    /// Decrypt a file written by `encrypt-file`
+    DecryptFile {
+        /// Encrypted file
+        #[arg(value_name = "IN")]
+        input: PathBuf,
+        /// Where to write the decrypted file (created with owner-only permissions)
+        #[arg(value_name = "OUT")]
+        output: PathBuf,
+    },
     /// Change master password
-    ChangeMaster,
+    ChangeMaster {
+        /// Note why the password changed, shown by `info`
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Re-encrypt the vault with a fresh salt, keeping the master password
+    Rekey {
+        /// Switch to a different key derivation profile while rekeying
+        #[arg(long, value_enum)]
+        kdf_profile: Option<KdfProfile>,
+        /// Switch to a different salt length while rekeying
+        #[arg(long, value_parser = clap::value_parser!(u16).range(MIN_SALT_SIZE as i64..=MAX_SALT_SIZE as i64))]
+        salt_size: Option<u16>,
+    },
+    /// Re-encrypt the vault under another cipher or key derivation function,
+    /// keeping the master password; the old files are backed up first
+    Convert {
+        /// Cipher to switch to
+        #[arg(long, value_enum, required_unless_present = "kdf")]
+        cipher: Option<Cipher>,
+        /// Key derivation function to switch to, at the settings file's profile
+        #[arg(long, value_enum)]
+        kdf: Option<KdfAlgorithm>,
+    },
+    /// Time key derivation on this machine and pick the Argon2id memory cost
+    /// that `init` uses to hit a target unlock time
+    Calibrate {
+        /// Unlock time to aim for, in milliseconds
+        #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+        target_ms: u64,
+    },
+    /// Upgrade a vault created by an older version to the current format
+    Migrate,
+    /// Show the vault's format, encryption and master password history
+    Info,
+    /// List automatic vault backups
+    Backups {
+        /// Set how many backups to keep (oldest are pruned)
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+    /// Generate a random password without storing it
+    Generate {
+        #[command(flatten)]
+        policy: PolicyArgs,
+    },
+    /// Generate distinct passwords for a batch of new accounts and write
+    /// them, labelled, to a plaintext file
+    GenerateBatch {
+        /// Number of passwords (1 to 10000)
+        #[arg(value_parser = clap::value_parser!(u32).range(1..=10_000))]
+        count: u32,
+        /// File to write, created readable by you only
+        out: PathBuf,
+        /// Label prefix; passwords are labelled PREFIX-01, PREFIX-02, ...
+        #[arg(long, default_value = "account")]
+        prefix: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = BatchFormat::Csv)]
+        format: BatchFormat,
+        #[command(flatten)]
+        policy: PolicyArgs,
+    },
+    /// Restore the vault from an automatic backup
+    RestoreBackup {
+        /// Backup timestamp as shown by `backups`
+        timestamp: String,
+    },
+    /// Browse identities in a full-screen terminal UI
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Copy the encrypted vault to or from an SSH or WebDAV server
+    #[cfg(any(feature = "sync", feature = "webdav"))]
+    Sync {
+        #[arg(value_enum)]
+        direction: SyncDirection,
+        /// SSH server and directory as [user@]host[:port][:dir], or a WebDAV
+        /// collection URL as http(s)://[user@]host/path
+        remote: crate::sync::Remote,
+        /// Overwrite even a copy that is newer or has changes of its own
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show where the vault and config files are
+    #[command(visible_alias = "which")]
+    Path,
+    /// Check the setup for common problems (permissions, settings, unlocking)
+    Doctor,
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print stored service names, one per line, for completion scripts
+    #[command(name = "__complete_services", hide = true)]
+    CompleteServices,
 }
 
-pub fn init() -> Result<()> {
-    let mut vault = Vault::new()?;
+pub fn init(
+    ctx: &Context,
+    kdf: KdfAlgorithm,
+    kdf_profile: Option<KdfProfile>,
+    cipher: Cipher,
+    salt_size: u16,
+) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
 
     if vault.is_initialized() {
         println!("{}", "Vault already initialized!".yellow());
         return Ok(());
     }
+    // Before asking for a password that couldn't be saved
+    vault.check_writable()?;
 
     println!("{}", "Initializing new vault...".cyan().bold());
     println!();
 
     let master_password = prompt_new_password("Enter master password: ")?;
 
-    vault.initialize(&master_password)?;
-
+    let (kdf_label, kdf_params) = match (kdf_profile, ctx.settings.kdf_calibration) {
+        (None, Some(calibrated)) if calibrated.algorithm == kdf => ("calibrated".to_string(), calibrated),
+        (profile, _) => {
+            let profile = profile.unwrap_or(ctx.settings.kdf_profile);
+            (profile.to_string(), profile.params(kdf))
+        }
+    };
     println!();
-    println!("{}", "✓ Vault initialized successfully!".green().bold());
-    println!(
-        "{}",
-        "Your data is encrypted with AES-256-GCM and stored locally.".dimmed()
-    );
+    println!("Key derivation profile: {} ({})", kdf_label.bright_white(), kdf_params);
+
+    // Unlocking repeats the same hashing and derivation, so this approximates unlock latency
+    let started = Instant::now();
+    vault.initialize(&master_password, kdf_params, cipher, salt_size.into())?;
+    let elapsed = started.elapsed();
+
+    ctx.gap();
+    ctx.success("✓ Vault initialized successfully!");
+    ctx.hint(&format!("Unlocking takes about {} ms on this machine.", elapsed.as_millis()));
+    ctx.hint(&format!("Your data is encrypted with {} and stored locally.", cipher));
     println!(
         "{}",
         "⚠ Remember your master password - it cannot be recovered!".yellow()
@@ -82,112 +870,353 @@ pub fn init() -> Result<()> {
     Ok(())
 }
 
-pub fn add_identity() -> Result<()> {
-    let mut vault = Vault::new()?;
+pub fn add_identity(
+    ctx: &Context,
+    force: bool,
+    template: Option<Template>,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
     unlock_vault(&mut vault)?;
 
     println!("{}", "Add New Identity".cyan().bold());
     println!();
 
     // Service name
-    let service = prompt("Service name: ")?;
+    let service = prompt_required("Service name: ", "Service name")?;
+    if vault.contains(&service)? {
+        if !force {
+            anyhow::bail!(
+                "Identity for service '{}' already exists; use `update`, or `add --force` to replace it",
+                service
+            );
+        }
+        let message = format!(
+            "Replace the existing identity for '{}'? Its other fields, attachments and one-time password are discarded. (y/n): ",
+            service
+        );
+        if !confirm(ctx, &message)? {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    }
 
     // Credentials
-    println!("{}", "Credentials:".bold());
-    let username = prompt("  Username: ")?;
-    let password = prompt_password("  Password (leave empty to generate): ")?;
-    let password = if password.is_empty() {
-        generate_password()
+    let has_login = template.is_none_or(Template::has_login);
+    let (credentials, generated) = if has_login {
+        match collect_credentials(ctx, &vault, &service)? {
+            Some(collected) => collected,
+            None => {
+                println!("Cancelled.");
+                return Ok(());
+            }
+        }
     } else {
-        password
+        let credentials = Credentials {
+            username: String::new(),
+            password: String::new(),
+            email: None,
+            alias: None,
+            recovery_codes: Vec::new(),
+        };
+        (credentials, false)
     };
-
-    let email = prompt_optional("  Email (optional): ")?;
-    let alias = prompt_optional("  Alias (optional): ")?;
-
-    let credentials = Credentials {
-        username,
-        password: password.clone(),
-        email,
-        alias,
+    let password = credentials.password.clone();
+    let security_questions = if has_login && prompt_yes_no("  Add security questions? (y/n): ")? {
+        collect_security_questions(ctx, &vault)?
+    } else {
+        Vec::new()
     };
 
-    // Personal info
+    // Template fields, then personal info
+    let mut info = PersonalInfo::new();
+    if let Some(template) = template {
+        collect_template_fields(template, &mut info)?;
+    }
     println!();
     let add_personal = prompt_yes_no("Add personal information? (y/n): ")?;
 
     let personal_info = if add_personal {
-        Some(collect_personal_info()?)
+        Some(collect_personal_info(info)?)
+    } else if !info.custom_fields.is_empty() {
+        Some(info)
     } else {
         None
     };
 
     // Notes
     println!();
-    let notes = prompt_optional("Notes (optional): ")?;
+    let notes = prompt_notes("Notes (optional, 'edit' for multi-line): ", None)?;
+
+    let rotate_after_days = if has_login {
+        prompt_rotation_days("Rotate password every N days (optional): ")?
+    } else {
+        None
+    };
 
     // Create identity
     let mut identity = Identity::new(service.clone(), credentials);
+    identity.template = template;
     identity.personal_info = personal_info;
     identity.notes = notes;
+    identity.rotate_after_days = rotate_after_days;
+    identity.security_questions = security_questions;
+    identity.color = color.filter(|color| !color.is_empty());
+    identity.icon = icon.filter(|icon| !icon.is_empty());
 
     // Save
-    vault.add_identity(identity)?;
+    let replaced = if force {
+        vault.add_or_replace_identity(identity)?
+    } else {
+        vault.add_identity(identity)?;
+        false
+    };
 
     println!();
-    println!("{}", "✓ Identity added successfully!".green().bold());
-    if !password.is_empty() {
+    if replaced {
+        ctx.success("✓ Identity replaced successfully!");
+        ctx.hint("The previous password was added to its history.");
+    } else {
+        ctx.success("✓ Identity added successfully!");
+    }
+    if generated {
         println!("Generated password: {}", password.bright_yellow());
     }
 
     Ok(())
 }
 
-pub fn list_identities() -> Result<()> {
-    let mut vault = Vault::new()?;
+pub fn list_identities(
+    ctx: &Context,
+    sort: Option<ListSort>,
+    reverse: bool,
+    long: bool,
+    favorites: bool,
+    older_than: Option<chrono::Duration>,
+    page: &PageArgs,
+) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
     unlock_vault(&mut vault)?;
 
-    let services = vault.list_services()?;
+    let cutoff = older_than.map(|age| Utc::now() - age);
+    let mut summaries = match (favorites, cutoff) {
+        (false, None) => vault.list_detailed()?,
+        (false, Some(cutoff)) => vault.list_older_than(cutoff)?,
+        (true, cutoff) => {
+            let mut summaries = vault.list_favorites()?;
+            summaries.retain(|s| cutoff.is_none_or(|cutoff| s.updated_at < cutoff));
+            summaries
+        }
+    };
+
+    if summaries.is_empty() {
+        let message = match (favorites, older_than) {
+            (_, Some(age)) => format!("Nothing left unchanged for {} days.", age.num_days()),
+            (true, None) => "No favorites yet.".to_string(),
+            (false, None) => "No identities stored yet.".to_string(),
+        };
+        println!("{}", message.yellow());
+        return Ok(());
+    }
+
+    // Stale entries are what --older-than looks for, so oldest come first
+    let sort = sort.unwrap_or(if older_than.is_some() { ListSort::Updated } else { ListSort::Name });
+    match sort {
+        ListSort::Name => {}
+        ListSort::Created => summaries.sort_by_key(|s| s.created_at),
+        ListSort::Updated => summaries.sort_by_key(|s| s.updated_at),
+    }
+    if reverse {
+        summaries.reverse();
+    }
+    // Stable, so favorites keep the chosen order among themselves
+    summaries.sort_by_key(|s| !s.favorite);
+
+    // The whole order is remembered, so numbers on any page work with `get`
+    let order: Vec<String> = summaries.iter().map(|s| s.service.clone()).collect();
+    if let Err(e) = vault.remember_listing(&order) {
+        log::warn!("Failed to remember the listing order: {:#}", e);
+    }
 
-    if services.is_empty() {
-        println!("{}", "No identities stored yet.".yellow());
+    let total = summaries.len();
+    let range = page.range(total);
+    if range.is_empty() {
+        println!("{}", format!("Nothing past entry {}; there are {} in all.", page.offset, total).yellow());
         return Ok(());
     }
+    let first = range.start;
+    let shown = &summaries[range];
 
     println!("{}", "Stored Identities:".cyan().bold());
     println!();
 
-    for (i, service) in services.iter().enumerate() {
-        println!("  {}. {}", i + 1, service.bright_white());
+    let width = shown.iter().map(|s| s.service.chars().count()).max().unwrap_or(0);
+    let icon_width = shown
+        .iter()
+        .filter_map(|s| s.icon.as_ref())
+        .map(|icon| icon.chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+    for (i, summary) in shown.iter().enumerate() {
+        let position = first + i + 1;
+        let star = if summary.favorite { "★".yellow() } else { " ".normal() };
+        let icon = icon_prefix(summary.icon.as_deref(), icon_width);
+        if long {
+            println!("  {}. {} {}{}", position, star, icon, format_summary(summary, width));
+        } else {
+            let service = colored_service(&summary.service, summary.color.as_deref(), Color::BrightWhite);
+            println!("  {}. {} {}{}", position, star, icon, service);
+        }
     }
 
     println!();
-    println!("Total: {}", services.len().to_string().green());
+    if page.is_set() {
+        println!(
+            "Showing {}..{} of {}",
+            first + 1,
+            first + shown.len(),
+            total.to_string().green()
+        );
+    } else {
+        println!("Total: {}", total.to_string().green());
+    }
 
     Ok(())
 }
 
-pub fn get_identity(service: &str) -> Result<()> {
-    let mut vault = Vault::new()?;
+/// Treats a bare number as a position in the last `list`, unless a service
+/// is actually named that
+fn resolve_listed(vault: &Vault, service: &str) -> Result<String> {
+    match service.parse::<usize>() {
+        Ok(position) if !vault.contains(service)? => vault.listed_service(position),
+        _ => Ok(service.to_string()),
+    }
+}
+
+fn format_summary(summary: &IdentitySummary, width: usize) -> String {
+    let mut line = format!(
+        "{:<width$}  {}  {}",
+        colored_service(&summary.service, summary.color.as_deref(), Color::BrightWhite),
+        format!("created {}", summary.created_at.format("%Y-%m-%d")).dimmed(),
+        format!("updated {}", summary.updated_at.format("%Y-%m-%d")).dimmed(),
+        width = width
+    );
+    if summary.attachments > 0 {
+        let noun = if summary.attachments == 1 { "attachment" } else { "attachments" };
+        line.push_str(&format!("  {} {}", summary.attachments, noun));
+    }
+    if summary.has_otp {
+        line.push_str("  otp");
+    }
+    if summary.rotation_overdue() {
+        line.push_str(&format!("  {}", "rotation overdue".red()));
+    }
+    line
+}
+
+pub fn get_identity(
+    ctx: &Context,
+    service: &str,
+    show: bool,
+    force: bool,
+    reveal_seconds: Option<u64>,
+    copy: bool,
+    field: Option<Field>,
+) -> Result<()> {
+    // Checked before unlocking so nothing is typed for a reveal that won't happen
+    let reveals = show && !copy && field.is_none();
+    if reveals && ctx.settings.guard_secret_output && !force && !is_output_safe_for_secrets() {
+        anyhow::bail!(
+            "Not revealing secrets: output isn't a terminal or the session looks recorded; pass --force to show them anyway"
+        );
+    }
+
+    let mut vault = open_vault(ctx)?;
+
+    // A bare --field is for `$(aliaser get ... --field ...)`: stdout carries
+    // the value and nothing else, and a missing value is an error
+    if let (Some(field), false) = (field, copy) {
+        unlock_vault_quietly(&mut vault)?;
+        let service = &resolve_listed(&vault, service)?;
+        let identity = vault.get_identity(service)?;
+        let value = field
+            .value(&vault, service, &identity)?
+            .context(format!("Identity '{}' has no {}", service, field.name()))?;
+
+        println!("{}", value.as_str());
+        if field == Field::Password {
+            vault.record_access(service)?;
+        }
+        return Ok(());
+    }
+
     unlock_vault(&mut vault)?;
 
+    let service = &resolve_listed(&vault, service)?;
     let identity = vault.get_identity(service)?;
 
+    // Blanking the line needs a terminal to move the cursor in
+    let reveal_seconds = reveal_seconds.filter(|_| io::stdout().is_terminal());
+    let shows_all = show && reveal_seconds.is_none();
+
+    if copy {
+        let field = field.unwrap_or(Field::Password);
+        let value = field
+            .value(&vault, service, &identity)?
+            .context(format!("Identity '{}' has no {}", service, field.name()))?;
+
+        let tool = clipboard::copy(&value)?;
+        clipboard::clear_after(ctx.settings.clipboard_seconds)?;
+        if field == Field::Password {
+            vault.record_access(service)?;
+        }
+
+        ctx.success(&format!("✓ Copied {} for '{}' to the clipboard.", field.name(), service));
+        ctx.hint(&format!("It will be cleared in {} seconds.", ctx.settings.clipboard_seconds));
+        if let Some(caveat) = tool.caveat {
+            println!("{}", format!("Note: {}", caveat).yellow());
+        }
+        return Ok(());
+    }
+
     println!();
-    println!("{}", format!("Identity: {}", service).cyan().bold());
+    println!(
+        "{} {}{}",
+        "Identity:".cyan().bold(),
+        icon_prefix(identity.icon.as_deref(), 0),
+        colored_service(service, identity.color.as_deref(), Color::Cyan).bold()
+    );
     println!("{}", "=".repeat(50).dimmed());
+    if let Some(template) = identity.template {
+        println!("Kind: {}", template.to_string().bright_white());
+    }
     println!();
 
-    // Credentials
-    println!("{}", "Credentials:".bold());
-    println!("  Username: {}", identity.credentials.username.bright_white());
-    println!("  Password: {}", identity.credentials.password.bright_yellow());
+    // Credentials; cards and notes usually have none
+    let credentials = &identity.credentials;
+    let no_login = credentials.username.is_empty() && credentials.password.is_empty();
+    if !(no_login && identity.template.is_some_and(|template| !template.has_login())) {
+        println!("{}", "Credentials:".bold());
+        println!("  Username: {}", credentials.username.bright_white());
+        println!("  Password: {}", display_secret(&credentials.password, shows_all));
+    }
     if let Some(email) = &identity.credentials.email {
         println!("  Email: {}", email.bright_white());
     }
     if let Some(alias) = &identity.credentials.alias {
         println!("  Alias: {}", alias.bright_white());
     }
+    if !identity.password_history.is_empty() {
+        println!("  Previous Passwords:");
+        for previous in &identity.password_history {
+            println!(
+                "    {}  {}",
+                previous.replaced_at.format("%Y-%m-%d").to_string().dimmed(),
+                display_secret(&previous.password, shows_all)
+            );
+        }
+    }
 
     // Personal info
     if let Some(info) = &identity.personal_info {
@@ -213,36 +1242,135 @@ pub fn get_identity(service: &str) -> Result<()> {
             println!();
             println!("  Custom Fields:");
             for field in &info.custom_fields {
-                println!("    {}: {}", field.key, field.value.bright_white());
+                let value = if identity.template.is_some_and(|template| template.is_secret_field(&field.key)) {
+                    display_secret(&field.value, shows_all)
+                } else {
+                    field.value.bright_white().to_string()
+                };
+                println!("    {}: {}", field.key, value);
             }
         }
     }
 
+    // One-time password setup; the code itself comes from `otp`
+    if let Some(otp) = &identity.otp {
+        let kind = match otp.kind {
+            OtpKind::Totp => "TOTP".to_string(),
+            OtpKind::Hotp { counter } => format!("HOTP, next counter {}", counter),
+        };
+        println!("  One-time Password: {} ({} digits; `aliaser otp`)", kind, otp.digits);
+    }
+
+    // Recovery codes
+    if !identity.credentials.recovery_codes.is_empty() {
+        println!();
+        println!(
+            "{}",
+            format!("Recovery Codes ({} unused):", identity.credentials.recovery_codes.len()).bold()
+        );
+        for code in &identity.credentials.recovery_codes {
+            println!("  {}", display_secret(code, shows_all));
+        }
+    }
+
+    if !identity.security_questions.is_empty() {
+        println!();
+        println!("{}", "Security Questions:".bold());
+        for entry in &identity.security_questions {
+            println!("  {}", entry.question.bright_white());
+            println!("    Answer: {}", display_secret(&entry.answer, shows_all));
+        }
+    }
+
+    // Attachments
+    if !identity.attachments.is_empty() {
+        println!();
+        println!("{}", "Attachments:".bold());
+        for attachment in &identity.attachments {
+            println!("  {} ({} bytes)", attachment.name.bright_white(), attachment.data.len());
+        }
+    }
+
     // Notes
     if let Some(notes) = &identity.notes {
         println!();
         println!("{}", "Notes:".bold());
-        println!("  {}", notes.bright_white());
+        for line in notes.lines() {
+            println!("  {}", line.bright_white());
+        }
     }
 
     // Metadata
     println!();
+    if let Some(due) = identity.rotation_due() {
+        let message = format!("Password rotation due: {}", due.format("%Y-%m-%d"));
+        if identity.rotation_overdue() {
+            println!("{}", format!("⚠ {} (overdue)", message).red().bold());
+        } else {
+            println!("{}", message.dimmed());
+        }
+    }
     println!("{}", "Metadata:".dimmed());
     println!("  Created: {}", identity.created_at.format("%Y-%m-%d %H:%M:%S").to_string().dimmed());
     println!("  Updated: {}", identity.updated_at.format("%Y-%m-%d %H:%M:%S").to_string().dimmed());
 
+    if let Some(seconds) = reveal_seconds.filter(|_| !identity.credentials.password.is_empty()) {
+        println!();
+        reveal_briefly("Password", &identity.credentials.password, seconds)?;
+    }
+    if show {
+        vault.record_access(service)?;
+    }
+
     Ok(())
 }
 
-pub fn update_identity(service: &str) -> Result<()> {
-    let mut vault = Vault::new()?;
+pub fn update_identity(
+    ctx: &Context,
+    service: &str,
+    generate: bool,
+    policy: &PolicyArgs,
+    edit: &EditArgs,
+) -> Result<()> {
+    if policy.is_set() && !generate {
+        anyhow::bail!("Generator options only apply with --generate");
+    }
+    let edit = edit.edit();
+    if generate && !edit.is_empty() {
+        anyhow::bail!("--generate can't be combined with --set-* or --remove-field");
+    }
+
+    let mut vault = open_vault(ctx)?;
     unlock_vault(&mut vault)?;
 
     let mut identity = vault.get_identity(service)?;
 
-    println!("{}", format!("Update Identity: {}", service).cyan().bold());
-    println!("{}", "(Press Enter to keep current value)".dimmed());
-    println!();
+    if !edit.is_empty() {
+        let missing = identity.apply_edit(&edit);
+        if !missing.is_empty() {
+            anyhow::bail!("No custom field named '{}' on '{}'", missing.join("', '"), service);
+        }
+        vault.update_identity(service, identity)?;
+
+        ctx.gap();
+        ctx.success(&format!("✓ Identity '{}' updated.", service));
+        return Ok(());
+    }
+
+    if generate {
+        let old_length = identity.credentials.password.chars().count();
+        identity.credentials.password = generator::generate(&policy.policy(&generator_defaults(ctx, &vault)?))?;
+        print_password_change(ctx, old_length, &identity.credentials.password);
+        vault.update_identity(service, identity)?;
+
+        ctx.gap();
+        ctx.success(&format!("✓ Password for '{}' regenerated.", service));
+        return Ok(());
+    }
+
+    println!("{}", format!("Update Identity: {}", service).cyan().bold());
+    println!("{}", "(Press Enter to keep current value)".dimmed());
+    println!();
 
     // Update credentials
     println!("{}", "Credentials:".bold());
@@ -254,11 +1382,16 @@ pub fn update_identity(service: &str) -> Result<()> {
 
     if prompt_yes_no("  Update password? (y/n): ")? {
         let new_password = prompt_password("  New password (leave empty to generate): ")?;
-        identity.credentials.password = if new_password.is_empty() {
-            generate_password()
+        let old_length = identity.credentials.password.chars().count();
+        if new_password.is_empty() {
+            identity.credentials.password = generator::generate(&generator_defaults(ctx, &vault)?)?;
+            print_password_change(ctx, old_length, &identity.credentials.password);
+        } else if confirm_password_reuse(ctx, &vault, &new_password, service)? {
+            identity.credentials.password = new_password;
+            print_password_change(ctx, old_length, &identity.credentials.password);
         } else {
-            new_password
-        };
+            println!("  Keeping the current password.");
+        }
     }
 
     let new_email = prompt_optional("  Email: ")?;
@@ -271,119 +1404,1413 @@ pub fn update_identity(service: &str) -> Result<()> {
         identity.credentials.alias = new_alias;
     }
 
-    // Update personal info
-    if prompt_yes_no("\nUpdate personal information? (y/n): ")? {
-        identity.personal_info = Some(collect_personal_info()?);
+    if prompt_yes_no("  Replace 2FA recovery codes? (y/n): ")? {
+        identity.credentials.recovery_codes = collect_recovery_codes()?;
+    }
+
+    let replace_questions = if identity.security_questions.is_empty() {
+        "  Add security questions? (y/n): "
+    } else {
+        "  Replace security questions? (y/n): "
+    };
+    if prompt_yes_no(replace_questions)? {
+        identity.security_questions = collect_security_questions(ctx, &vault)?;
+    }
+
+    // Update personal info
+    if prompt_yes_no("\nUpdate personal information? (y/n): ")? {
+        let mut info = identity.personal_info.take().unwrap_or_default();
+        if let Some(template) = identity.template {
+            collect_template_fields(template, &mut info)?;
+            println!();
+        }
+        identity.personal_info = Some(collect_personal_info(info)?);
+    } else if let Some(info) = identity.personal_info.as_mut() {
+        if prompt_yes_no("Edit custom fields? (y/n): ")? {
+            edit_custom_fields(info)?;
+        }
+    }
+
+    // Update notes
+    println!();
+    let new_notes = prompt_notes("Notes ('edit' for multi-line): ", identity.notes.as_deref())?;
+    if new_notes.is_some() {
+        identity.notes = new_notes;
+    }
+
+    let new_rotation = prompt_rotation_days("Rotate password every N days: ")?;
+    if new_rotation.is_some() {
+        identity.rotate_after_days = new_rotation;
+    }
+
+    vault.update_identity(service, identity)?;
+
+    ctx.gap();
+    ctx.success("✓ Identity updated successfully!");
+
+    Ok(())
+}
+
+/// Warns if `password` is already used by another identity and asks whether
+/// to use it anyway; true if it isn't reused or the user accepts
+fn confirm_password_reuse(ctx: &Context, vault: &Vault, password: &str, service: &str) -> Result<bool> {
+    let mut reused = vault.services_using_password(password)?;
+    reused.retain(|other| other != service);
+    if reused.is_empty() {
+        return Ok(true);
+    }
+
+    println!(
+        "{}",
+        format!("  This password is already used for: {}", reused.join(", ")).yellow()
+    );
+    confirm(ctx, "  Use it anyway? (y/n): ")
+}
+
+/// Reports how the password length changed; a non-empty previous password
+/// is kept in the identity's history when the vault saves it
+fn print_password_change(ctx: &Context, old_length: usize, new_password: &str) {
+    let mut message = format!("  Password length: {} -> {}", old_length, new_password.chars().count());
+    if old_length > 0 {
+        message.push_str(" (previous password kept in history)");
+    }
+    ctx.hint(&message);
+}
+
+pub fn touch(ctx: &Context, service: &str, rotated: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    vault.touch(service, rotated)?;
+
+    let message = if rotated {
+        format!("✓ Marked '{}' as reviewed and its password as rotated.", service)
+    } else {
+        format!("✓ Marked '{}' as reviewed.", service)
+    };
+    ctx.success(&message);
+
+    Ok(())
+}
+
+pub fn set_favorite(ctx: &Context, service: &str, favorite: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    match (favorite, vault.set_favorite(service, favorite)?) {
+        (true, true) => ctx.success(&format!("✓ '{}' added to favorites.", service)),
+        (true, false) => ctx.hint(&format!("'{}' is already a favorite.", service)),
+        (false, true) => ctx.success(&format!("✓ '{}' removed from favorites.", service)),
+        (false, false) => ctx.hint(&format!("'{}' isn't a favorite.", service)),
+    }
+
+    Ok(())
+}
+
+pub fn delete_identity(ctx: &Context, service: &str) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    // Only an exact name gets as far as the prompt; anything else fails here
+    // with suggestions. Numbers from `list` aren't accepted either.
+    vault.get_identity(service)?;
+
+    println!(
+        "{}",
+        format!("Delete identity for '{}'?", service).yellow().bold()
+    );
+    println!("{}", "This action cannot be undone!".red());
+
+    if !confirm(ctx, "\nConfirm deletion (y/n): ")? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    vault.delete_identity(service)?;
+
+    ctx.gap();
+    ctx.success("✓ Identity deleted successfully.");
+
+    Ok(())
+}
+
+pub fn delete_many(ctx: &Context, pattern: &str) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let matches = vault.find_matching(|identity| matches_pattern(pattern, &identity.service))?;
+
+    if matches.is_empty() {
+        println!("{}", format!("No identities match '{}'.", pattern).yellow());
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        format!("The following {} identities will be deleted:", matches.len())
+            .yellow()
+            .bold()
+    );
+    for service in &matches {
+        println!("  {}", service.bright_white());
+    }
+    println!("{}", "This action cannot be undone!".red());
+    println!();
+
+    if !confirm_phrase(ctx, "DELETE")? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let removed = vault.delete_matching(|identity| matches.contains(&identity.service))?;
+
+    ctx.gap();
+    ctx.success(&format!("✓ Deleted {} identities.", removed.len()));
+
+    Ok(())
+}
+
+pub fn prune(ctx: &Context, dry_run: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let incomplete = vault.find_incomplete()?;
+
+    if incomplete.is_empty() {
+        println!("{}", "No incomplete identities found.".green());
+        return Ok(());
+    }
+
+    let heading = if dry_run {
+        format!(
+            "{} identities have no username or password (dry run, nothing deleted):",
+            incomplete.len()
+        )
+    } else {
+        format!("The following {} identities have no username or password:", incomplete.len())
+    };
+    println!("{}", heading.yellow().bold());
+    for service in &incomplete {
+        println!("  {}", service.bright_white());
+    }
+    if dry_run {
+        return Ok(());
+    }
+    println!();
+
+    if !confirm(ctx, "Delete them? (y/n): ")? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let removed = vault.delete_matching(|identity| incomplete.contains(&identity.service))?;
+
+    ctx.gap();
+    ctx.success(&format!("✓ Deleted {} identities.", removed.len()));
+
+    Ok(())
+}
+
+pub fn export_data(ctx: &Context, path: &Path, self_contained: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+
+    // With `-`, stdout carries the export and nothing else
+    if is_std_stream(path) {
+        let mut out = open_output(path)?;
+        unlock_vault_quietly(&mut vault)?;
+        return vault.export(&mut out, self_contained);
+    }
+    unlock_vault(&mut vault)?;
+
+    vault.export(&mut open_output(path)?, self_contained)?;
+
+    ctx.gap();
+    ctx.success(&format!("✓ Vault exported to: {}", path.display()));
+    ctx.hint("The exported file is encrypted with your master password.");
+    if self_contained {
+        ctx.hint("It includes the vault config, so `aliaser import` can restore it where there is no vault.");
+    }
+
+    Ok(())
+}
+
+pub fn export_one(ctx: &Context, service: &str, path: &Path) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    println!("{}", "Choose a passphrase to share with the recipient.".dimmed());
+    let passphrase = prompt_new_password("Share passphrase: ")?;
+
+    vault.export_identity(service, path, &passphrase)?;
+
+    ctx.gap();
+    ctx.success(&format!("✓ Identity '{}' exported to: {}", service, path.display()));
+    ctx.hint("Send the passphrase to the recipient separately from the file.");
+
+    Ok(())
+}
+
+pub fn import_one(ctx: &Context, path: &Path, overwrite: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let passphrase = prompt_master_password("Share passphrase: ")?;
+    println!();
+
+    let service = vault.import_identity(path, &passphrase, overwrite)?;
+
+    ctx.success(&format!("✓ Identity '{}' imported successfully!", service));
+
+    Ok(())
+}
+
+pub fn import_data(ctx: &Context, path: &Path, dry_run: bool, map: &MapArgs) -> Result<()> {
+    let mapping = map.mapping();
+    let mut vault = open_vault(ctx)?;
+    if !vault.is_initialized() {
+        if !mapping.is_empty() {
+            anyhow::bail!("A restore keeps the export's service names; run `aliaser init` first to import with renames");
+        }
+        return restore_data(ctx, &mut vault, path, dry_run);
+    }
+    // The master password is read from the terminal, but the confirmation
+    // would be read from the same stdin as the export
+    if is_std_stream(path) && !dry_run && !ctx.assume_yes {
+        anyhow::bail!("Importing from stdin can't ask for confirmation; pass --yes to overwrite the vault");
+    }
+    unlock_vault(&mut vault)?;
+
+    // Read once, since stdin can't be read again after asking for a password
+    let mut contents = Vec::new();
+    open_input(path)?.read_to_end(&mut contents).context("Failed to read export file")?;
+    let mut incoming = match vault.load_import(contents.as_slice(), None) {
+        Err(e) if matches!(e.downcast_ref(), Some(VaultError::ExportPasswordNeeded)) => {
+            println!("{}", e.to_string().yellow());
+            let password = prompt_master_password("Master password of the exported vault: ")?;
+            println!();
+            vault.load_import(contents.as_slice(), Some(&password))?
+        }
+        result => result?,
+    };
+    if !mapping.is_empty() {
+        let before = incoming.identities.len();
+        let renames = incoming.remap_services(&mapping);
+        print_renames(&renames, before - incoming.identities.len());
+    }
+    if dry_run {
+        print_diff(&vault.diff(&incoming)?);
+        return Ok(());
+    }
+
+    let current = vault.identity_count()?;
+
+    println!(
+        "{}",
+        "This will overwrite your current vault!".yellow().bold()
+    );
+    println!("  Current identities:  {}", current.to_string().red());
+    println!("  Incoming identities: {}", incoming.identities.len().to_string().green());
+    println!();
+    if !confirm_phrase(ctx, "OVERWRITE")? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    vault.replace_data(incoming)?;
+
+    ctx.gap();
+    ctx.success("✓ Vault imported successfully!");
+    ctx.hint("The previous vault was backed up; see `aliaser backups`.");
+
+    Ok(())
+}
+
+/// Sets up a new vault from a self-contained export
+fn restore_data(ctx: &Context, vault: &mut Vault, path: &Path, dry_run: bool) -> Result<()> {
+    if dry_run {
+        anyhow::bail!("There is no vault to compare against; run without --dry-run to restore");
+    }
+
+    println!("{}", "No vault found; restoring from the export.".cyan());
+    let password = prompt_master_password("Master password of the exported vault: ")?;
+    println!();
+
+    let count = vault.restore(open_input(path)?, &password)?;
+
+    ctx.success(&format!("✓ Vault restored with {} identities!", count));
+    ctx.hint("Unlock it with the master password of the exported vault.");
+
+    Ok(())
+}
+
+pub fn list_expiring(ctx: &Context, within_days: u32) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let expiring = vault.expiring(within_days)?;
+
+    if expiring.is_empty() {
+        println!(
+            "{}",
+            format!("No password rotations due in the next {} days.", within_days).green()
+        );
+        return Ok(());
+    }
+
+    println!("{}", "Password Rotations Due:".cyan().bold());
+    println!();
+
+    let now = Utc::now();
+    for (service, due) in &expiring {
+        let date = due.format("%Y-%m-%d").to_string();
+        if *due <= now {
+            println!("  {}  {}", date.red(), service.bright_white());
+        } else {
+            println!("  {}  {}", date.yellow(), service.bright_white());
+        }
+    }
+
+    println!();
+    println!("Total: {}", expiring.len().to_string().green());
+
+    Ok(())
+}
+
+pub fn audit_log(ctx: &Context) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let log = vault.audit_log()?;
+    if log.entries().is_empty() {
+        println!("{}", "No changes recorded yet.".yellow());
+        return Ok(());
+    }
+
+    println!("{}", "Audit Log:".cyan().bold());
+    println!();
+    for entry in log.entries() {
+        println!(
+            "  {}  {:<8} {}",
+            entry.at.format("%Y-%m-%d %H:%M:%S").to_string().dimmed(),
+            entry.op.to_string(),
+            entry.service.as_deref().unwrap_or("(whole vault)").bright_white()
+        );
+    }
+
+    if let Some(broken) = log.first_broken() {
+        println!();
+        println!(
+            "{}",
+            format!(
+                "⚠ Entry {} onwards doesn't follow from the entries before it; the log was altered.",
+                broken + 1
+            )
+            .red()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn access_log(ctx: &Context, service: &str) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let identity = vault.get_identity(service)?;
+
+    match identity.last_accessed {
+        Some(at) => println!(
+            "Password for '{}' last revealed: {}",
+            service,
+            at.format("%Y-%m-%d %H:%M:%S").to_string().bright_white()
+        ),
+        None => println!("{}", format!("No recorded access for '{}'.", service).yellow()),
+    }
+    if !vault.tracks_access()? {
+        ctx.hint("Access tracking is off; enable it with `aliaser track-access true`.");
+    }
+
+    Ok(())
+}
+
+pub fn track_access(ctx: &Context, enabled: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    vault.set_track_access(enabled)?;
+
+    if enabled {
+        ctx.success("✓ Password reveals will be recorded.");
+        ctx.hint("Note: `get --show` and `get --copy` now write to the vault.");
+    } else {
+        ctx.success("✓ Access tracking turned off.");
+    }
+
+    Ok(())
+}
+
+pub fn lockout(
+    ctx: &Context,
+    free_attempts: Option<u32>,
+    max_delay: Option<u64>,
+    after: Option<u32>,
+    minutes: Option<u32>,
+) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let mut policy = vault.unlock_policy()?;
+    let changes = [free_attempts.is_some(), max_delay.is_some(), after.is_some(), minutes.is_some()];
+    if changes.contains(&true) {
+        policy.free_attempts = free_attempts.unwrap_or(policy.free_attempts);
+        policy.max_delay_secs = max_delay.unwrap_or(policy.max_delay_secs);
+        policy.lockout_after = after.unwrap_or(policy.lockout_after);
+        policy.lockout_minutes = minutes.unwrap_or(policy.lockout_minutes);
+        vault.set_unlock_policy(policy)?;
+        ctx.success("✓ Unlock throttling updated.");
+        println!();
+    }
+
+    println!("{}", "Failed Unlock Throttling:".cyan().bold());
+    println!();
+    println!(
+        "  Delay:   after {} failures, doubling from 1 s up to {} s",
+        policy.free_attempts, policy.max_delay_secs
+    );
+    if policy.lockout_after == 0 {
+        println!("  Lockout: never");
+    } else {
+        println!(
+            "  Lockout: {} minutes after {} consecutive failures",
+            policy.lockout_minutes, policy.lockout_after
+        );
+    }
+
+    Ok(())
+}
+
+pub fn generator_policy(ctx: &Context, set: bool, clear: bool, policy: &PolicyArgs) -> Result<()> {
+    if policy.is_set() && !set {
+        anyhow::bail!("Generator options only apply with --set-generator");
+    }
+
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    if set {
+        vault.set_generator_policy(Some(policy.policy(&ctx.settings.generator)))?;
+        ctx.success("✓ Default generator policy stored in the vault.");
+        println!();
+    } else if clear {
+        vault.set_generator_policy(None)?;
+        ctx.success("✓ Stored generator policy removed.");
+        println!();
+    }
+
+    let stored = vault.generator_policy()?;
+    let source = if stored.is_some() { "stored in the vault" } else { "from the settings file" };
+    let current = stored.unwrap_or_else(|| ctx.settings.generator.clone());
+
+    println!("{}", "Password Generator Policy:".cyan().bold());
+    println!("{}", format!("({})", source).dimmed());
+    println!();
+    println!("  Length:    {}", current.length);
+    let classes: Vec<&str> = [
+        (current.lowercase, "lowercase"),
+        (current.uppercase, "uppercase"),
+        (current.digits, "digits"),
+        (current.symbols, "symbols"),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, name)| enabled.then_some(name))
+    .collect();
+    println!("  Classes:   {}", classes.join(", "));
+    let required: Vec<&str> = current.required().into_iter().map(CharClass::name).collect();
+    println!("  Required:  {}", if required.is_empty() { "none".to_string() } else { required.join(", ") });
+    println!("  Ambiguous: {}", if current.exclude_ambiguous { "excluded" } else { "allowed" });
+
+    Ok(())
+}
+
+pub fn recovery_code(ctx: &Context, service: &str, consume: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    if consume {
+        let code = vault.consume_recovery_code(service)?;
+        let remaining = vault.get_identity(service)?.credentials.recovery_codes.len();
+
+        println!("{}", code.bright_yellow());
+        println!();
+        ctx.hint(&format!("Code removed; {} recovery codes left for '{}'.", remaining, service));
+        return Ok(());
+    }
+
+    let identity = vault.get_identity(service)?;
+    let codes = &identity.credentials.recovery_codes;
+    if codes.is_empty() {
+        println!("{}", format!("No recovery codes stored for '{}'.", service).yellow());
+    } else {
+        println!(
+            "{} unused recovery codes for '{}'. Use --consume to take the next one.",
+            codes.len().to_string().green(),
+            service
+        );
+    }
+
+    Ok(())
+}
+
+pub struct OtpOptions {
+    pub hotp: bool,
+    pub counter: u64,
+    pub digits: u32,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+pub fn set_otp(ctx: &Context, service: &str, options: OtpOptions, remove: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    if remove {
+        if !confirm(ctx, &format!("Remove the one-time password secret for '{}'? (y/n): ", service))? {
+            println!("Cancelled.");
+            return Ok(());
+        }
+        vault.set_otp(service, None)?;
+        ctx.success(&format!("✓ One-time password removed from '{}'.", service));
+        return Ok(());
+    }
+
+    let encoded = Zeroizing::new(prompt_master_password("Secret (base32, as shown under the QR code): ")?);
+    println!();
+    let secret = totp::decode_base32(&encoded)?;
+    let issuer = match options.issuer {
+        Some(issuer) => Some(issuer),
+        None => prompt_optional(&format!("Issuer shown in authenticator apps (Enter for '{}'): ", service))?,
+    };
+    let account = match options.account {
+        Some(account) => Some(account),
+        None => prompt_optional("Account shown in authenticator apps (Enter for the username): ")?,
+    };
+    let otp = OtpSecret {
+        secret,
+        kind: if options.hotp { OtpKind::Hotp { counter: options.counter } } else { OtpKind::Totp },
+        digits: options.digits,
+        issuer,
+        account,
+    };
+    vault.set_otp(service, Some(otp))?;
+
+    ctx.success(&format!("✓ One-time password set up for '{}'.", service));
+    ctx.hint(&format!("Run `aliaser otp {}` to get a code.", service));
+
+    Ok(())
+}
+
+pub fn otp(ctx: &Context, service: &str, uri: bool) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+
+    if uri {
+        unlock_vault_quietly(&mut vault)?;
+        let identity = vault.get_identity(service)?;
+        let uri = identity
+            .otpauth_uri()
+            .with_context(|| format!("No one-time password set up for service '{}'", service))?;
+        println!("{}", uri);
+        return Ok(());
+    }
+
+    unlock_vault(&mut vault)?;
+
+    let code = vault.next_otp(service)?;
+    println!("{}", code.bright_yellow());
+
+    let kind = vault.get_identity(service)?.otp.as_ref().map(|otp| otp.kind);
+    if let Some(OtpKind::Totp) = kind {
+        let now = Utc::now().timestamp().max(0) as u64;
+        ctx.hint(&format!("Valid for {} more seconds.", totp::seconds_remaining(now)));
+    }
+
+    Ok(())
+}
+
+pub fn qr(ctx: &Context, service: &str, png: Option<&Path>) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let identity = vault.get_identity(service)?;
+    let uri = Zeroizing::new(
+        identity
+            .otpauth_uri()
+            .with_context(|| format!("No one-time password set up for service '{}'; add one with `set-otp`", service))?,
+    );
+    let code = QrCode::new(uri.as_bytes()).context("Failed to encode the setup URI as a QR code")?;
+
+    // Light modules as blocks, so the code reads dark on light on the usual
+    // dark terminal
+    let art = Zeroizing::new(
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build(),
+    );
+    println!("{}", art.as_str());
+    ctx.hint("Scan it with an authenticator app; it holds the secret, so don't leave it on screen.");
+
+    if let Some(path) = png {
+        write_private_file(path, &qr_png(&code)?)?;
+        ctx.success(&format!("✓ Saved to {}", path.display()));
+        ctx.hint("The image holds the secret; delete it once it's scanned.");
+    }
+
+    Ok(())
+}
+
+/// Encodes `code` as a PNG with a few pixels per module
+fn qr_png(code: &QrCode) -> Result<Vec<u8>> {
+    let image = code.render::<image::Luma<u8>>().min_dimensions(256, 256).build();
+    let mut png = Vec::new();
+    image
+        .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("Failed to encode the QR code as PNG")?;
+    Ok(png)
+}
+
+pub fn attach(ctx: &Context, service: &str, path: &Path) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let size = fs::metadata(path)
+        .context(format!("Failed to read {}", path.display()))?
+        .len();
+    if size > MAX_ATTACHMENT_SIZE as u64 {
+        anyhow::bail!(
+            "{} is {} bytes; attachments are limited to {} KiB",
+            path.display(),
+            size,
+            MAX_ATTACHMENT_SIZE / 1024
+        );
+    }
+
+    let name = path
+        .file_name()
+        .context("Attachment path has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    let attachment = Attachment {
+        mime: Attachment::guess_mime(&name).to_string(),
+        data: fs::read(path).context(format!("Failed to read {}", path.display()))?,
+        name,
+    };
+    let (name, len) = (attachment.name.clone(), attachment.data.len());
+
+    vault.attach(service, attachment)?;
+
+    ctx.success(&format!("✓ Attached '{}' ({} bytes) to '{}'.", name, len, service));
+    ctx.hint("The original file was left in place; delete it if it should only live in the vault.");
+
+    Ok(())
+}
+
+pub fn list_attachments(ctx: &Context, service: &str) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let identity = vault.get_identity(service)?;
+    if identity.attachments.is_empty() {
+        println!("{}", format!("No attachments for '{}'.", service).yellow());
+        return Ok(());
+    }
+
+    println!("{}", format!("Attachments for {}:", service).cyan().bold());
+    println!();
+    for attachment in &identity.attachments {
+        println!(
+            "  {}  {} bytes  {}",
+            attachment.name.bright_white(),
+            attachment.data.len(),
+            attachment.mime.dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn extract_attachment(ctx: &Context, service: &str, name: &str, out: &Path) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    let identity = vault.get_identity(service)?;
+    let attachment = identity
+        .attachments
+        .iter()
+        .find(|a| a.name == name)
+        .context(format!("Service '{}' has no attachment named '{}'", service, name))?;
+
+    if out.exists() && !confirm(ctx, &format!("{} already exists. Overwrite? (y/n): ", out.display()))? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    write_private_file(out, &attachment.data)?;
+
+    ctx.success(&format!("✓ Wrote '{}' to {}.", name, out.display()));
+
+    Ok(())
+}
+
+pub fn encrypt_file(ctx: &Context, input: &Path, output: &Path) -> Result<()> {
+    let data = Zeroizing::new(fs::read(input).context(format!("Failed to read {}", input.display()))?);
+
+    if output.exists() && !confirm(ctx, &format!("{} already exists. Overwrite? (y/n): ", output.display()))? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let passphrase = prompt_new_password("File passphrase: ")?;
+    let kdf_params = ctx.settings.kdf_profile.params(KdfAlgorithm::Argon2id);
+    let sealed = crypto::encrypt_with_passphrase(&data, &passphrase, kdf_params)?;
+    write_private_file(output, &sealed)?;
+
+    ctx.gap();
+    ctx.success(&format!("✓ Encrypted {} to {}.", input.display(), output.display()));
+    ctx.hint("The original is untouched; delete it yourself if it shouldn't stay in plaintext.");
+
+    Ok(())
+}
+
+pub fn decrypt_file(ctx: &Context, input: &Path, output: &Path) -> Result<()> {
+    let sealed = fs::read(input).context(format!("Failed to read {}", input.display()))?;
+
+    if output.exists() && !confirm(ctx, &format!("{} already exists. Overwrite? (y/n): ", output.display()))? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let passphrase = prompt_master_password("File passphrase: ")?;
+    println!();
+    let data = Zeroizing::new(crypto::decrypt_with_passphrase(&sealed, &passphrase)?);
+    write_private_file(output, &data)?;
+
+    ctx.success(&format!("✓ Decrypted {} to {}.", input.display(), output.display()));
+
+    Ok(())
+}
+
+pub fn change_master_password(ctx: &Context, reason: Option<&str>) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+
+    println!("{}", "Change Master Password".cyan().bold());
+    println!();
+
+    let old_password = prompt_master_password("Current master password: ")?;
+
+    println!();
+    let new_password = prompt_new_password("New master password: ")?;
+
+    vault.change_master_password(&old_password, &new_password, reason)?;
+
+    ctx.gap();
+    ctx.success("✓ Master password changed successfully!");
+
+    Ok(())
+}
+
+pub fn info(ctx: &Context) -> Result<()> {
+    let vault = open_vault(ctx)?;
+    let info = vault.info()?;
+
+    println!("{}", "Vault Info:".cyan().bold());
+    println!();
+    println!("  Format:    {}", info.version);
+    println!("  Cipher:    {}", info.cipher);
+    println!("  KDF:       {}", info.kdf_params);
+    println!("  Salt:      {} bytes", info.salt_size);
+    println!();
+    println!("{}", "Master Password:".cyan().bold());
+    println!();
+    match info.master_password_changed_at {
+        Some(changed) => {
+            let days = (Utc::now() - changed).num_days();
+            println!("  Changed:   {} ({} days ago)", changed.format("%Y-%m-%d %H:%M UTC"), days);
+            println!("  Version:   {}", info.password_version);
+        }
+        None => {
+            println!("  Changed:   {}", "unknown (set before changes were recorded)".dimmed());
+        }
+    }
+    if let Some(reason) = &info.master_password_change_reason {
+        println!("  Reason:    {}", reason);
+    }
+
+    Ok(())
+}
+
+pub fn migrate(ctx: &Context) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    if !vault.is_initialized() {
+        return Err(VaultError::NotInitialized.into());
+    }
+
+    let pending = vault.pending_migrations()?;
+    if pending.is_empty() {
+        println!("{}", "Vault is already in the current format.".green());
+        return Ok(());
+    }
+
+    println!("{}", "Migrate Vault".cyan().bold());
+    println!();
+    for step in &pending {
+        println!("  • {}", step);
+    }
+    println!();
+    println!("{}", "The current vault and config are backed up first.".dimmed());
+    println!();
+
+    let password = prompt_master_password("Master password: ")?;
+    println!();
+
+    let applied = vault.migrate(&password)?;
+    if applied.is_empty() {
+        println!("{}", "Vault is already in the current format.".green());
+    } else {
+        ctx.success(&format!("✓ Vault migrated ({} changes).", applied.len()));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+pub fn tui(ctx: &Context) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    match crate::tui::run(&mut vault, &ctx.settings)? {
+        crate::tui::Exit::Quit => {}
+        crate::tui::Exit::IdleLock => {
+            println!(
+                "{}",
+                format!(
+                    "Vault locked after {} minutes without input.",
+                    crate::tui::IDLE_TIMEOUT.as_secs() / 60
+                )
+                .yellow()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(any(feature = "sync", feature = "webdav"))]
+pub fn sync(ctx: &Context, direction: SyncDirection, remote: &crate::sync::Remote, force: bool) -> Result<()> {
+    use storage::{SnapshotOrder, VaultSnapshot};
+
+    let mut vault = open_vault(ctx)?;
+    let local = if vault.is_initialized() { Some(vault.snapshot()?) } else { None };
+    if direction == SyncDirection::Push && local.is_none() {
+        return Err(VaultError::NotInitialized.into());
+    }
+
+    let password = match remote.password_user() {
+        Some(user) => Some(match std::env::var("ALIASER_WEBDAV_PASSWORD") {
+            Ok(password) => Zeroizing::new(password),
+            Err(_) => Zeroizing::new(prompt_password(&format!("Password for {} at {}: ", user, remote))?),
+        }),
+        None => None,
+    };
+    let store = remote.open(password)?;
+    let remote_copy = VaultSnapshot::read_from(store.as_ref())?;
+
+    // Compare the copy being sent with the one it would replace
+    let (from, to, target) = match direction {
+        SyncDirection::Push => (local.as_ref(), remote_copy.as_ref(), "remote"),
+        SyncDirection::Pull => (remote_copy.as_ref(), local.as_ref(), "local"),
+    };
+    let Some(from) = from else {
+        anyhow::bail!("There is no vault at {} to pull", remote);
+    };
+    if let Some(to) = to {
+        match from.compare(to) {
+            SnapshotOrder::Same => {
+                ctx.success(&format!("✓ Already in sync (revision {}).", from.revision));
+                return Ok(());
+            }
+            SnapshotOrder::Newer => {}
+            SnapshotOrder::Older if !force => anyhow::bail!(
+                "The {} vault is newer (revision {} against {}); sync the other way first, or pass --force",
+                target,
+                to.revision,
+                from.revision
+            ),
+            SnapshotOrder::Diverged if !force => anyhow::bail!(
+                "The local and remote vaults were both changed since revision {}; \
+                 pass --force to overwrite the {} one",
+                to.revision,
+                target
+            ),
+            SnapshotOrder::Older | SnapshotOrder::Diverged => {
+                println!("{}", format!("Overwriting the {} vault's own changes (--force).", target).yellow());
+            }
+        }
+    }
+
+    match direction {
+        SyncDirection::Push => {
+            from.write_to(store.as_ref())?;
+            ctx.success(&format!("✓ Pushed revision {} to {}.", from.revision, remote));
+        }
+        SyncDirection::Pull => {
+            vault.replace_with_snapshot(from)?;
+            ctx.success(&format!("✓ Pulled revision {} from {}.", from.revision, remote));
+            if local.is_some() {
+                ctx.hint("The previous local vault was backed up first (see `aliaser backups`).");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn list_backups(ctx: &Context, keep: Option<usize>) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    unlock_vault(&mut vault)?;
+
+    if let Some(keep) = keep {
+        vault.set_max_backups(keep)?;
+        ctx.success(&format!("✓ Keeping up to {} backups.", keep));
+        println!();
+    }
+
+    let backups = vault.list_backups()?;
+
+    if backups.is_empty() {
+        println!("{}", "No backups found.".yellow());
+        return Ok(());
+    }
+
+    println!("{}", "Vault Backups:".cyan().bold());
+    println!();
+
+    for backup in &backups {
+        println!(
+            "  {}  {}",
+            backup.timestamp.bright_white(),
+            backup.vault_path.display().to_string().dimmed()
+        );
+    }
+
+    println!();
+    println!("Total: {}", backups.len().to_string().green());
+
+    Ok(())
+}
+
+pub fn restore_backup(ctx: &Context, timestamp: &str) -> Result<()> {
+    let vault = open_vault(ctx)?;
+
+    println!(
+        "{}",
+        format!("Restore vault from backup '{}'?", timestamp).yellow().bold()
+    );
+    println!(
+        "{}",
+        "The current vault and config will be replaced (a backup is kept).".dimmed()
+    );
+    println!();
+    if !confirm_phrase(ctx, "RESTORE")? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    vault.restore_backup(timestamp)?;
+
+    ctx.gap();
+    ctx.success("✓ Vault restored from backup.");
+    ctx.hint("Unlock with the master password that was active when the backup was taken.");
+
+    Ok(())
+}
+
+pub fn show_paths(ctx: &Context) -> Result<()> {
+    let vault = open_vault(ctx)?;
+    let paths = vault.paths()?;
+
+    println!("{}", "Vault Files:".cyan().bold());
+    println!();
+    for (label, path) in [
+        ("Vault", &paths.vault),
+        ("Config", &paths.config),
+        ("Index", &paths.index),
+        ("Log", &paths.log),
+    ] {
+        let status = if path.exists() {
+            "exists".green()
+        } else {
+            "missing".yellow()
+        };
+        println!(
+            "  {:<8}{} ({})",
+            format!("{}:", label),
+            path.display().to_string().bright_white(),
+            status
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs setup checks and prints a pass/fail checklist, offering to tighten
+/// file permissions; fails if any check still fails
+///
+/// Takes the parsed arguments rather than a [`Context`], since a settings
+/// file that won't load is one of the things it reports on.
+pub fn doctor(cli: &Cli) -> Result<()> {
+    let settings = Settings::load(cli.profile.as_deref());
+    let ctx = Context {
+        assume_yes: cli.yes,
+        read_only: cli.read_only,
+        quiet: cli.quiet,
+        config_path: cli.config.clone(),
+        settings: settings.as_ref().cloned().unwrap_or_default(),
+    };
+
+    println!("{}", "Aliaser Doctor:".cyan().bold());
+    println!();
+
+    let mut healthy = true;
+
+    let mut vault = match open_vault(&ctx) {
+        Ok(vault) => vault,
+        Err(e) => {
+            report_check("Home directory found", Err(e));
+            anyhow::bail!("Cannot continue without a home directory");
+        }
+    };
+    let paths = vault.paths()?;
+    report_check("Home directory found", Ok(String::new()));
+
+    if ctx.read_only {
+        println!("  {} Vault directory writable {}", "-".yellow(), "(skipped with --read-only)".dimmed());
+    } else {
+        healthy &= report_check("Vault directory writable", vault.check_directory_writable().map(|_| String::new()));
+    }
+    healthy &= report_check(
+        "Settings file loads",
+        settings.map(|_| cli.profile.as_ref().map(|p| format!("profile '{}'", p)).unwrap_or_default()),
+    );
+
+    if !vault.is_initialized() {
+        healthy &= report_check(
+            "Vault exists",
+            Err(anyhow::anyhow!("no vault at {}; run `aliaser init`", paths.vault.display())),
+        );
+    } else {
+        report_check("Vault exists", Ok(paths.vault.display().to_string()));
+        healthy &= report_check("Vault config readable", vault.info().map(|info| format!("format {}", info.version)));
+
+        let loose = vault.loose_permissions()?;
+        let listing: Vec<String> =
+            loose.iter().map(|(path, mode)| format!("{} is {:o}", path.display(), mode)).collect();
+        let private = report_check(
+            "Vault files are private",
+            if loose.is_empty() {
+                Ok(String::new())
+            } else {
+                Err(anyhow::anyhow!("readable by other users: {}", listing.join(", ")))
+            },
+        );
+        if !private {
+            let fixed = !ctx.read_only && confirm(&ctx, "    Restrict them to owner-only (chmod 600)? (y/n): ")?;
+            if fixed {
+                let count = vault.restrict_permissions()?;
+                println!("    {}", format!("✓ Fixed permissions on {} files.", count).green());
+            }
+            healthy &= fixed;
+        }
+
+        println!();
+        let unlocked = unlock_vault(&mut vault)
+            .and_then(|_| vault.identity_count())
+            .map(|count| format!("{} identities", count));
+        healthy &= report_check("Vault unlocks and decrypts", unlocked);
+    }
+
+    println!();
+    if !healthy {
+        anyhow::bail!("Some checks failed");
+    }
+    ctx.success("✓ No problems found.");
+    Ok(())
+}
+
+/// Prints one line of the `doctor` checklist; returns whether it passed
+fn report_check(label: &str, result: Result<String>) -> bool {
+    match result {
+        Ok(detail) if detail.is_empty() => println!("  {} {}", "✓".green(), label),
+        Ok(detail) => println!("  {} {} {}", "✓".green(), label, format!("({})", detail).dimmed()),
+        Err(e) => {
+            println!("  {} {}: {}", "✗".red(), label, format!("{:#}", e).red());
+            return false;
+        }
+    }
+    true
+}
+
+pub fn completions(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+}
+
+/// Prints service names for dynamic shell completion
+pub fn complete_services(ctx: &Context) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    if !vault.is_initialized() {
+        return Ok(());
     }
 
-    // Update notes
-    let new_notes = prompt_optional("\nNotes: ")?;
-    if new_notes.is_some() {
-        identity.notes = new_notes;
+    unlock_vault_quietly(&mut vault)?;
+
+    for service in vault.list_services()? {
+        println!("{}", service);
     }
 
-    vault.update_identity(service, identity)?;
+    Ok(())
+}
 
-    println!();
-    println!("{}", "✓ Identity updated successfully!".green().bold());
+pub fn generate_password(ctx: &Context, policy: &PolicyArgs) -> Result<()> {
+    // The stored policy lives in the plaintext config, so no unlock is needed
+    let vault = open_vault(ctx)?;
+    let policy = policy.policy(&generator_defaults(ctx, &vault)?);
+
+    println!("{}", generator::generate(&policy)?);
 
     Ok(())
 }
 
-pub fn delete_identity(service: &str) -> Result<()> {
-    let mut vault = Vault::new()?;
-    unlock_vault(&mut vault)?;
+pub fn generate_batch(
+    ctx: &Context,
+    count: u32,
+    out: &Path,
+    prefix: &str,
+    format: BatchFormat,
+    policy: &PolicyArgs,
+) -> Result<()> {
+    let vault = open_vault(ctx)?;
+    let policy = policy.policy(&generator_defaults(ctx, &vault)?);
+
+    if out.exists() && !confirm(ctx, &format!("{} already exists. Overwrite? (y/n): ", out.display()))? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let passwords = Zeroizing::new(generator::generate_batch(&policy, count as usize)?);
+    let width = count.to_string().len().max(2);
+    let labels: Vec<String> = (1..=count).map(|i| format!("{}-{:0width$}", prefix, i, width = width)).collect();
+
+    let contents = match format {
+        BatchFormat::Csv => {
+            let mut csv = Zeroizing::new(String::from("label,password\n"));
+            for (label, password) in labels.iter().zip(passwords.iter()) {
+                push_csv_field(&mut csv, label);
+                csv.push(',');
+                push_csv_field(&mut csv, password);
+                csv.push('\n');
+            }
+            csv
+        }
+        BatchFormat::Json => {
+            #[derive(serde::Serialize)]
+            struct Entry<'a> {
+                label: &'a str,
+                password: &'a str,
+            }
+            let entries: Vec<Entry> = labels
+                .iter()
+                .zip(passwords.iter())
+                .map(|(label, password)| Entry { label, password })
+                .collect();
+            let mut json = Zeroizing::new(serde_json::to_string_pretty(&entries)?);
+            json.push('\n');
+            json
+        }
+    };
+    write_private_file(out, contents.as_bytes())?;
 
+    ctx.success(&format!("✓ Wrote {} passwords to {}", count, out.display()));
     println!(
         "{}",
-        format!("Delete identity for '{}'?", service).yellow().bold()
+        "⚠ The file holds the passwords in plaintext; delete it once they're handed out.".yellow()
     );
-    println!("{}", "This action cannot be undone!".red());
 
-    if !prompt_yes_no("\nConfirm deletion (y/n): ")? {
-        println!("Cancelled.");
-        return Ok(());
+    Ok(())
+}
+
+pub fn rekey(ctx: &Context, kdf_profile: Option<KdfProfile>, salt_size: Option<u16>) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    if !vault.is_initialized() {
+        return Err(VaultError::NotInitialized.into());
     }
 
-    vault.delete_identity(service)?;
+    println!("{}", "Rekey Vault".cyan().bold());
+    println!();
 
+    let password = prompt_master_password("Master password: ")?;
     println!();
-    println!("{}", "✓ Identity deleted successfully.".green().bold());
+
+    let kdf_params = vault.rekey(&password, kdf_profile, salt_size.map(usize::from))?;
+
+    ctx.success("✓ Vault re-encrypted with a fresh salt and key.");
+    if let Some(profile) = kdf_profile {
+        ctx.hint(&format!("Key derivation profile is now {} ({}).", profile, kdf_params));
+    }
+    if let Some(size) = salt_size {
+        ctx.hint(&format!("Salt length is now {} bytes.", size));
+    }
 
     Ok(())
 }
 
-pub fn export_data(path: &PathBuf) -> Result<()> {
-    let mut vault = Vault::new()?;
-    unlock_vault(&mut vault)?;
+pub fn convert(ctx: &Context, cipher: Option<Cipher>, kdf: Option<KdfAlgorithm>) -> Result<()> {
+    let mut vault = open_vault(ctx)?;
+    if !vault.is_initialized() {
+        return Err(VaultError::NotInitialized.into());
+    }
 
-    vault.export(path)?;
+    println!("{}", "Convert Vault".cyan().bold());
+    println!();
 
+    let password = prompt_master_password("Master password: ")?;
     println!();
-    println!(
-        "{}",
-        format!("✓ Vault exported to: {}", path.display()).green().bold()
-    );
-    println!(
-        "{}",
-        "The exported file is encrypted with your master password.".dimmed()
-    );
+
+    // Same choice of parameters as init makes for a new vault
+    let kdf_params = kdf.map(|kdf| match ctx.settings.kdf_calibration {
+        Some(calibrated) if calibrated.algorithm == kdf => calibrated,
+        _ => ctx.settings.kdf_profile.params(kdf),
+    });
+    let (cipher, kdf_params) = vault.convert(&password, cipher, kdf_params)?;
+
+    ctx.success("✓ Vault converted; the previous files were backed up.");
+    ctx.hint(&format!("Cipher: {}", cipher));
+    ctx.hint(&format!("Key derivation: {}", kdf_params));
 
     Ok(())
 }
 
-pub fn import_data(path: &PathBuf) -> Result<()> {
-    let mut vault = Vault::new()?;
-    unlock_vault(&mut vault)?;
+pub fn calibrate(ctx: &Context, target_ms: u64) -> Result<()> {
+    println!("{}", "Calibrate Key Derivation".cyan().bold());
+    println!();
+    ctx.hint(&format!("Timing Argon2id against a {} ms target; this takes a few seconds...", target_ms));
+
+    let kdf_params = crypto::calibrate(Duration::from_millis(target_ms))?;
+    let interactive = KdfProfile::Interactive.params(KdfAlgorithm::Argon2id);
+
+    ctx.gap();
+    println!("Calibrated parameters: {}", kdf_params.to_string().bright_white());
+    if kdf_params == interactive {
+        println!(
+            "{}",
+            "⚠ Even the interactive profile takes longer than the target here; using it anyway.".yellow()
+        );
+    }
 
-    println!(
-        "{}",
-        "This will overwrite your current vault!".yellow().bold()
-    );
-    if !prompt_yes_no("Continue? (y/n): ")? {
-        println!("Cancelled.");
+    if ctx.read_only {
+        ctx.hint("Not saved (read-only mode).");
         return Ok(());
     }
-
-    vault.import(path)?;
-
-    println!();
-    println!("{}", "✓ Vault imported successfully!".green().bold());
+    let path = config::save_calibration(target_ms, kdf_params)?;
+    ctx.success(&format!("✓ Saved to {}", path.display()));
+    ctx.hint("`init` uses these for new vaults unless --kdf-profile is given; existing vaults are unchanged.");
 
     Ok(())
 }
 
-pub fn change_master_password() -> Result<()> {
-    let mut vault = Vault::new()?;
+// Helper functions
+
+/// Generator policy to apply when no options are given: the vault's stored
+/// policy, else the settings file's
+fn generator_defaults(ctx: &Context, vault: &Vault) -> Result<PasswordPolicy> {
+    Ok(vault.generator_policy()?.unwrap_or_else(|| ctx.settings.generator.clone()))
+}
 
-    println!("{}", "Change Master Password".cyan().bold());
-    println!();
+/// Appends `value` as a CSV field, quoted if it contains a comma, quote or
+/// line break (generated symbols include commas)
+fn push_csv_field(out: &mut String, value: &str) {
+    if !value.contains([',', '"', '\n', '\r']) {
+        out.push_str(value);
+        return;
+    }
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' {
+            out.push('"');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
 
-    print!("Current master password: ");
-    io::stdout().flush()?;
-    let old_password = read_password()?;
+/// Whether `path` is `-`, meaning stdin or stdout instead of a file
+fn is_std_stream(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-    println!();
-    let new_password = prompt_new_password("New master password: ")?;
+/// Opens `path` for reading, or stdin for `-`
+fn open_input(path: &Path) -> Result<Box<dyn io::Read>> {
+    if is_std_stream(path) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let file = fs::File::open(path).context(format!("Failed to open {}", path.display()))?;
+    Ok(Box::new(io::BufReader::new(file)))
+}
 
-    vault.change_master_password(&old_password, &new_password)?;
+/// Opens `path` for writing, made readable by the owner only, or stdout
+/// for `-` as long as it isn't a terminal the binary data would garble
+fn open_output(path: &Path) -> Result<Box<dyn Write>> {
+    if is_std_stream(path) {
+        if io::stdout().is_terminal() {
+            anyhow::bail!("Refusing to write binary data to the terminal; redirect stdout to a file or pipe");
+        }
+        return Ok(Box::new(io::stdout().lock()));
+    }
 
-    println!();
-    println!("{}", "✓ Master password changed successfully!".green().bold());
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(path).context(format!("Failed to create {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .context(format!("Failed to change permissions of {}", path.display()))?;
+    }
+    Ok(Box::new(io::BufWriter::new(file)))
+}
 
-    Ok(())
+/// Writes `data` to `path`, readable by the owner only
+fn write_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    storage::write_private_file(path, data).context(format!("Failed to create {}", path.display()))
 }
 
-// Helper functions
+fn open_vault(ctx: &Context) -> Result<Vault> {
+    let mut vault = Vault::new(ctx.config_path.clone())?;
+    vault.set_read_only(ctx.read_only);
+    Ok(vault)
+}
 
 fn unlock_vault(vault: &mut Vault) -> Result<()> {
+    if vault.is_unlocked() {
+        return Ok(());
+    }
     if !vault.is_initialized() {
-        anyhow::bail!("Vault not initialized. Run 'aliaser init' first.");
+        return Err(VaultError::NotInitialized.into());
     }
 
-    print!("Master password: ");
-    io::stdout().flush()?;
-    let password = read_password()?;
+    let password = prompt_master_password("Master password: ")?;
     println!();
 
     vault.unlock(&password)?;
@@ -391,6 +2818,20 @@ fn unlock_vault(vault: &mut Vault) -> Result<()> {
     Ok(())
 }
 
+/// Like [`unlock_vault`], but prompts on stderr so that stdout only carries
+/// what the command prints for scripts
+fn unlock_vault_quietly(vault: &mut Vault) -> Result<()> {
+    if !vault.is_initialized() {
+        return Err(VaultError::NotInitialized.into());
+    }
+
+    eprint!("Master password: ");
+    let password = Zeroizing::new(read_password()?);
+    eprintln!();
+
+    vault.unlock(&password)
+}
+
 fn prompt(message: &str) -> Result<String> {
     print!("{}", message);
     io::stdout().flush()?;
@@ -408,6 +2849,17 @@ fn prompt_optional(message: &str) -> Result<Option<String>> {
     }
 }
 
+/// Re-prompts until a non-blank value is entered
+fn prompt_required(message: &str, field: &str) -> Result<String> {
+    loop {
+        let input = prompt(message)?;
+        if !input.is_empty() {
+            return Ok(input);
+        }
+        println!("{}", format!("{} cannot be empty!", field).red());
+    }
+}
+
 fn prompt_password(message: &str) -> Result<String> {
     print!("{}", message);
     io::stdout().flush()?;
@@ -415,22 +2867,26 @@ fn prompt_password(message: &str) -> Result<String> {
     Ok(password)
 }
 
-fn prompt_new_password(message: &str) -> Result<String> {
+/// Reads a master password into a buffer that is scrubbed when dropped, so
+/// the plaintext never outlives the unlock or key derivation that uses it
+fn prompt_master_password(message: &str) -> Result<Zeroizing<String>> {
+    print!("{}", message);
+    io::stdout().flush()?;
+    Ok(Zeroizing::new(read_password()?))
+}
+
+fn prompt_new_password(message: &str) -> Result<Zeroizing<String>> {
     loop {
-        print!("{}", message);
-        io::stdout().flush()?;
-        let password = read_password()?;
+        let password = prompt_master_password(message)?;
 
         if password.len() < 8 {
             println!("{}", "Password must be at least 8 characters!".red());
             continue;
         }
 
-        print!("Confirm password: ");
-        io::stdout().flush()?;
-        let confirm = read_password()?;
+        let confirm = prompt_master_password("Confirm password: ")?;
 
-        if password != confirm {
+        if !ct_eq(password.as_bytes(), confirm.as_bytes()) {
             println!("{}", "Passwords don't match!".red());
             continue;
         }
@@ -450,23 +2906,310 @@ fn prompt_yes_no(message: &str) -> Result<bool> {
     }
 }
 
-fn collect_personal_info() -> Result<PersonalInfo> {
-    println!("{}", "Personal Information:".bold());
+/// Requires the user to type `expected` exactly; anything else cancels.
+/// Used for destructive operations where a stray `y` is too easy to type.
+fn prompt_confirmation_phrase(expected: &str) -> Result<bool> {
+    let input = prompt(&format!("Type {} to confirm: ", expected.bold()))?;
+    Ok(input == expected)
+}
+
+/// Asks a y/n confirmation before a destructive step, auto-accepting under `--yes`
+fn confirm(ctx: &Context, message: &str) -> Result<bool> {
+    if ctx.assume_yes {
+        return Ok(true);
+    }
+    prompt_yes_no(message)
+}
+
+/// Like [`confirm`], but requires typing `expected` unless `--yes` was given
+fn confirm_phrase(ctx: &Context, expected: &str) -> Result<bool> {
+    if ctx.assume_yes {
+        return Ok(true);
+    }
+    prompt_confirmation_phrase(expected)
+}
+
+/// Whether secrets printed now would only reach the screen: stdout is a
+/// terminal and no session recorder such as `script` or asciinema is running
+fn is_output_safe_for_secrets() -> bool {
+    let recorded = ["SCRIPT", "ASCIINEMA_REC"].iter().any(|var| std::env::var_os(var).is_some());
+    io::stdout().is_terminal() && !recorded
+}
+
+/// Prints `secret` on a line of its own, then after `seconds` moves back up
+/// and blanks the line so it no longer shows in the terminal. Scrollback
+/// may still hold it, and a long value that wrapped leaves its first rows.
+fn reveal_briefly(label: &str, secret: &str, seconds: u64) -> Result<()> {
+    let mut stdout = io::stdout();
+    println!("{}: {}", label, secret.bright_yellow());
+    stdout.flush()?;
+    std::thread::sleep(Duration::from_secs(seconds));
+
+    // Cursor up one line, then erase it
+    print!("\x1b[1A\r\x1b[2K");
+    println!("{}: {}", label, MASKED_SECRET.dimmed());
+    stdout.flush()?;
+    Ok(())
+}
+
+fn display_secret(secret: &str, show: bool) -> String {
+    if show {
+        secret.bright_yellow().to_string()
+    } else {
+        MASKED_SECRET.dimmed().to_string()
+    }
+}
+
+fn prompt_rotation_days(message: &str) -> Result<Option<u32>> {
+    loop {
+        let Some(input) = prompt_optional(message)? else {
+            return Ok(None);
+        };
+        match input.parse::<u32>() {
+            Ok(days) if days > 0 => return Ok(Some(days)),
+            _ => println!("{}", "Please enter a positive number of days".red()),
+        }
+    }
+}
+
+fn print_renames(renames: &[(String, String)], merged: usize) {
+    if renames.is_empty() {
+        println!("{}", "No service names changed.".dimmed());
+    }
+    for (from, to) in renames {
+        println!("  {} {} {}", from.dimmed(), "→".cyan(), to.cyan());
+    }
+    if merged > 0 {
+        println!(
+            "{}",
+            format!("{} identities shared a new name; kept the most recently updated of each", merged).yellow()
+        );
+    }
+    println!();
+}
+
+fn print_diff(diff: &VaultDiff) {
+    println!("{}", "Import preview (dry run, nothing written):".cyan().bold());
+    println!();
+
+    if diff.is_empty() {
+        println!("{}", "No changes.".green());
+        return;
+    }
+
+    for service in &diff.added {
+        println!("  {} {}", "+".green().bold(), service.green());
+    }
+    for (service, fields) in &diff.updated {
+        println!("  {} {} {}", "~".yellow().bold(), service.yellow(), format!("({})", fields.join(", ")).dimmed());
+    }
+    for service in &diff.removed {
+        println!("  {} {}", "-".red().bold(), service.red());
+    }
+
+    println!();
+    println!(
+        "{} added, {} updated, {} removed, {} unchanged",
+        diff.added.len().to_string().green(),
+        diff.updated.len().to_string().yellow(),
+        diff.removed.len().to_string().red(),
+        diff.unchanged.len()
+    );
+}
+
+/// Reads a single-line note, or opens an editor for multi-line notes when the
+/// user types `edit`
+fn prompt_notes(message: &str, current: Option<&str>) -> Result<Option<String>> {
+    let input = prompt(message)?;
+    if input != "edit" {
+        return Ok(Some(input).filter(|s| !s.is_empty()));
+    }
+
+    let notes = edit_in_editor(current.unwrap_or(""))?;
+    let notes = notes.trim_end();
+    Ok(Some(notes.to_string()).filter(|s| !s.is_empty()))
+}
+
+/// A command running `editor`, which like git's may include arguments such
+/// as `code --wait`; the file to edit is appended as the last argument
+fn editor_command(editor: &str) -> Command {
+    if cfg!(unix) {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("{} \"$@\"", editor)).arg(editor);
+        command
+    } else {
+        let mut words = editor.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or(editor));
+        command.args(words);
+        command
+    }
+}
+
+/// Lets the user edit `initial` in `$VISUAL`/`$EDITOR` via a private temp
+/// file that is overwritten and removed afterwards. Without an editor,
+/// falls back to reading lines from stdin until a lone `.` or EOF.
+fn edit_in_editor(initial: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).ok();
+    let Some(editor) = editor.filter(|editor| !editor.trim().is_empty()) else {
+        println!("{}", "Enter notes; finish with a line containing only '.' (or Ctrl-D):".dimmed());
+        let mut lines = Vec::new();
+        for line in io::stdin().lines() {
+            let line = line?;
+            if line == "." {
+                break;
+            }
+            lines.push(line);
+        }
+        return Ok(lines.join("\n"));
+    };
+
+    // Unguessable, so neither a leftover file nor another user's can be in the way
+    let path = std::env::temp_dir().join(format!("aliaser-notes-{:016x}.txt", rand::random::<u64>()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .context("Failed to create temporary notes file")?
+        .write_all(initial.as_bytes())?;
+
+    let result = editor_command(&editor)
+        .arg(&path)
+        .status()
+        .context(format!("Failed to launch editor {:?}", editor))
+        .and_then(|status| {
+            anyhow::ensure!(status.success(), "Editor exited with {}", status);
+            Ok(fs::read_to_string(&path)?)
+        });
+
+    // Scrub the temp file before removing it, even if editing failed
+    if let Ok(metadata) = fs::metadata(&path) {
+        let _ = fs::write(&path, vec![0u8; metadata.len() as usize]);
+    }
+    let _ = fs::remove_file(&path);
+
+    result
+}
+
+/// Prompts for the login details of a new identity; returns them with
+/// whether the password was generated, or `None` if the user backed out
+/// over a reused password
+fn collect_credentials(ctx: &Context, vault: &Vault, service: &str) -> Result<Option<(Credentials, bool)>> {
+    println!("{}", "Credentials:".bold());
+    let username = prompt_required("  Username: ", "Username")?;
+    let password = prompt_password("  Password (leave empty to generate): ")?;
+    let generated = password.is_empty();
+    let password = if generated {
+        // A policy stored with `policy --set-generator` already answers this
+        let policy = match vault.generator_policy()? {
+            Some(policy) => policy,
+            None => PasswordPolicy {
+                exclude_ambiguous: prompt_yes_no("  Exclude ambiguous characters (l, 1, I, O, 0)? (y/n): ")?,
+                ..ctx.settings.generator.clone()
+            },
+        };
+        generator::generate(&policy)?
+    } else {
+        password
+    };
+    if !generated && !confirm_password_reuse(ctx, vault, &password, service)? {
+        return Ok(None);
+    }
+
+    let email = prompt_optional("  Email (optional): ")?;
+    let alias = prompt_optional("  Alias (optional): ")?;
+
+    let recovery_codes = if prompt_yes_no("  Add 2FA recovery codes? (y/n): ")? {
+        collect_recovery_codes()?
+    } else {
+        Vec::new()
+    };
 
-    let first_name = prompt_optional("  First Name: ")?;
-    let last_name = prompt_optional("  Last Name: ")?;
-    let birthdate = prompt_optional("  Birthdate (YYYY-MM-DD): ")?;
-    let address = prompt_optional("  Address: ")?;
-    let phone = prompt_optional("  Phone: ")?;
-
-    let mut info = PersonalInfo {
-        first_name,
-        last_name,
-        birthdate,
-        address,
-        phone,
-        custom_fields: Vec::new(),
+    let credentials = Credentials {
+        username,
+        password,
+        email,
+        alias,
+        recovery_codes,
     };
+    Ok(Some((credentials, generated)))
+}
+
+/// Prompts for each of a template's fields, storing the ones answered as
+/// custom fields; secret ones are read without echo
+fn collect_template_fields(template: Template, info: &mut PersonalInfo) -> Result<()> {
+    if template.fields().is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", format!("{} Details:", template).bold());
+    println!("{}", "  (leave a field empty to skip it)".dimmed());
+
+    for field in template.fields() {
+        let message = format!("  {}: ", field.name);
+        let value = if field.secret { prompt_password(&message)? } else { prompt(&message)? };
+        if !value.trim().is_empty() {
+            info.upsert_custom_field(field.name.to_string(), value.trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Reads pasted recovery codes, one per line, until an empty line
+fn collect_recovery_codes() -> Result<Vec<String>> {
+    println!("{}", "  Paste recovery codes, one per line (empty line to finish):".dimmed());
+    let mut codes = Vec::new();
+    loop {
+        let code = prompt("    ")?;
+        if code.is_empty() {
+            return Ok(codes);
+        }
+        codes.push(code);
+    }
+}
+
+/// Reads question and answer pairs until an empty question; an empty answer
+/// is generated, without symbols since answer forms often reject them
+fn collect_security_questions(ctx: &Context, vault: &Vault) -> Result<Vec<SecurityQuestion>> {
+    println!("{}", "  Enter each question and its answer (empty question to finish):".dimmed());
+    let mut questions = Vec::new();
+    loop {
+        let question = prompt("    Question: ")?;
+        if question.is_empty() {
+            return Ok(questions);
+        }
+        let mut answer = prompt_password("    Answer (leave empty to generate): ")?;
+        if answer.is_empty() {
+            let policy = PasswordPolicy {
+                symbols: false,
+                required_classes: None,
+                ..generator_defaults(ctx, vault)?
+            };
+            answer = generator::generate(&policy)?;
+            ctx.hint("    Generated an answer; see it with `get --show`.");
+        }
+        questions.push(SecurityQuestion { question, answer });
+    }
+}
+
+/// Prompts for the standard personal details, keeping whatever `info`
+/// already has (such as a template's custom fields) where nothing is entered
+fn collect_personal_info(mut info: PersonalInfo) -> Result<PersonalInfo> {
+    println!("{}", "Personal Information:".bold());
+
+    for (message, value) in [
+        ("  First Name: ", &mut info.first_name),
+        ("  Last Name: ", &mut info.last_name),
+        ("  Birthdate (YYYY-MM-DD): ", &mut info.birthdate),
+        ("  Address: ", &mut info.address),
+        ("  Phone: ", &mut info.phone),
+    ] {
+        if let Some(input) = prompt_optional(message)? {
+            *value = Some(input);
+        }
+    }
 
     // Custom fields
     if prompt_yes_no("\nAdd custom fields? (y/n): ")? {
@@ -476,7 +3219,7 @@ fn collect_personal_info() -> Result<PersonalInfo> {
                 break;
             }
             let value = prompt("  Field value: ")?;
-            info.add_custom_field(key, value);
+            info.upsert_custom_field(key, value);
 
             if !prompt_yes_no("  Add another field? (y/n): ")? {
                 break;
@@ -487,21 +3230,150 @@ fn collect_personal_info() -> Result<PersonalInfo> {
     Ok(info)
 }
 
-fn generate_password() -> String {
-    use rand::Rng;
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                            abcdefghijklmnopqrstuvwxyz\
-                            0123456789\
-                            !@#$%^&*()_+-=[]{}|;:,.<>?";
-    const PASSWORD_LEN: usize = 20;
-    
-    let mut rng = rand::thread_rng();
-    let password: String = (0..PASSWORD_LEN)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect();
-    
-    password
+/// Adds, changes or removes custom fields of existing personal info
+fn edit_custom_fields(info: &mut PersonalInfo) -> Result<()> {
+    for field in &info.custom_fields {
+        println!("  {}: {}", field.key.bold(), field.value);
+    }
+    println!(
+        "{}",
+        "  (Enter an existing name to change it, '-' as the value to remove it, an empty name to finish)"
+            .dimmed()
+    );
+
+    loop {
+        let key = prompt("  Field name: ")?;
+        if key.is_empty() {
+            break;
+        }
+        let value = prompt("  Field value: ")?;
+        if value == "-" {
+            if !info.remove_custom_field(&key) {
+                println!("{}", format!("  No field named '{}'.", key).yellow());
+            }
+        } else {
+            info.upsert_custom_field(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_args(args: &[&str]) -> PolicyArgs {
+        let cli = Cli::try_parse_from(["aliaser", "generate"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::Generate { policy } => policy,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_policy_flags_override_stored_policy() {
+        let stored = PasswordPolicy {
+            symbols: false,
+            digits: false,
+            exclude_ambiguous: true,
+            ..PasswordPolicy::default()
+        };
+
+        let unchanged = generate_args(&[]);
+        assert!(!unchanged.is_set());
+        assert_eq!(unchanged.policy(&stored), stored);
+
+        let widened = generate_args(&["--symbols", "--allow-ambiguous"]).policy(&stored);
+        assert!(widened.symbols && !widened.exclude_ambiguous);
+        assert!(!widened.digits);
+
+        let narrowed = generate_args(&["--no-symbols", "--no-digits"]).policy(&PasswordPolicy::default());
+        assert!(!narrowed.symbols && !narrowed.digits);
+
+        let conflicting = ["aliaser", "generate", "--symbols", "--no-symbols"];
+        assert!(Cli::try_parse_from(conflicting).is_err());
+    }
+
+    fn list_page(args: &[&str]) -> PageArgs {
+        let cli = Cli::try_parse_from(["aliaser", "list"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::List { page, .. } => page,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_page_range() {
+        let all = list_page(&[]);
+        assert!(!all.is_set());
+        assert_eq!(all.range(5), 0..5);
+
+        assert_eq!(list_page(&["--limit", "2"]).range(5), 0..2);
+        assert_eq!(list_page(&["--offset", "3"]).range(5), 3..5);
+        assert_eq!(list_page(&["--limit", "2", "--offset", "4"]).range(5), 4..5);
+        // Past the end is an empty page, not an error
+        assert_eq!(list_page(&["--limit", "2", "--offset", "9"]).range(5), 5..5);
+        assert_eq!(list_page(&["--limit", "18446744073709551615", "--offset", "1"]).range(5), 1..5);
+
+        assert!(Cli::try_parse_from(["aliaser", "list", "--limit", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90d"), Ok(chrono::Duration::days(90)));
+        assert_eq!(parse_age("2W"), Ok(chrono::Duration::days(14)));
+        assert_eq!(parse_age("6mo"), Ok(chrono::Duration::days(180)));
+        assert_eq!(parse_age("1000y"), Ok(chrono::Duration::days(MAX_AGE_DAYS)));
+        assert!(parse_age("1001y").is_err());
+        assert!(parse_age("9223372036854775807d").is_err());
+        assert!(parse_age("90").is_err());
+        assert!(parse_age("d").is_err());
+
+        // The longest age still makes a valid cutoff
+        let cutoff = Utc::now().checked_sub_signed(parse_age("1000y").unwrap());
+        assert!(cutoff.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_generate_batch_makes_existing_file_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("aliaser-test-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("passwords.csv");
+        fs::write(&out, "old contents\n").unwrap();
+        fs::set_permissions(&out, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let ctx = Context {
+            assume_yes: true,
+            read_only: false,
+            quiet: true,
+            config_path: Some(dir.join("config.json")),
+            settings: Settings::default(),
+        };
+        generate_batch(&ctx, 3, &out, "user", BatchFormat::Csv, &generate_args(&[])).unwrap();
+
+        assert_eq!(fs::metadata(&out).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&out).unwrap().lines().count(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_qr_png() {
+        let code = QrCode::new(totp::otpauth_uri("GitHub", "alice", b"12345678901234567890", 6, None)).unwrap();
+        let png = qr_png(&code).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let image = image::load_from_memory(&png).unwrap();
+        assert!(image.width() >= 256 && image.width() == image.height());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_command_keeps_arguments() {
+        let output = editor_command("printf '%s\\n' --wait").arg("/tmp/notes file.txt").output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "--wait\n/tmp/notes file.txt\n");
+    }
 }
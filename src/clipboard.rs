@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// How long a copied secret stays on the clipboard before it is cleared
+pub const DEFAULT_CLEAR_SECONDS: u64 = 30;
+
+/// A command-line clipboard tool that reads the new contents from stdin
+pub struct Tool {
+    pub program: &'static str,
+    args: &'static [&'static str],
+    /// Why the copy might not last until it is cleared, or might outlive
+    /// the clear, when that isn't up to the OS
+    pub caveat: Option<&'static str>,
+}
+
+impl Tool {
+    fn shell_command(&self) -> String {
+        std::iter::once(self.program)
+            .chain(self.args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// X11 and Wayland have no clipboard storage of their own: the contents are
+// served by whichever process owns the selection. These tools fork a
+// background process that keeps serving them after aliaser exits, until the
+// clear (or another copy) takes the selection over.
+const HELPER_CAVEAT: &str =
+    "The clipboard is served by a background process; if it is killed before the clear, the copy is lost.";
+
+const WAYLAND_TOOLS: &[Tool] = &[Tool {
+    program: "wl-copy",
+    args: &[],
+    caveat: Some(HELPER_CAVEAT),
+}];
+
+const X11_TOOLS: &[Tool] = &[
+    Tool {
+        program: "xclip",
+        args: &["-selection", "clipboard"],
+        caveat: Some(HELPER_CAVEAT),
+    },
+    Tool {
+        program: "xsel",
+        args: &["--clipboard", "--input"],
+        caveat: Some(HELPER_CAVEAT),
+    },
+];
+
+const MACOS_TOOLS: &[Tool] = &[Tool {
+    program: "pbcopy",
+    args: &[],
+    caveat: None,
+}];
+
+const WINDOWS_TOOLS: &[Tool] = &[Tool {
+    program: "clip",
+    args: &[],
+    caveat: Some("Windows clipboard history (Win+V) may keep its own copy after the clear."),
+}];
+
+/// Picks the first installed clipboard tool for the current platform/session,
+/// falling back to X11 tools under Wayland (XWayland)
+fn detect_tool() -> Result<&'static Tool> {
+    let mut candidates: Vec<&'static Tool> = Vec::new();
+    if cfg!(target_os = "macos") {
+        candidates.extend(MACOS_TOOLS);
+    } else if cfg!(windows) {
+        candidates.extend(WINDOWS_TOOLS);
+    } else {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            candidates.extend(WAYLAND_TOOLS);
+        }
+        if env::var_os("DISPLAY").is_some() {
+            candidates.extend(X11_TOOLS);
+        }
+        if candidates.is_empty() {
+            anyhow::bail!("No clipboard available (no Wayland or X11 display found)");
+        }
+    }
+
+    candidates
+        .iter()
+        .copied()
+        .find(|tool| in_path(tool.program))
+        .with_context(|| {
+            let names: Vec<&str> = candidates.iter().map(|tool| tool.program).collect();
+            format!("No clipboard tool found; install one of: {}", names.join(", "))
+        })
+}
+
+/// Whether `program` is an executable on PATH
+fn in_path(program: &str) -> bool {
+    let Some(path) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(program);
+        is_file(&candidate) || (cfg!(windows) && is_file(&candidate.with_extension("exe")))
+    })
+}
+
+fn is_file(path: &Path) -> bool {
+    path.metadata().is_ok_and(|metadata| metadata.is_file())
+}
+
+/// Copies `text` to the system clipboard, returning the tool used so callers
+/// can surface its caveat
+pub fn copy(text: &str) -> Result<&'static Tool> {
+    let tool = detect_tool()?;
+    debug!("Copying to clipboard with {}", tool.program);
+
+    let mut child = Command::new(tool.program)
+        .args(tool.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context(format!("Failed to run '{}'", tool.program))?;
+
+    child
+        .stdin
+        .take()
+        .context("Failed to open clipboard tool stdin")?
+        .write_all(text.as_bytes())?;
+
+    if !child.wait()?.success() {
+        anyhow::bail!("'{}' failed to copy to the clipboard", tool.program);
+    }
+    Ok(tool)
+}
+
+/// Clears the clipboard after `seconds` from a detached background process,
+/// so it happens even after this command exits
+pub fn clear_after(seconds: u64) -> Result<()> {
+    let tool = detect_tool()?;
+
+    let (shell, flag, script) = if cfg!(windows) {
+        (
+            "cmd",
+            "/C",
+            format!("timeout /T {} /NOBREAK > NUL & echo off | {}", seconds, tool.shell_command()),
+        )
+    } else {
+        (
+            "sh",
+            "-c",
+            format!("sleep {} && printf '' | {}", seconds, tool.shell_command()),
+        )
+    };
+
+    Command::new(shell)
+        .args([flag, &script])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to schedule clipboard clear")?;
+    Ok(())
+}
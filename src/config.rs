@@ -0,0 +1,435 @@
+use crate::clipboard;
+use crate::crypto::{KdfParams, KdfProfile};
+use crate::generator::{CharClass, PasswordPolicy};
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Location of the settings file, relative to the home directory
+const CONFIG_PATH: &str = ".config/aliaser/config.toml";
+/// Where `calibrate` records its result; kept apart from the settings file so
+/// calibrating never rewrites something the user edits by hand
+const CALIBRATION_PATH: &str = ".config/aliaser/calibration.toml";
+/// Project settings file, looked for in the current directory and then each
+/// of its parents
+const PROJECT_FILE: &str = ".aliaser.toml";
+/// Weakest generator policy a project file may set, in bits of entropy
+/// (the defaults give about 130)
+const MIN_PROJECT_BITS: f64 = 80.0;
+
+/// Resolved settings the command handlers read their defaults from
+///
+/// Built from the compiled-in defaults, overlaid with the nearest project
+/// file, then the top level of the config file, then the selected profile
+/// and everything it inherits.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Hardening profile used by `init` when `--kdf-profile` is not given
+    pub kdf_profile: KdfProfile,
+    /// Argon2id parameters measured by `calibrate`; `init` prefers them to
+    /// `kdf_profile` unless `--kdf-profile` is given
+    pub kdf_calibration: Option<KdfParams>,
+    /// Seconds a copied secret stays on the clipboard
+    pub clipboard_seconds: u64,
+    /// Policy used whenever a password is generated
+    pub generator: PasswordPolicy,
+    /// Refuse `get --show` without `--force` when the output may be recorded
+    pub guard_secret_output: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            kdf_profile: KdfProfile::default(),
+            kdf_calibration: None,
+            clipboard_seconds: clipboard::DEFAULT_CLEAR_SECONDS,
+            generator: PasswordPolicy::default(),
+            guard_secret_output: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads `~/.config/aliaser/config.toml` over the nearest `.aliaser.toml`
+    /// and resolves `profile` against it
+    ///
+    /// A missing file yields the defaults, unless a profile was asked for.
+    pub fn load(profile: Option<&str>) -> Result<Self> {
+        let home = dirs::home_dir().context("Failed to get home directory")?;
+        let project = std::env::current_dir().ok().and_then(|dir| find_project_file(&dir));
+        let mut settings = Self::load_from(&home.join(CONFIG_PATH), project.as_deref(), profile)?;
+        settings.kdf_calibration = read_calibration(&home.join(CALIBRATION_PATH))?.map(|calibration| calibration.kdf);
+        Ok(settings)
+    }
+
+    fn load_from(path: &Path, project: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let mut settings = Settings::default();
+        if let Some(project) = project {
+            // Any directory can hold one, so a bad file is skipped rather
+            // than stopping commands that never generate a password
+            match read_project_file(project) {
+                Ok(generator) => settings.generator = generator,
+                Err(e) => warn!("Ignoring project settings file {}: {:#}", project.display(), e),
+            }
+        }
+
+        let file = if path.exists() {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            ConfigFile::parse(&contents).with_context(|| format!("Invalid config file {}", path.display()))?
+        } else {
+            ConfigFile::default()
+        };
+
+        file.resolve(settings, profile)
+            .with_context(|| format!("Failed to apply settings from {}", path.display()))
+    }
+}
+
+/// Reads a project file's generator settings over the defaults, refusing
+/// any that would generate passwords weaker than [`MIN_PROJECT_BITS`]
+fn read_project_file(path: &Path) -> Result<PasswordPolicy> {
+    let contents = fs::read_to_string(path).context("Failed to read it")?;
+    let file = ProjectFile::parse(&contents)?;
+
+    let mut policy = PasswordPolicy::default();
+    file.generator.apply(&mut policy);
+    let bits = policy.entropy_bits()?;
+    if bits < MIN_PROJECT_BITS {
+        anyhow::bail!(
+            "its generator settings give passwords of about {:.0} bits; a project file must allow at least {:.0}",
+            bits,
+            MIN_PROJECT_BITS
+        );
+    }
+    Ok(policy)
+}
+
+/// Returns the `.aliaser.toml` in `dir` or its nearest parent that has one
+fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+}
+
+/// Result of `calibrate`, as stored in `~/.config/aliaser/calibration.toml`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Calibration {
+    /// Unlock time the parameters were tuned for
+    target_ms: u64,
+    kdf: KdfParams,
+}
+
+/// Records calibrated key derivation parameters for later `init`s and
+/// returns the file they were written to
+pub fn save_calibration(target_ms: u64, kdf: KdfParams) -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Failed to get home directory")?;
+    let path = home.join(CALIBRATION_PATH);
+    write_calibration(&path, &Calibration { target_ms, kdf })?;
+    Ok(path)
+}
+
+fn write_calibration(path: &Path, calibration: &Calibration) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let contents = format!(
+        "# Written by `aliaser calibrate`; delete this file to go back to kdf_profile\n{}",
+        toml::to_string(calibration)?
+    );
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn read_calibration(path: &Path) -> Result<Option<Calibration>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let calibration = toml::from_str(&contents)
+        .with_context(|| format!("Invalid calibration file {}; run `calibrate` again", path.display()))?;
+    Ok(Some(calibration))
+}
+
+/// On-disk shape of a project's `.aliaser.toml`
+///
+/// Limited to the generator, so a checked-out repository can set a team's
+/// password policy but not weaken key derivation or clipboard handling.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProjectFile {
+    #[serde(default)]
+    generator: GeneratorOverrides,
+}
+
+impl ProjectFile {
+    fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+/// On-disk shape of the settings file
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(flatten)]
+    base: Overrides,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProfileSection {
+    /// Name of another profile whose settings apply first
+    inherits: Option<String>,
+    #[serde(flatten)]
+    overrides: Overrides,
+}
+
+/// A set of optional settings; anything left out falls through to the parent
+#[derive(Debug, Default, Deserialize)]
+struct Overrides {
+    kdf_profile: Option<KdfProfile>,
+    clipboard_seconds: Option<u64>,
+    guard_secret_output: Option<bool>,
+    #[serde(default)]
+    generator: GeneratorOverrides,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeneratorOverrides {
+    length: Option<usize>,
+    lowercase: Option<bool>,
+    uppercase: Option<bool>,
+    digits: Option<bool>,
+    symbols: Option<bool>,
+    exclude_ambiguous: Option<bool>,
+    required_classes: Option<Vec<CharClass>>,
+}
+
+impl ConfigFile {
+    fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Applies the file to `settings`, which hold whatever lies beneath it
+    fn resolve(&self, mut settings: Settings, profile: Option<&str>) -> Result<Settings> {
+        self.base.apply(&mut settings);
+
+        if let Some(name) = profile {
+            for section in self.chain(name)?.iter().rev() {
+                section.overrides.apply(&mut settings);
+            }
+        }
+
+        if settings.clipboard_seconds == 0 {
+            anyhow::bail!("clipboard_seconds must be at least 1");
+        }
+
+        Ok(settings)
+    }
+
+    /// Returns `name` followed by each profile it inherits from, nearest first
+    fn chain(&self, name: &str) -> Result<Vec<&ProfileSection>> {
+        let mut chain = Vec::new();
+        let mut seen: Vec<&str> = Vec::new();
+        let mut next = Some(name);
+
+        while let Some(current) = next {
+            if seen.contains(&current) {
+                anyhow::bail!("Profile '{}' inherits from itself (via {})", current, seen.join(" -> "));
+            }
+            let section = self
+                .profiles
+                .get(current)
+                .with_context(|| format!("Unknown profile '{}'", current))?;
+
+            seen.push(current);
+            chain.push(section);
+            next = section.inherits.as_deref();
+        }
+
+        Ok(chain)
+    }
+}
+
+impl Overrides {
+    fn apply(&self, settings: &mut Settings) {
+        if let Some(profile) = self.kdf_profile {
+            settings.kdf_profile = profile;
+        }
+        if let Some(seconds) = self.clipboard_seconds {
+            settings.clipboard_seconds = seconds;
+        }
+        if let Some(guard) = self.guard_secret_output {
+            settings.guard_secret_output = guard;
+        }
+
+        self.generator.apply(&mut settings.generator);
+    }
+}
+
+impl GeneratorOverrides {
+    fn apply(&self, policy: &mut PasswordPolicy) {
+        let fields = [
+            (self.lowercase, &mut policy.lowercase),
+            (self.uppercase, &mut policy.uppercase),
+            (self.digits, &mut policy.digits),
+            (self.symbols, &mut policy.symbols),
+            (self.exclude_ambiguous, &mut policy.exclude_ambiguous),
+        ];
+        for (value, field) in fields {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Some(length) = self.length {
+            policy.length = length;
+        }
+        if let Some(required) = &self.required_classes {
+            policy.required_classes = Some(required.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+clipboard_seconds = 20
+
+[generator]
+length = 24
+
+[profiles.work]
+kdf_profile = "moderate"
+clipboard_seconds = 10
+
+[profiles.work.generator]
+symbols = false
+required_classes = ["lowercase", "digits"]
+
+[profiles.locked-down]
+inherits = "work"
+kdf_profile = "paranoid"
+guard_secret_output = true
+
+[profiles.locked-down.generator]
+length = 32
+exclude_ambiguous = true
+"#;
+
+    #[test]
+    fn test_parse_sample_config() {
+        let file = ConfigFile::parse(SAMPLE).unwrap();
+
+        let base = file.resolve(Settings::default(), None).unwrap();
+        assert_eq!(base.kdf_profile, KdfProfile::Interactive);
+        assert_eq!(base.clipboard_seconds, 20);
+        assert_eq!(base.generator.length, 24);
+        assert!(base.generator.symbols);
+        assert!(!base.guard_secret_output);
+
+        let work = file.resolve(Settings::default(), Some("work")).unwrap();
+        assert_eq!(work.kdf_profile, KdfProfile::Moderate);
+        assert_eq!(work.clipboard_seconds, 10);
+        assert_eq!(work.generator.length, 24);
+        assert!(!work.generator.symbols);
+        assert_eq!(work.generator.required(), vec![CharClass::Lowercase, CharClass::Digits]);
+        assert_eq!(base.generator.required().len(), 4);
+    }
+
+    #[test]
+    fn test_profile_inheritance() {
+        let file = ConfigFile::parse(SAMPLE).unwrap();
+        let settings = file.resolve(Settings::default(), Some("locked-down")).unwrap();
+
+        assert_eq!(settings.kdf_profile, KdfProfile::Paranoid);
+        assert_eq!(settings.clipboard_seconds, 10);
+        assert_eq!(settings.generator.length, 32);
+        assert!(!settings.generator.symbols);
+        assert!(settings.generator.exclude_ambiguous);
+        assert!(settings.guard_secret_output);
+    }
+
+    #[test]
+    fn test_bad_profiles_rejected() {
+        let file = ConfigFile::parse(SAMPLE).unwrap();
+        assert!(file.resolve(Settings::default(), Some("home")).is_err());
+
+        let cyclic = ConfigFile::parse(
+            "[profiles.a]\ninherits = \"b\"\n\n[profiles.b]\ninherits = \"a\"\n",
+        )
+        .unwrap();
+        assert!(cyclic.resolve(Settings::default(), Some("a")).is_err());
+
+        assert!(ConfigFile::parse("[generator]\nlenght = 5\n").is_err());
+    }
+
+    #[test]
+    fn test_missing_file_uses_defaults() {
+        let path = std::env::temp_dir().join("aliaser-test-no-such-config.toml");
+        let settings = Settings::load_from(&path, None, None).unwrap();
+        assert_eq!(settings.clipboard_seconds, clipboard::DEFAULT_CLEAR_SECONDS);
+        assert_eq!(settings.generator.length, PasswordPolicy::default().length);
+
+        assert!(Settings::load_from(&path, None, Some("work")).is_err());
+    }
+
+    #[test]
+    fn test_project_file_sits_between_defaults_and_config() {
+        let dir = std::env::temp_dir().join(format!("aliaser-test-project-{}", std::process::id()));
+        let nested = dir.join("repo/src");
+        fs::create_dir_all(&nested).unwrap();
+        let project = dir.join("repo").join(PROJECT_FILE);
+        fs::write(&project, "[generator]\nlength = 40\nsymbols = false\nexclude_ambiguous = true\n").unwrap();
+        let config = dir.join("config.toml");
+        fs::write(&config, SAMPLE).unwrap();
+
+        assert_eq!(find_project_file(&nested), Some(project.clone()));
+
+        // The project file beats the defaults...
+        let settings = Settings::load_from(&dir.join("missing.toml"), Some(&project), None).unwrap();
+        assert_eq!(settings.generator.length, 40);
+        assert!(!settings.generator.symbols);
+
+        // ...and loses to the config file and its profiles
+        let settings = Settings::load_from(&config, Some(&project), None).unwrap();
+        assert_eq!(settings.generator.length, 24);
+        assert!(!settings.generator.symbols);
+        assert!(settings.generator.exclude_ambiguous);
+        let settings = Settings::load_from(&config, Some(&project), Some("locked-down")).unwrap();
+        assert_eq!(settings.generator.length, 32);
+
+        // A file setting anything beyond the generator, or weakening it too
+        // far, is skipped rather than failing every command
+        let missing = dir.join("missing.toml");
+        for contents in ["guard_secret_output = false\n", "[generator]\nlength = 8\n", "[generator\n"] {
+            fs::write(&project, contents).unwrap();
+            let settings = Settings::load_from(&missing, Some(&project), None).unwrap();
+            assert_eq!(settings.generator, PasswordPolicy::default());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_calibration_round_trip() {
+        let path = std::env::temp_dir().join(format!("aliaser-test-calibration-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(read_calibration(&path).unwrap().is_none());
+
+        let calibration = Calibration {
+            target_ms: 500,
+            kdf: KdfParams {
+                memory_kib: 300 * 1024,
+                ..KdfParams::default()
+            },
+        };
+        write_calibration(&path, &calibration).unwrap();
+        let read = read_calibration(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, Some(calibration));
+    }
+}
@@ -1,97 +1,615 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
-    Aes256Gcm, Nonce,
+    aead::{self, generic_array::typenum::Unsigned, Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm,
 };
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
 use argon2::{
     password_hash::{PasswordHasher, SaltString},
-    Argon2, PasswordHash, PasswordVerifier,
+    Algorithm, Argon2, Params, PasswordHash, Version,
 };
+use pbkdf2::Pbkdf2;
 use rand::RngCore;
 use anyhow::Result;
+use log::{debug, trace};
+use scrypt::Scrypt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use std::fmt;
+use zeroize::Zeroizing;
+use std::time::{Duration, Instant};
 
-const NONCE_SIZE: usize = 12;
-const SALT_SIZE: usize = 32;
+/// Salt length for new vaults unless `init --salt-size` says otherwise
+pub const DEFAULT_SALT_SIZE: usize = 32;
+/// Shortest salt accepted for a vault
+pub const MIN_SALT_SIZE: usize = 16;
+/// Longest salt accepted for a vault
+pub const MAX_SALT_SIZE: usize = 64;
+const KEY_SIZE: usize = 32;
 
-/// Derives a 256-bit key from a password using Argon2id
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
-    let argon2 = Argon2::default();
+/// First bytes of a file written by [`encrypt_with_passphrase`]
+const SEALED_MAGIC: &[u8] = b"aliaser-sealed\0";
+const SEALED_VERSION: u8 = 1;
+/// Start of the vault file's header, followed by a format version byte and
+/// a cipher id byte
+const ENVELOPE_MAGIC: &[u8] = b"ALSR";
+const ENVELOPE_VERSION: u8 = 1;
+/// Length of the header [`encrypt_envelope`] writes before the nonce
+pub const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 2;
+
+/// scrypt block size; with r = 8 each unit of N costs 1 KiB of memory
+const SCRYPT_R: u32 = 8;
+
+/// Most memory `calibrate` will ask Argon2id for (1 GiB)
+const CALIBRATION_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+/// Derivations timed per candidate; the fastest counts
+const CALIBRATION_TRIALS: usize = 3;
+
+/// Key derivation function used for the vault key and master password hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KdfAlgorithm {
+    /// Argon2id (memory-hard, recommended)
+    #[default]
+    Argon2id,
+    /// scrypt (memory-hard)
+    Scrypt,
+    /// PBKDF2-HMAC-SHA256 (for FIPS-style requirements; not memory-hard)
+    Pbkdf2Sha256,
+}
+
+impl fmt::Display for KdfAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KdfAlgorithm::Argon2id => "argon2id",
+            KdfAlgorithm::Scrypt => "scrypt",
+            KdfAlgorithm::Pbkdf2Sha256 => "pbkdf2-sha256",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Cost parameters used for key derivation and password hashing
+///
+/// The fields are interpreted per algorithm:
+/// - Argon2id: memory, iterations (t_cost) and lanes
+/// - scrypt: memory is N·r·128 bytes with r = 8, so N = `memory_kib` (a power
+///   of two); `parallelism` is p and `iterations` is unused
+/// - PBKDF2: `iterations` is the round count; the other fields are unused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Configs written before other algorithms existed are Argon2id
+    #[serde(default)]
+    pub algorithm: KdfAlgorithm,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfProfile::Interactive.params(KdfAlgorithm::default())
+    }
+}
+
+impl KdfParams {
+    /// Fails if any cost is above the most this version ever sets: the
+    /// paranoid profile's, or calibration's memory ceiling for Argon2id.
+    /// Checked before deriving with parameters read from a file from
+    /// elsewhere, which could otherwise exhaust memory or run for hours.
+    pub fn check_limits(&self) -> Result<()> {
+        let max = KdfProfile::Paranoid.params(self.algorithm);
+        let max_memory_kib = match self.algorithm {
+            KdfAlgorithm::Argon2id => CALIBRATION_MAX_MEMORY_KIB,
+            _ => max.memory_kib,
+        };
+        if self.memory_kib > max_memory_kib || self.iterations > max.iterations || self.parallelism > max.parallelism {
+            anyhow::bail!("Key derivation parameters are beyond what aliaser uses ({})", self);
+        }
+        Ok(())
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_SIZE))
+            .map_err(|e| anyhow::anyhow!("Invalid key derivation parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn scrypt(&self) -> Result<scrypt::Params> {
+        if !self.memory_kib.is_power_of_two() {
+            anyhow::bail!("Invalid key derivation parameters: scrypt memory must be a power of two KiB");
+        }
+        let log_n = self.memory_kib.trailing_zeros() as u8;
+        scrypt::Params::new(log_n, SCRYPT_R, self.parallelism, KEY_SIZE)
+            .map_err(|e| anyhow::anyhow!("Invalid key derivation parameters: {}", e))
+    }
+
+    fn pbkdf2(&self) -> Result<pbkdf2::Params> {
+        if self.iterations == 0 {
+            anyhow::bail!("Invalid key derivation parameters: PBKDF2 needs at least one round");
+        }
+        Ok(pbkdf2::Params {
+            rounds: self.iterations,
+            output_length: KEY_SIZE,
+        })
+    }
+}
+
+impl fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.algorithm {
+            KdfAlgorithm::Argon2id => write!(
+                f,
+                "Argon2id, {} MiB, {} iterations, {} lanes",
+                self.memory_kib / 1024,
+                self.iterations,
+                self.parallelism
+            ),
+            KdfAlgorithm::Scrypt => write!(
+                f,
+                "scrypt, {} MiB (N=2^{}, r={}, p={})",
+                self.memory_kib / 1024,
+                self.memory_kib.trailing_zeros(),
+                SCRYPT_R,
+                self.parallelism
+            ),
+            KdfAlgorithm::Pbkdf2Sha256 => write!(f, "PBKDF2-HMAC-SHA256, {} rounds", self.iterations),
+        }
+    }
+}
+
+/// Named hardening levels, trading unlock latency for brute-force resistance
+///
+/// The descriptions are the Argon2id costs; scrypt and PBKDF2 scale up similarly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfProfile {
+    /// Argon2 defaults (19 MiB, 2 iterations); what vaults used before profiles existed
+    #[default]
+    Interactive,
+    /// 64 MiB, 3 iterations, 4 lanes
+    Moderate,
+    /// 256 MiB, 4 iterations, 4 lanes
+    Paranoid,
+}
+
+impl KdfProfile {
+    pub fn params(self, algorithm: KdfAlgorithm) -> KdfParams {
+        let (memory_kib, iterations, parallelism) = match (algorithm, self) {
+            (KdfAlgorithm::Argon2id, KdfProfile::Interactive) => {
+                (Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST)
+            }
+            (KdfAlgorithm::Argon2id, KdfProfile::Moderate) => (64 * 1024, 3, 4),
+            (KdfAlgorithm::Argon2id, KdfProfile::Paranoid) => (256 * 1024, 4, 4),
+            // OWASP's minimum is N=2^17 (128 MiB) with r=8, p=1
+            (KdfAlgorithm::Scrypt, KdfProfile::Interactive) => (128 * 1024, 1, 1),
+            (KdfAlgorithm::Scrypt, KdfProfile::Moderate) => (256 * 1024, 1, 1),
+            (KdfAlgorithm::Scrypt, KdfProfile::Paranoid) => (512 * 1024, 1, 2),
+            // OWASP recommends 600,000 rounds of PBKDF2-HMAC-SHA256
+            (KdfAlgorithm::Pbkdf2Sha256, KdfProfile::Interactive) => (0, 600_000, 1),
+            (KdfAlgorithm::Pbkdf2Sha256, KdfProfile::Moderate) => (0, 1_200_000, 1),
+            (KdfAlgorithm::Pbkdf2Sha256, KdfProfile::Paranoid) => (0, 2_400_000, 1),
+        };
+        KdfParams {
+            algorithm,
+            memory_kib,
+            iterations,
+            parallelism,
+        }
+    }
+}
+
+impl fmt::Display for KdfProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KdfProfile::Interactive => "interactive",
+            KdfProfile::Moderate => "moderate",
+            KdfProfile::Paranoid => "paranoid",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Derives a 256-bit key from a password using the configured KDF
+pub fn derive_key(password: &str, salt: &[u8], params: &KdfParams) -> Result<[u8; 32]> {
     let mut output_key = [0u8; 32];
     
-    argon2
-        .hash_password_into(password.as_bytes(), salt, &mut output_key)
-        .map_err(|e| anyhow::anyhow!("Failed to derive key from password: {}", e))?;
+    debug!("Deriving key ({})", params);
+    let started = Instant::now();
+    match params.algorithm {
+        KdfAlgorithm::Argon2id => params
+            .argon2()?
+            .hash_password_into(password.as_bytes(), salt, &mut output_key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key from password: {}", e))?,
+        KdfAlgorithm::Scrypt => scrypt::scrypt(password.as_bytes(), salt, &params.scrypt()?, &mut output_key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key from password: {}", e))?,
+        KdfAlgorithm::Pbkdf2Sha256 => {
+            pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, params.pbkdf2()?.rounds, &mut output_key)
+        }
+    }
+    debug!("Key derived in {} ms", started.elapsed().as_millis());
     
     Ok(output_key)
 }
 
-/// Generates a random salt for key derivation
-pub fn generate_salt() -> [u8; SALT_SIZE] {
-    let mut salt = [0u8; SALT_SIZE];
+/// Finds the Argon2id memory cost whose derivation takes about `target` on this machine
+///
+/// Iterations and lanes stay at the interactive defaults. Memory never drops
+/// below the interactive profile's, so a machine too slow for `target` gets
+/// those parameters rather than weaker ones.
+pub fn calibrate(target: Duration) -> Result<KdfParams> {
+    let salt = generate_salt(DEFAULT_SALT_SIZE)?;
+    calibrate_with(target, |params| {
+        // The fastest trial is the one least disturbed by whatever else is running
+        let mut fastest = Duration::MAX;
+        for _ in 0..CALIBRATION_TRIALS {
+            let started = Instant::now();
+            derive_key("calibration", &salt, params)?;
+            fastest = fastest.min(started.elapsed());
+        }
+        debug!("{} took {} ms", params, fastest.as_millis());
+        Ok(fastest)
+    })
+}
+
+fn calibrate_with(target: Duration, mut measure: impl FnMut(&KdfParams) -> Result<Duration>) -> Result<KdfParams> {
+    let base = KdfProfile::Interactive.params(KdfAlgorithm::Argon2id);
+    let with_memory = |memory_kib| KdfParams { memory_kib, ..base };
+
+    if measure(&base)? > target {
+        return Ok(base);
+    }
+
+    // Double until a derivation is too slow, then bisect between the last
+    // fit and the first miss, in whole MiB, to within 1/16
+    let mut fits = base.memory_kib;
+    let mut too_slow = loop {
+        let next = (fits * 2).min(CALIBRATION_MAX_MEMORY_KIB);
+        if next == fits {
+            return Ok(with_memory(fits));
+        }
+        if measure(&with_memory(next))? > target {
+            break next;
+        }
+        fits = next;
+    };
+    while too_slow - fits > fits / 16 {
+        let middle = (fits + (too_slow - fits) / 2) / 1024 * 1024;
+        if measure(&with_memory(middle))? > target {
+            too_slow = middle;
+        } else {
+            fits = middle;
+        }
+    }
+
+    Ok(with_memory(fits))
+}
+
+/// Generates a random salt of `size` bytes for key derivation
+pub fn generate_salt(size: usize) -> Result<Vec<u8>> {
+    validate_salt_size(size)?;
+    let mut salt = vec![0u8; size];
     OsRng.fill_bytes(&mut salt);
-    salt
+    Ok(salt)
 }
 
-/// Encrypts data using AES-256-GCM
-pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>> {
-    let cipher = Aes256Gcm::new(key.into());
-    
-    // Generate random nonce
-    let mut nonce_bytes = [0u8; NONCE_SIZE];
-    OsRng.fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
+/// Checks a salt length against [`MIN_SALT_SIZE`] and [`MAX_SALT_SIZE`]
+pub fn validate_salt_size(size: usize) -> Result<()> {
+    if !(MIN_SALT_SIZE..=MAX_SALT_SIZE).contains(&size) {
+        anyhow::bail!(
+            "Salt size must be between {} and {} bytes, not {}",
+            MIN_SALT_SIZE,
+            MAX_SALT_SIZE,
+            size
+        );
+    }
+    Ok(())
+}
+
+/// Authenticated cipher used to encrypt vault data
+///
+/// Every save re-encrypts the whole vault under the same key with a fresh
+/// random nonce. With the 96-bit nonces of AES-GCM and ChaCha20-Poly1305 the
+/// chance of a repeat reaches the recommended 2^-32 limit after about 2^32
+/// saves, and a repeat leaks the XOR of two plaintexts (and, for GCM, the
+/// authentication key). XChaCha20-Poly1305's 192-bit nonce makes random nonces
+/// safe for any realistic number of writes without keeping a counter that
+/// could be rolled back along with the config, so new vaults use it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Cipher {
+    /// XChaCha20-Poly1305 with 192-bit random nonces (recommended)
+    #[default]
+    #[serde(rename = "xchacha20-poly1305")]
+    #[value(name = "xchacha20")]
+    XChaCha20Poly1305,
+    /// AES-256-GCM (fastest with AES hardware acceleration)
+    #[serde(rename = "aes-256-gcm")]
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305 (faster and constant-time without AES instructions)
+    #[serde(rename = "chacha20-poly1305")]
+    #[value(name = "chacha20")]
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Cipher of files written before the choice was recorded
+    pub fn legacy() -> Self {
+        Cipher::Aes256Gcm
+    }
+
+    /// Length of the random nonce [`encrypt`] puts at the start of its output
+    pub fn nonce_len(self) -> usize {
+        match self {
+            Cipher::XChaCha20Poly1305 => <XChaCha20Poly1305 as aead::AeadCore>::NonceSize::USIZE,
+            Cipher::Aes256Gcm => <Aes256Gcm as aead::AeadCore>::NonceSize::USIZE,
+            Cipher::ChaCha20Poly1305 => <ChaCha20Poly1305 as aead::AeadCore>::NonceSize::USIZE,
+        }
+    }
+}
+
+impl Cipher {
+    /// Byte identifying the cipher in an envelope header; never reuse one
+    fn id(self) -> u8 {
+        match self {
+            Cipher::XChaCha20Poly1305 => 1,
+            Cipher::Aes256Gcm => 2,
+            Cipher::ChaCha20Poly1305 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        [Cipher::XChaCha20Poly1305, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305]
+            .into_iter()
+            .find(|cipher| cipher.id() == id)
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Cipher::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+            Cipher::Aes256Gcm => "AES-256-GCM",
+            Cipher::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Encrypts data with `cipher`, returning the random nonce followed by the ciphertext
+///
+/// `aad` is authenticated but not encrypted; decryption fails unless the same
+/// bytes are passed to [`decrypt`].
+pub fn encrypt(data: &[u8], key: &[u8; 32], cipher: Cipher, aad: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; cipher.nonce_len()];
+    OsRng.fill_bytes(&mut nonce);
+    encrypt_with_nonce(data, key, cipher, aad, &nonce)
+}
+
+/// [`encrypt`] with a caller-chosen nonce, so tests can pin the output
+///
+/// Never reuse a nonce with the same key; everything outside tests should go
+/// through [`encrypt`].
+pub(crate) fn encrypt_with_nonce(
+    data: &[u8],
+    key: &[u8; 32],
+    cipher: Cipher,
+    aad: &[u8],
+    nonce: &[u8],
+) -> Result<Vec<u8>> {
+    if nonce.len() != cipher.nonce_len() {
+        anyhow::bail!("Invalid nonce: {} needs {} bytes, got {}", cipher, cipher.nonce_len(), nonce.len());
+    }
+    let payload = Payload { msg: data, aad };
+    let result = match cipher {
+        Cipher::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(payload, key, nonce),
+        Cipher::Aes256Gcm => seal::<Aes256Gcm>(payload, key, nonce),
+        Cipher::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(payload, key, nonce),
+    }?;
+    trace!("Encrypted {} bytes with {}", data.len(), cipher);
+    Ok(result)
+}
+
+/// Decrypts data produced by [`encrypt`] with the same `cipher` and `aad`
+pub fn decrypt(encrypted_data: &[u8], key: &[u8; 32], cipher: Cipher, aad: &[u8]) -> Result<Vec<u8>> {
+    let plaintext = match cipher {
+        Cipher::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(encrypted_data, key, aad),
+        Cipher::Aes256Gcm => open::<Aes256Gcm>(encrypted_data, key, aad),
+        Cipher::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(encrypted_data, key, aad),
+    }?;
+    trace!("Decrypted {} bytes with {}", plaintext.len(), cipher);
+    Ok(plaintext)
+}
+
+/// Encrypts like [`encrypt`], behind a header saying how: [`ENVELOPE_MAGIC`],
+/// the format version and the cipher id
+///
+/// The header is authenticated along with `aad`, so editing it fails
+/// decryption rather than changing how the data is read.
+pub fn encrypt_envelope(data: &[u8], key: &[u8; 32], cipher: Cipher, aad: &[u8]) -> Result<Vec<u8>> {
+    let mut envelope = envelope_header(cipher);
+    let ciphertext = encrypt(data, key, cipher, &envelope_aad(&envelope, aad))?;
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decrypts [`encrypt_envelope`] output with the cipher its header names, or
+/// headerless [`encrypt`] output, as written before the header existed,
+/// with `cipher`
+pub fn decrypt_envelope(data: &[u8], key: &[u8; 32], cipher: Cipher, aad: &[u8]) -> Result<Vec<u8>> {
+    if !has_envelope(data) || data.len() < ENVELOPE_HEADER_LEN {
+        return decrypt(data, key, cipher, aad);
+    }
+    let (header, ciphertext) = data.split_at(ENVELOPE_HEADER_LEN);
+    // A headerless file whose random nonce happens to start like a header
+    // is read the old way, but errors describe the header
+    open_envelope(header, ciphertext, key, aad).or_else(|e| decrypt(data, key, cipher, aad).map_err(|_| e))
+}
+
+/// Whether `prefix`, the start of an encrypted file, has an envelope header
+pub fn has_envelope(prefix: &[u8]) -> bool {
+    prefix.starts_with(ENVELOPE_MAGIC)
+}
+
+fn envelope_header(cipher: Cipher) -> Vec<u8> {
+    let mut header = ENVELOPE_MAGIC.to_vec();
+    header.push(ENVELOPE_VERSION);
+    header.push(cipher.id());
+    header
+}
+
+fn envelope_aad(header: &[u8], aad: &[u8]) -> Vec<u8> {
+    [header, aad].concat()
+}
+
+fn open_envelope(header: &[u8], ciphertext: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>> {
+    let version = header[ENVELOPE_MAGIC.len()];
+    if version > ENVELOPE_VERSION {
+        anyhow::bail!("File format version {} is newer than this version of aliaser supports", version);
+    }
+    let cipher_id = header[ENVELOPE_MAGIC.len() + 1];
+    let cipher =
+        Cipher::from_id(cipher_id).ok_or_else(|| anyhow::anyhow!("Unknown cipher {} in the file header", cipher_id))?;
+    decrypt(ciphertext, key, cipher, &envelope_aad(header, aad))
+}
+
+fn seal<C: Aead + KeyInit>(payload: Payload, key: &[u8; 32], nonce: &[u8]) -> Result<Vec<u8>> {
+    let cipher = C::new_from_slice(key).map_err(|e| anyhow::anyhow!("Invalid key: {}", e))?;
+    let nonce = aead::Nonce::<C>::from_slice(nonce);
     
     // Encrypt
     let ciphertext = cipher
-        .encrypt(nonce, data)
+        .encrypt(nonce, payload)
         .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
-    
+
     // Prepend nonce to ciphertext
-    let mut result = nonce_bytes.to_vec();
+    let mut result = nonce.to_vec();
     result.extend_from_slice(&ciphertext);
     
     Ok(result)
 }
 
-/// Decrypts data using AES-256-GCM
-pub fn decrypt(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>> {
-    if encrypted_data.len() < NONCE_SIZE {
+fn open<C: Aead + KeyInit>(encrypted_data: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce_size = C::NonceSize::USIZE;
+    if encrypted_data.len() < nonce_size {
         anyhow::bail!("Invalid encrypted data: too short");
     }
     
-    let cipher = Aes256Gcm::new(key.into());
+    let cipher = C::new_from_slice(key).map_err(|e| anyhow::anyhow!("Invalid key: {}", e))?;
     
     // Extract nonce and ciphertext
-    let (nonce_bytes, ciphertext) = encrypted_data.split_at(NONCE_SIZE);
-    let nonce = Nonce::from_slice(nonce_bytes);
+    let (nonce_bytes, ciphertext) = encrypted_data.split_at(nonce_size);
+    let nonce = aead::Nonce::<C>::from_slice(nonce_bytes);
     
     // Decrypt
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
-    
-    Ok(plaintext)
+    cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+}
+
+/// Compares two secrets in constant time
+///
+/// Use this instead of `==` whenever either side is secret. The master
+/// password itself is only ever checked by [`verify_password`], whose hash
+/// comparison is already constant-time; the direct comparisons are the new
+/// master password against its confirmation and a stored password against
+/// its replacement when detecting rotation. Lengths are not hidden.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Key derivation settings recorded in front of sealed data
+#[derive(Serialize, Deserialize)]
+struct SealedHeader {
+    kdf_params: KdfParams,
+    cipher: Cipher,
+    salt: Vec<u8>,
+}
+
+/// Encrypts `data` under a key derived from `passphrase` and a fresh salt,
+/// independently of any vault
+///
+/// The output is self-describing: [`SEALED_MAGIC`], a version byte, a
+/// length-prefixed JSON header with the KDF parameters, cipher and salt,
+/// then the ciphertext. The header is passed as associated data, so
+/// tampering with it fails decryption like tampering with the ciphertext.
+pub fn encrypt_with_passphrase(data: &[u8], passphrase: &str, kdf_params: KdfParams) -> Result<Vec<u8>> {
+    let cipher = Cipher::default();
+    let salt = generate_salt(DEFAULT_SALT_SIZE)?;
+    let key = Zeroizing::new(derive_key(passphrase, &salt, &kdf_params)?);
+
+    let header = serde_json::to_vec(&SealedHeader { kdf_params, cipher, salt })?;
+    let mut sealed = SEALED_MAGIC.to_vec();
+    sealed.push(SEALED_VERSION);
+    sealed.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    sealed.extend_from_slice(&header);
+
+    let ciphertext = encrypt(data, &key, cipher, &sealed)?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts data written by [`encrypt_with_passphrase`]
+pub fn decrypt_with_passphrase(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let rest = sealed
+        .strip_prefix(SEALED_MAGIC)
+        .ok_or_else(|| anyhow::anyhow!("Not a file encrypted by aliaser"))?;
+    let (&version, rest) = rest.split_first().ok_or_else(|| anyhow::anyhow!("Sealed file is truncated"))?;
+    if version > SEALED_VERSION {
+        anyhow::bail!("Sealed file version {} is newer than this version of aliaser supports", version);
+    }
+    if rest.len() < 4 {
+        anyhow::bail!("Sealed file is truncated");
+    }
+    let (length, rest) = rest.split_at(4);
+    let length = u32::from_be_bytes(length.try_into()?) as usize;
+    if rest.len() < length {
+        anyhow::bail!("Sealed file is truncated");
+    }
+    let (header_bytes, ciphertext) = rest.split_at(length);
+    let header: SealedHeader = serde_json::from_slice(header_bytes)?;
+    header.kdf_params.check_limits()?;
+
+    let key = Zeroizing::new(derive_key(passphrase, &header.salt, &header.kdf_params)?);
+    let aad = &sealed[..sealed.len() - ciphertext.len()];
+    decrypt(ciphertext, &key, header.cipher, aad).map_err(|_| anyhow::anyhow!("Wrong passphrase, or the file is damaged"))
 }
 
 /// Hashes a password for verification (not for encryption key derivation)
-pub fn hash_password(password: &str) -> Result<String> {
+///
+/// Uses the same cost parameters as key derivation so the stored hash isn't a
+/// cheaper brute-force target than the vault itself.
+pub fn hash_password(password: &str, params: &KdfParams) -> Result<String> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
     
-    let password_hash = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| anyhow::anyhow!("Password hashing failed: {}", e))?
-        .to_string();
+    let password_hash = match params.algorithm {
+        KdfAlgorithm::Argon2id => params.argon2()?.hash_password(password.as_bytes(), &salt),
+        KdfAlgorithm::Scrypt => {
+            Scrypt.hash_password_customized(password.as_bytes(), None, None, params.scrypt()?, &salt)
+        }
+        KdfAlgorithm::Pbkdf2Sha256 => Pbkdf2.hash_password_customized(
+            password.as_bytes(),
+            Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
+            None,
+            params.pbkdf2()?,
+            &salt,
+        ),
+    }
+    .map_err(|e| anyhow::anyhow!("Password hashing failed: {}", e))?
+    .to_string();
     
     Ok(password_hash)
 }
 
 /// Verifies a password against a hash
+///
+/// The algorithm and its costs are read from the PHC string itself.
 pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
     let parsed_hash = PasswordHash::new(hash)
         .map_err(|e| anyhow::anyhow!("Invalid password hash: {}", e))?;
     
-    Ok(Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
+    Ok(parsed_hash
+        .verify_password(&[&Argon2::default(), &Scrypt, &Pbkdf2], password.as_bytes())
         .is_ok())
 }
 
@@ -104,18 +622,296 @@ mod tests {
         let key = [0u8; 32];
         let data = b"Hello, World!";
         
-        let encrypted = encrypt(data, &key).unwrap();
-        let decrypted = decrypt(&encrypted, &key).unwrap();
-        
-        assert_eq!(data, decrypted.as_slice());
+        for cipher in [Cipher::XChaCha20Poly1305, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let encrypted = encrypt(data, &key, cipher, b"aad").unwrap();
+            let decrypted = decrypt(&encrypted, &key, cipher, b"aad").unwrap();
+            
+            assert_eq!(data, decrypted.as_slice(), "{}", cipher);
+        }
+    }
+
+    #[test]
+    fn test_decrypting_with_the_other_cipher_fails() {
+        let key = [1u8; 32];
+        let encrypted = encrypt(b"Hello, World!", &key, Cipher::ChaCha20Poly1305, &[]).unwrap();
+
+        assert!(decrypt(&encrypted, &key, Cipher::Aes256Gcm, &[]).is_err());
+    }
+
+    #[test]
+    fn test_decrypting_with_different_aad_fails() {
+        let key = [1u8; 32];
+        for cipher in [Cipher::XChaCha20Poly1305, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let encrypted = encrypt(b"Hello, World!", &key, cipher, b"version 1").unwrap();
+
+            assert!(decrypt(&encrypted, &key, cipher, b"version 2").is_err(), "{}", cipher);
+            assert!(decrypt(&encrypted, &key, cipher, &[]).is_err(), "{}", cipher);
+        }
+    }
+
+    #[test]
+    fn test_default_cipher_nonces_do_not_repeat() {
+        let key = [2u8; 32];
+        let mut nonces = std::collections::HashSet::new();
+
+        for _ in 0..10_000 {
+            let encrypted = encrypt(b"vault", &key, Cipher::default(), &[]).unwrap();
+            assert!(nonces.insert(encrypted[..24].to_vec()));
+        }
+    }
+
+    /// The sunscreen vector from RFC 8439 section 2.8.2 and the XChaCha20 draft
+    const VECTOR_PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip \
+for the future, sunscreen would be it.";
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_ciphertext_known_answers() {
+        let key: [u8; 32] = std::array::from_fn(|i| 0x80 + i as u8);
+        let aad = unhex("50515253c0c1c2c3c4c5c6c7");
+        let vectors = [
+            (
+                Cipher::ChaCha20Poly1305,
+                "070000004041424344454647",
+                "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b\
+                 1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                 3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691",
+            ),
+            (
+                Cipher::Aes256Gcm,
+                "070000004041424344454647",
+                "7c0df61c33f0c998dbe516797c7908dcdfd52f1f10ec0b5ae2e4de9942ced85eeec8b953385268b2f9fb8414d169f7f4\
+                 b24a93c0b5d29afbe1b442dc4077e8f48f22ad0a409f977cac9fcaf05be1ba04040f8b04667362fff434a71b9f2d09a3\
+                 e14283372d3c5946111486e8c1a155a28965029f36e34e07302fbf985597bca58e5f",
+            ),
+            (
+                Cipher::XChaCha20Poly1305,
+                "404142434445464748494a4b4c4d4e4f5051525354555657",
+                "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb731c7f1b0b4aa6440bf3a82f4eda7e39\
+                 ae64c6708c54c216cb96b72e1213b4522f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+                 21f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780acf49",
+            ),
+        ];
+
+        for (cipher, nonce, ciphertext) in vectors {
+            let nonce = unhex(nonce);
+            let encrypted = encrypt_with_nonce(VECTOR_PLAINTEXT, &key, cipher, &aad, &nonce).unwrap();
+
+            // The stored format is the nonce followed by ciphertext and tag
+            assert_eq!(encrypted[..nonce.len()], nonce[..], "{}", cipher);
+            assert_eq!(encrypted[nonce.len()..], unhex(ciphertext)[..], "{}", cipher);
+            assert_eq!(decrypt(&encrypted, &key, cipher, &aad).unwrap(), VECTOR_PLAINTEXT, "{}", cipher);
+        }
+
+        assert!(encrypt_with_nonce(b"data", &key, Cipher::XChaCha20Poly1305, &[], &[0u8; 12]).is_err());
+    }
+
+    #[test]
+    fn test_envelope_header() {
+        let key = [4u8; 32];
+        for cipher in [Cipher::XChaCha20Poly1305, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let sealed = encrypt_envelope(b"vault", &key, cipher, b"aad").unwrap();
+            assert_eq!(sealed[..ENVELOPE_HEADER_LEN], [b'A', b'L', b'S', b'R', ENVELOPE_VERSION, cipher.id()]);
+            assert_eq!(sealed.len(), ENVELOPE_HEADER_LEN + cipher.nonce_len() + b"vault".len() + 16);
+
+            // The header's cipher wins over the one passed in
+            assert_eq!(decrypt_envelope(&sealed, &key, Cipher::Aes256Gcm, b"aad").unwrap(), b"vault", "{}", cipher);
+            assert!(decrypt_envelope(&sealed, &key, cipher, b"other").is_err(), "{}", cipher);
+
+            // Headerless data from before envelopes still opens
+            let legacy = encrypt(b"vault", &key, cipher, b"aad").unwrap();
+            assert_eq!(decrypt_envelope(&legacy, &key, cipher, b"aad").unwrap(), b"vault", "{}", cipher);
+        }
+    }
+
+    #[test]
+    fn test_envelope_header_is_authenticated() {
+        let key = [4u8; 32];
+        let sealed = encrypt_envelope(b"vault", &key, Cipher::ChaCha20Poly1305, &[]).unwrap();
+
+        let mut other_cipher = sealed.clone();
+        other_cipher[ENVELOPE_HEADER_LEN - 1] = Cipher::Aes256Gcm.id();
+        assert!(decrypt_envelope(&other_cipher, &key, Cipher::ChaCha20Poly1305, &[]).is_err());
+
+        let mut unknown_cipher = sealed.clone();
+        unknown_cipher[ENVELOPE_HEADER_LEN - 1] = 0xff;
+        assert!(decrypt_envelope(&unknown_cipher, &key, Cipher::ChaCha20Poly1305, &[]).is_err());
+
+        let mut newer = sealed;
+        newer[ENVELOPE_HEADER_LEN - 2] = ENVELOPE_VERSION + 1;
+        let err = decrypt_envelope(&newer, &key, Cipher::ChaCha20Poly1305, &[]).unwrap_err();
+        assert!(err.to_string().contains("newer"), "{}", err);
+    }
+
+    /// Changing any of these keys locks users out of existing vaults
+    #[test]
+    fn test_key_derivation_known_answers() {
+        let salt = [3u8; DEFAULT_SALT_SIZE];
+        let vectors = [
+            (
+                KdfProfile::Interactive.params(KdfAlgorithm::Argon2id),
+                "1d14572f3967e28957497c1617e7ca06112cb37a4097d7a3f20db1b169477684",
+            ),
+            (test_params(KdfAlgorithm::Scrypt), "30b87a20aedbfa68ab28b96d6ceab4023d110805fe1ae3d7b7901283330ce32c"),
+            (test_params(KdfAlgorithm::Pbkdf2Sha256), "8ab4be7c59ccf733fbd68b0825cc2b6126f9962f2780db34708e0b9d65389d5f"),
+        ];
+
+        for (params, key) in vectors {
+            assert_eq!(derive_key("password", &salt, &params).unwrap()[..], unhex(key)[..], "{}", params);
+        }
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"hunter22", b"hunter22"));
+        assert!(!ct_eq(b"hunter22", b"hunter23"));
+        assert!(!ct_eq(b"hunter22", b"hunter2"));
+        assert!(ct_eq(b"", b""));
     }
 
     #[test]
     fn test_password_hashing() {
         let password = "super_secret_password";
-        let hash = hash_password(password).unwrap();
+        let hash = hash_password(password, &KdfParams::default()).unwrap();
         
         assert!(verify_password(password, &hash).unwrap());
         assert!(!verify_password("wrong_password", &hash).unwrap());
     }
+
+    #[test]
+    fn test_interactive_profile_matches_argon2_defaults() {
+        // Vaults created before profiles existed used Argon2::default()
+        let salt = [7u8; DEFAULT_SALT_SIZE];
+        let mut legacy = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(b"password", &salt, &mut legacy)
+            .unwrap();
+
+        let key = derive_key("password", &salt, &KdfProfile::Interactive.params(KdfAlgorithm::Argon2id)).unwrap();
+        assert_eq!(key, legacy);
+    }
+
+    #[test]
+    fn test_kdf_limits() {
+        for algorithm in [KdfAlgorithm::Argon2id, KdfAlgorithm::Scrypt, KdfAlgorithm::Pbkdf2Sha256] {
+            for profile in [KdfProfile::Interactive, KdfProfile::Moderate, KdfProfile::Paranoid] {
+                profile.params(algorithm).check_limits().unwrap();
+            }
+        }
+        let paranoid = KdfProfile::Paranoid.params(KdfAlgorithm::Argon2id);
+        KdfParams { memory_kib: CALIBRATION_MAX_MEMORY_KIB, ..paranoid }.check_limits().unwrap();
+        assert!(KdfParams { memory_kib: u32::MAX, ..paranoid }.check_limits().is_err());
+        assert!(KdfParams { iterations: u32::MAX, ..paranoid }.check_limits().is_err());
+    }
+
+    /// Cheap parameters so the round-trip tests stay fast in debug builds
+    fn test_params(algorithm: KdfAlgorithm) -> KdfParams {
+        let iterations = match algorithm {
+            KdfAlgorithm::Pbkdf2Sha256 => 1_000,
+            _ => 1,
+        };
+        KdfParams {
+            algorithm,
+            memory_kib: 1024,
+            iterations,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_each_algorithm_round_trips() {
+        let salt = [3u8; DEFAULT_SALT_SIZE];
+        let algorithms = [KdfAlgorithm::Argon2id, KdfAlgorithm::Scrypt, KdfAlgorithm::Pbkdf2Sha256];
+
+        let mut keys = Vec::new();
+        for algorithm in algorithms {
+            let params = test_params(algorithm);
+
+            let key = derive_key("password", &salt, &params).unwrap();
+            assert_eq!(key, derive_key("password", &salt, &params).unwrap(), "{}", algorithm);
+            assert_ne!(key, derive_key("passw0rd", &salt, &params).unwrap(), "{}", algorithm);
+            keys.push(key);
+
+            let hash = hash_password("password", &params).unwrap();
+            assert!(verify_password("password", &hash).unwrap(), "{}", algorithm);
+            assert!(!verify_password("passw0rd", &hash).unwrap(), "{}", algorithm);
+
+            let data = b"round trip";
+            let encrypted = encrypt(data, &key, Cipher::default(), &[]).unwrap();
+            assert_eq!(decrypt(&encrypted, &key, Cipher::default(), &[]).unwrap(), data);
+        }
+
+        keys.dedup();
+        assert_eq!(keys.len(), algorithms.len());
+    }
+
+    #[test]
+    fn test_calibration_targets_memory_cost() {
+        // A machine where each MiB costs a millisecond
+        let per_mib = |params: &KdfParams| Ok(Duration::from_millis(u64::from(params.memory_kib / 1024)));
+
+        let params = calibrate_with(Duration::from_millis(500), per_mib).unwrap();
+        assert_eq!(params.algorithm, KdfAlgorithm::Argon2id);
+        assert_eq!(params.memory_kib % 1024, 0);
+        assert!(params.memory_kib <= 500 * 1024, "{}", params);
+        assert!(params.memory_kib >= 500 * 1024 * 15 / 16, "{}", params);
+
+        // Too slow for the target, or fast enough for any memory
+        let interactive = KdfProfile::Interactive.params(KdfAlgorithm::Argon2id);
+        assert_eq!(calibrate_with(Duration::from_millis(5), per_mib).unwrap(), interactive);
+        let params = calibrate_with(Duration::from_secs(3600), per_mib).unwrap();
+        assert_eq!(params.memory_kib, CALIBRATION_MAX_MEMORY_KIB);
+    }
+
+    #[test]
+    fn test_params_without_algorithm_default_to_argon2id() {
+        let params: KdfParams =
+            serde_json::from_str(r#"{"memory_kib":19456,"iterations":2,"parallelism":1}"#).unwrap();
+        assert_eq!(params, KdfProfile::Interactive.params(KdfAlgorithm::Argon2id));
+    }
+
+    #[test]
+    fn test_passphrase_round_trip() {
+        let path = std::env::temp_dir().join(format!("aliaser-test-sealed-{}", std::process::id()));
+        let data = b"arbitrary file contents \x00\xff";
+
+        let sealed = encrypt_with_passphrase(data, "passphrase", test_params(KdfAlgorithm::Argon2id)).unwrap();
+        std::fs::write(&path, sealed).unwrap();
+        let mut sealed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decrypt_with_passphrase(&sealed, "passphrase").unwrap(), data);
+        assert!(decrypt_with_passphrase(&sealed, "wrong").is_err());
+        assert!(decrypt_with_passphrase(data, "passphrase").is_err());
+
+        // Tampering with the header fails as well
+        let last_header_byte = sealed.len() - data.len() - Cipher::default().nonce_len() - 17;
+        sealed[last_header_byte] ^= 1;
+        assert!(decrypt_with_passphrase(&sealed, "passphrase").is_err());
+    }
+
+    #[test]
+    fn test_passphrase_refuses_oversized_kdf() {
+        let kdf_params = KdfParams {
+            iterations: u32::MAX,
+            ..test_params(KdfAlgorithm::Argon2id)
+        };
+        let header = serde_json::to_vec(&SealedHeader {
+            kdf_params,
+            cipher: Cipher::default(),
+            salt: vec![0; DEFAULT_SALT_SIZE],
+        })
+        .unwrap();
+        let mut sealed = SEALED_MAGIC.to_vec();
+        sealed.push(SEALED_VERSION);
+        sealed.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        sealed.extend_from_slice(&header);
+        sealed.extend_from_slice(&[0; 64]);
+
+        let err = decrypt_with_passphrase(&sealed, "passphrase").unwrap_err();
+        assert!(err.to_string().starts_with("Key derivation parameters are beyond"));
+    }
 }
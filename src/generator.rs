@@ -0,0 +1,389 @@
+use anyhow::Result;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()_+-=[]{}|;:,.<>?";
+
+/// Characters that are easy to confuse when read or transcribed
+pub const AMBIGUOUS: &str = "Il1|O0o";
+
+const DEFAULT_LENGTH: usize = 20;
+
+/// Longest password the generator will produce
+pub const MAX_LENGTH: usize = 256;
+
+/// A group of characters a password can draw from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digits,
+    Symbols,
+}
+
+impl CharClass {
+    const ALL: [CharClass; 4] = [CharClass::Lowercase, CharClass::Uppercase, CharClass::Digits, CharClass::Symbols];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CharClass::Lowercase => "lowercase",
+            CharClass::Uppercase => "uppercase",
+            CharClass::Digits => "digits",
+            CharClass::Symbols => "symbols",
+        }
+    }
+
+    fn charset(self) -> &'static str {
+        match self {
+            CharClass::Lowercase => LOWERCASE,
+            CharClass::Uppercase => UPPERCASE,
+            CharClass::Digits => DIGITS,
+            CharClass::Symbols => SYMBOLS,
+        }
+    }
+}
+
+/// Options controlling how passwords are generated
+///
+/// The four class flags choose which characters are in the pool;
+/// `required_classes` chooses which of those must appear at least once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude_ambiguous: bool,
+    /// Classes guaranteed to appear; `None` means every enabled class
+    pub required_classes: Option<Vec<CharClass>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: DEFAULT_LENGTH,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+            required_classes: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Checks that passwords can be generated under this policy
+    pub fn validate(&self) -> Result<()> {
+        self.classes().map(|_| ())
+    }
+
+    /// Estimated strength of a password generated under this policy, in
+    /// bits: its length times log2 of the pool size
+    pub fn entropy_bits(&self) -> Result<f64> {
+        let pool: usize = self.classes()?.iter().map(|(chars, _)| chars.len()).sum();
+        Ok(self.length as f64 * (pool as f64).log2())
+    }
+
+    /// Whether `class` is in the pool
+    pub fn is_enabled(&self, class: CharClass) -> bool {
+        match class {
+            CharClass::Lowercase => self.lowercase,
+            CharClass::Uppercase => self.uppercase,
+            CharClass::Digits => self.digits,
+            CharClass::Symbols => self.symbols,
+        }
+    }
+
+    /// The classes every generated password contains
+    pub fn required(&self) -> Vec<CharClass> {
+        match &self.required_classes {
+            Some(required) => CharClass::ALL.into_iter().filter(|class| required.contains(class)).collect(),
+            None => CharClass::ALL.into_iter().filter(|class| self.is_enabled(*class)).collect(),
+        }
+    }
+
+    /// Returns the character set of each enabled class, with ambiguous
+    /// characters removed if requested, paired with whether it is required
+    fn classes(&self) -> Result<Vec<(Vec<char>, bool)>> {
+        if self.length == 0 {
+            anyhow::bail!("Password length must be at least 1");
+        }
+        if self.length > MAX_LENGTH {
+            anyhow::bail!("Password length {} exceeds the maximum of {}", self.length, MAX_LENGTH);
+        }
+
+        let required = self.required();
+        if let Some(class) = required.iter().find(|class| !self.is_enabled(**class)) {
+            anyhow::bail!("Character class '{}' is required but not enabled", class.name());
+        }
+
+        let mut classes = Vec::new();
+        for class in CharClass::ALL.into_iter().filter(|class| self.is_enabled(*class)) {
+            let chars: Vec<char> = class
+                .charset()
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect();
+
+            if chars.is_empty() {
+                anyhow::bail!("Character class '{}' is empty after excluding ambiguous characters", class.name());
+            }
+            classes.push((chars, required.contains(&class)));
+        }
+
+        if classes.is_empty() {
+            anyhow::bail!("Password policy must enable at least one character class");
+        }
+        if self.length < required.len() {
+            anyhow::bail!(
+                "Password length {} is too short to include all {} required character classes",
+                self.length,
+                required.len()
+            );
+        }
+
+        Ok(classes)
+    }
+}
+
+/// Generates a password drawn from every enabled class that contains at
+/// least one character from every required class
+///
+/// Characters are drawn from the OS CSPRNG. `gen_range` samples uniformly
+/// using rejection, so there is no modulo bias towards the start of a charset.
+pub fn generate(policy: &PasswordPolicy) -> Result<String> {
+    let classes = policy.classes()?;
+    let pool: Vec<char> = classes.iter().flat_map(|(chars, _)| chars).copied().collect();
+    let mut rng = OsRng;
+
+    let mut password: Vec<char> = classes
+        .iter()
+        .filter(|(_, required)| *required)
+        .map(|(chars, _)| chars[rng.gen_range(0..chars.len())])
+        .collect();
+    while password.len() < policy.length {
+        password.push(pool[rng.gen_range(0..pool.len())]);
+    }
+    password.shuffle(&mut rng);
+
+    Ok(password.into_iter().collect())
+}
+
+/// Generates `count` passwords under `policy`, no two alike
+///
+/// Gives up once duplicates keep coming, which means the policy allows too
+/// few distinct passwords for `count`.
+pub fn generate_batch(policy: &PasswordPolicy, count: usize) -> Result<Vec<String>> {
+    let mut passwords: Vec<String> = Vec::with_capacity(count);
+    let mut duplicates_left = count * 10 + 100;
+
+    while passwords.len() < count {
+        let mut password = generate(policy)?;
+        if !passwords.contains(&password) {
+            passwords.push(password);
+            continue;
+        }
+
+        password.zeroize();
+        duplicates_left -= 1;
+        if duplicates_left == 0 {
+            passwords.zeroize();
+            anyhow::bail!(
+                "The password policy allows too few distinct passwords for {}; use a longer length or more character classes",
+                count
+            );
+        }
+    }
+
+    Ok(passwords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_includes_every_class() {
+        let password = generate(&PasswordPolicy::default()).unwrap();
+
+        assert_eq!(password.chars().count(), DEFAULT_LENGTH);
+        assert!(password.chars().any(|c| LOWERCASE.contains(c)));
+        assert!(password.chars().any(|c| UPPERCASE.contains(c)));
+        assert!(password.chars().any(|c| DIGITS.contains(c)));
+        assert!(password.chars().any(|c| SYMBOLS.contains(c)));
+    }
+
+    #[test]
+    fn test_exclude_ambiguous() {
+        let policy = PasswordPolicy {
+            exclude_ambiguous: true,
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            let password = generate(&policy).unwrap();
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_class_distribution_is_uniform() {
+        const SAMPLES: usize = 2000;
+        let policy = PasswordPolicy::default();
+        let charsets = [LOWERCASE, UPPERCASE, DIGITS, SYMBOLS];
+        let pool_size: usize = charsets.iter().map(|c| c.len()).sum();
+
+        let mut counts = [0usize; 4];
+        for _ in 0..SAMPLES {
+            for c in generate(&policy).unwrap().chars() {
+                let class = charsets.iter().position(|set| set.contains(c)).unwrap();
+                counts[class] += 1;
+            }
+        }
+
+        // One guaranteed character per class, the rest drawn from the whole pool
+        let free = (policy.length - charsets.len()) as f64;
+        for (count, charset) in counts.iter().zip(charsets) {
+            let expected = SAMPLES as f64 * (1.0 + free * charset.len() as f64 / pool_size as f64);
+            let deviation = (*count as f64 - expected).abs() / expected;
+            assert!(
+                deviation < 0.05,
+                "class {:?} drawn {} times, expected ~{:.0}",
+                charset,
+                count,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_is_unique() {
+        let passwords = generate_batch(&PasswordPolicy::default(), 200).unwrap();
+        assert_eq!(passwords.len(), 200);
+        for (i, password) in passwords.iter().enumerate() {
+            assert!(!passwords[..i].contains(password));
+        }
+
+        // Ten possible passwords: all of them, but not an eleventh
+        let digit = PasswordPolicy {
+            length: 1,
+            lowercase: false,
+            uppercase: false,
+            symbols: false,
+            ..Default::default()
+        };
+        let mut passwords = generate_batch(&digit, 10).unwrap();
+        passwords.sort();
+        assert_eq!(passwords.concat(), DIGITS);
+        assert!(generate_batch(&digit, 11).is_err());
+    }
+
+    #[test]
+    fn test_no_classes_is_an_error() {
+        let policy = PasswordPolicy {
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..Default::default()
+        };
+
+        assert!(generate(&policy).is_err());
+    }
+
+    #[test]
+    fn test_alphanumeric_policy() {
+        let policy = PasswordPolicy {
+            length: 8,
+            symbols: false,
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            let password = generate(&policy).unwrap();
+            assert!(!password.chars().any(|c| SYMBOLS.contains(c)));
+            assert!(password.chars().any(|c| LOWERCASE.contains(c)));
+            assert!(password.chars().any(|c| UPPERCASE.contains(c)));
+            assert!(password.chars().any(|c| DIGITS.contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_required_classes() {
+        // Symbols plus digits, where only symbols are guaranteed
+        let symbol_heavy = PasswordPolicy {
+            length: 3,
+            lowercase: false,
+            uppercase: false,
+            required_classes: Some(vec![CharClass::Symbols]),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let password = generate(&symbol_heavy).unwrap();
+            assert!(password.chars().all(|c| SYMBOLS.contains(c) || DIGITS.contains(c)));
+            assert!(password.chars().any(|c| SYMBOLS.contains(c)));
+        }
+
+        // With nothing required, a length shorter than the class count is fine
+        let unconstrained = PasswordPolicy {
+            length: 2,
+            required_classes: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert_eq!(generate(&unconstrained).unwrap().chars().count(), 2);
+
+        let not_enabled = PasswordPolicy {
+            symbols: false,
+            required_classes: Some(vec![CharClass::Symbols]),
+            ..Default::default()
+        };
+        let err = generate(&not_enabled).unwrap_err();
+        assert!(err.to_string().contains("not enabled"));
+    }
+
+    #[test]
+    fn test_length_bounds() {
+        let only_lowercase = PasswordPolicy {
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..Default::default()
+        };
+
+        let err = generate(&PasswordPolicy { length: 0, ..only_lowercase.clone() }).unwrap_err();
+        assert!(err.to_string().contains("at least 1"));
+
+        let password = generate(&PasswordPolicy { length: 1, ..only_lowercase.clone() }).unwrap();
+        assert_eq!(password.chars().count(), 1);
+
+        let password = generate(&PasswordPolicy { length: MAX_LENGTH, ..Default::default() }).unwrap();
+        assert_eq!(password.chars().count(), MAX_LENGTH);
+
+        let err = generate(&PasswordPolicy { length: MAX_LENGTH + 1, ..Default::default() }).unwrap_err();
+        assert!(err.to_string().contains("maximum"));
+    }
+
+    #[test]
+    fn test_entropy_bits() {
+        let only_lowercase = PasswordPolicy {
+            length: 10,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..Default::default()
+        };
+        let bits = only_lowercase.entropy_bits().unwrap();
+        assert!((bits - 10.0 * 26f64.log2()).abs() < 1e-9);
+        assert!(PasswordPolicy::default().entropy_bits().unwrap() > 120.0);
+    }
+}
@@ -1,6 +1,16 @@
-use chrono::{DateTime, Utc};
+use crate::crypto::ct_eq;
+use crate::templates::Template;
+use crate::totp;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Largest file that can be attached to an identity
+pub const MAX_ATTACHMENT_SIZE: usize = 64 * 1024;
+
+/// Most previous passwords kept per identity
+pub const MAX_PASSWORD_HISTORY: usize = 5;
 
 /// Represents a complete identity for a service
 #[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
@@ -13,19 +23,102 @@ pub struct Identity {
     pub credentials: Credentials,
     pub personal_info: Option<PersonalInfo>,
     pub notes: Option<String>,
+    /// Remind the user to change the password this many days after it was set
+    #[serde(default)]
+    pub rotate_after_days: Option<u32>,
+    /// When the password last changed; older vaults fall back to `created_at`
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub last_rotated: Option<DateTime<Utc>>,
+    /// When the password was last revealed; only recorded when access tracking is on
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub last_accessed: Option<DateTime<Utc>>,
+    /// Small files such as SSH keys or certificates, encrypted with the vault
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Passwords replaced by updates, newest first, at most [`MAX_PASSWORD_HISTORY`]
+    #[serde(default)]
+    pub password_history: Vec<PreviousPassword>,
+    /// Second-factor one-time password generator, if set up
+    #[serde(default)]
+    pub otp: Option<OtpSecret>,
+    /// Template the identity was added with, which says what kind of entry it is
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub template: Option<Template>,
+    /// Answers to the service's account recovery questions, which work like
+    /// extra passwords
+    #[serde(default)]
+    pub security_questions: Vec<SecurityQuestion>,
+    /// Listed ahead of everything else
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub favorite: bool,
+    /// Color `list` and `get` show the service name in, by name or `#rrggbb`
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Emoji or short glyph shown before the service name
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+/// A security question and the answer given to the service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SecurityQuestion {
+    pub question: String,
+    pub answer: String,
+}
+
+/// Shared secret and state for generating one-time passwords
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct OtpSecret {
+    /// Raw secret, decoded from the base32 form services hand out
+    pub secret: Vec<u8>,
+    #[zeroize(skip)]
+    pub kind: OtpKind,
+    /// Code length; 6 almost everywhere
+    pub digits: u32,
+    /// Issuer authenticator apps show, if not the service name
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Account authenticator apps show, if not the username
+    #[serde(default)]
+    pub account: Option<String>,
+}
+
+/// Whether codes follow the clock or a counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    /// Time-based (RFC 6238), 30-second steps
+    Totp,
+    /// Counter-based (RFC 4226); `counter` is the value for the next code
+    Hotp { counter: u64 },
+}
+
+/// A password the identity used before, kept in case a change didn't take
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct PreviousPassword {
+    pub password: String,
+    #[zeroize(skip)]
+    pub replaced_at: DateTime<Utc>,
 }
 
 /// Credentials for authentication
-#[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct Credentials {
     pub username: String,
     pub password: String,
     pub email: Option<String>,
     pub alias: Option<String>,
+    /// Unused 2FA recovery/backup codes issued by the service
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
 }
 
 /// Personal information for an identity
-#[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct PersonalInfo {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
@@ -35,13 +128,48 @@ pub struct PersonalInfo {
     pub custom_fields: Vec<CustomField>,
 }
 
+/// A file stored with an identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct Attachment {
+    pub name: String,
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
 /// Custom key-value field
-#[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct CustomField {
     pub key: String,
     pub value: String,
 }
 
+/// Individual changes to an identity, leaving everything not mentioned as it was
+#[derive(Debug, Default, Zeroize, ZeroizeOnDrop)]
+pub struct IdentityEdit {
+    pub username: Option<String>,
+    /// An empty email clears it
+    pub email: Option<String>,
+    /// Custom fields to add or change, matched case-insensitively
+    pub set_fields: Vec<(String, String)>,
+    /// Custom fields to remove, matched case-insensitively
+    pub remove_fields: Vec<String>,
+    /// An empty color clears it
+    pub color: Option<String>,
+    /// An empty icon clears it
+    pub icon: Option<String>,
+}
+
+impl IdentityEdit {
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none()
+            && self.email.is_none()
+            && self.set_fields.is_empty()
+            && self.remove_fields.is_empty()
+            && self.color.is_none()
+            && self.icon.is_none()
+    }
+}
+
 impl Identity {
     pub fn new(service: String, credentials: Credentials) -> Self {
         let now = Utc::now();
@@ -52,12 +180,174 @@ impl Identity {
             credentials,
             personal_info: None,
             notes: None,
+            rotate_after_days: None,
+            last_rotated: Some(now),
+            last_accessed: None,
+            attachments: Vec::new(),
+            password_history: Vec::new(),
+            otp: None,
+            template: None,
+            security_questions: Vec::new(),
+            favorite: false,
+            color: None,
+            icon: None,
         }
     }
 
     pub fn update_timestamp(&mut self) {
         self.updated_at = Utc::now();
     }
+
+    /// Whether the identity has neither a username nor a password, as
+    /// happens with half-filled entries from imports; entries whose template
+    /// has no login, such as cards and notes, never count
+    pub fn is_incomplete(&self) -> bool {
+        self.template.is_none_or(Template::has_login)
+            && self.credentials.username.trim().is_empty()
+            && self.credentials.password.is_empty()
+    }
+
+    /// When the password was last changed
+    pub fn last_rotated(&self) -> DateTime<Utc> {
+        self.last_rotated.unwrap_or(self.created_at)
+    }
+
+    /// When the password is due to be rotated, if a rotation period is set
+    pub fn rotation_due(&self) -> Option<DateTime<Utc>> {
+        self.rotate_after_days
+            .map(|days| self.last_rotated() + Duration::days(days.into()))
+    }
+
+    /// Whether the rotation due date has passed
+    pub fn rotation_overdue(&self) -> bool {
+        self.rotation_due().is_some_and(|due| due <= Utc::now())
+    }
+
+    /// The `otpauth://` URI that sets up the identity's one-time passwords in
+    /// an authenticator app, labelled with the stored issuer and account or
+    /// else the service and username
+    pub fn otpauth_uri(&self) -> Option<String> {
+        let otp = self.otp.as_ref()?;
+        let issuer = otp.issuer.as_deref().unwrap_or(&self.service);
+        let account = match otp.account.as_deref() {
+            Some(account) => account,
+            None if !self.credentials.username.is_empty() => &self.credentials.username,
+            None => &self.service,
+        };
+        let counter = match otp.kind {
+            OtpKind::Totp => None,
+            OtpKind::Hotp { counter } => Some(counter),
+        };
+        Some(totp::otpauth_uri(issuer, account, &otp.secret, otp.digits, counter))
+    }
+
+    /// Records `password` as replaced just now, dropping the oldest entries
+    /// beyond [`MAX_PASSWORD_HISTORY`]
+    pub fn remember_password(&mut self, password: String) {
+        self.password_history.insert(
+            0,
+            PreviousPassword {
+                password,
+                replaced_at: Utc::now(),
+            },
+        );
+        self.password_history.truncate(MAX_PASSWORD_HISTORY);
+    }
+
+    /// Drops what only this vault should know: previous passwords and when
+    /// the identity was last accessed
+    pub fn clear_history(&mut self) {
+        self.password_history.clear();
+        self.last_accessed = None;
+    }
+
+    /// Applies `edit`, creating personal info only if a field is set; returns
+    /// the fields it was asked to remove that didn't exist
+    pub fn apply_edit(&mut self, edit: &IdentityEdit) -> Vec<String> {
+        if let Some(username) = &edit.username {
+            self.credentials.username = username.clone();
+        }
+        if let Some(email) = &edit.email {
+            self.credentials.email = Some(email.clone()).filter(|email| !email.is_empty());
+        }
+        if let Some(color) = &edit.color {
+            self.color = Some(color.clone()).filter(|color| !color.is_empty());
+        }
+        if let Some(icon) = &edit.icon {
+            self.icon = Some(icon.clone()).filter(|icon| !icon.is_empty());
+        }
+
+        if !edit.set_fields.is_empty() {
+            let info = self.personal_info.get_or_insert_with(PersonalInfo::new);
+            for (key, value) in &edit.set_fields {
+                info.upsert_custom_field(key.clone(), value.clone());
+            }
+        }
+
+        let mut missing = Vec::new();
+        for key in &edit.remove_fields {
+            let removed = self.personal_info.as_mut().is_some_and(|info| info.remove_custom_field(key));
+            if !removed {
+                missing.push(key.clone());
+            }
+        }
+        missing
+    }
+
+    /// Names the fields that differ between two versions of an identity.
+    /// Timestamps (`created_at`, `updated_at`, `last_rotated`,
+    /// `last_accessed`) are ignored, so the list is empty exactly when
+    /// [`Identity::content_hash`] matches.
+    pub fn changed_fields(&self, other: &Identity) -> Vec<&'static str> {
+        let (ours, theirs) = (&self.credentials, &other.credentials);
+        let fields = [
+            ("service", self.service != other.service),
+            ("username", ours.username != theirs.username),
+            ("password", !ct_eq(ours.password.as_bytes(), theirs.password.as_bytes())),
+            ("email", ours.email != theirs.email),
+            ("alias", ours.alias != theirs.alias),
+            ("recovery codes", ours.recovery_codes != theirs.recovery_codes),
+            ("personal info", self.personal_info != other.personal_info),
+            ("notes", self.notes != other.notes),
+            ("rotation period", self.rotate_after_days != other.rotate_after_days),
+            ("attachments", self.attachments != other.attachments),
+            ("password history", self.password_history != other.password_history),
+            ("one-time password", self.otp != other.otp),
+            ("template", self.template != other.template),
+            ("security questions", self.security_questions != other.security_questions),
+            ("favorite", self.favorite != other.favorite),
+            ("color", self.color != other.color),
+            ("icon", self.icon != other.icon),
+        ];
+        fields.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect()
+    }
+
+    /// SHA-256 of everything [`Identity::changed_fields`] compares, for
+    /// telling versions apart without holding both. It covers the secrets,
+    /// so it must never be stored or shown.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut content = self.clone();
+        content.created_at = DateTime::UNIX_EPOCH;
+        content.updated_at = DateTime::UNIX_EPOCH;
+        content.last_rotated = None;
+        content.last_accessed = None;
+        let json = Zeroizing::new(serde_json::to_vec(&content).expect("identities always serialize"));
+        Sha256::digest(json.as_slice()).into()
+    }
+}
+
+impl Attachment {
+    /// Guesses a MIME type from the file name's extension
+    pub fn guess_mime(name: &str) -> &'static str {
+        let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pem" | "crt" | "cer" | "key") => "application/x-pem-file",
+            Some("pub" | "txt" | "asc") => "text/plain",
+            Some("json") => "application/json",
+            Some("p12" | "pfx") => "application/x-pkcs12",
+            _ => "application/octet-stream",
+        }
+    }
 }
 
 impl PersonalInfo {
@@ -72,8 +362,26 @@ impl PersonalInfo {
         }
     }
 
-    pub fn add_custom_field(&mut self, key: String, value: String) {
-        self.custom_fields.push(CustomField { key, value });
+    /// Sets a custom field, replacing any existing field whose key matches
+    /// case-insensitively instead of adding a duplicate
+    pub fn upsert_custom_field(&mut self, key: String, value: String) {
+        let key = key.trim().to_string();
+        match self.custom_fields.iter_mut().find(|f| f.key.eq_ignore_ascii_case(&key)) {
+            Some(field) => {
+                field.key = key;
+                field.value = value;
+            }
+            None => self.custom_fields.push(CustomField { key, value }),
+        }
+    }
+
+    /// Removes the custom field whose key matches case-insensitively,
+    /// returning whether there was one
+    pub fn remove_custom_field(&mut self, key: &str) -> bool {
+        let key = key.trim();
+        let before = self.custom_fields.len();
+        self.custom_fields.retain(|f| !f.key.eq_ignore_ascii_case(key));
+        self.custom_fields.len() != before
     }
 }
 
@@ -82,3 +390,181 @@ impl Default for PersonalInfo {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_credentials() -> Credentials {
+        Credentials {
+            username: "alice".to_string(),
+            password: "hunter22".to_string(),
+            email: None,
+            alias: None,
+            recovery_codes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_upsert_custom_field_replaces_case_insensitively() {
+        let mut info = PersonalInfo::new();
+        info.upsert_custom_field("PIN".to_string(), "1234".to_string());
+        info.upsert_custom_field(" pin ".to_string(), "5678".to_string());
+        info.upsert_custom_field("Member ID".to_string(), "42".to_string());
+
+        assert_eq!(info.custom_fields.len(), 2);
+        assert_eq!(info.custom_fields[0].key, "pin");
+        assert_eq!(info.custom_fields[0].value, "5678");
+        assert_eq!(info.custom_fields[1].key, "Member ID");
+    }
+
+    #[test]
+    fn test_remove_custom_field() {
+        let mut info = PersonalInfo::new();
+        info.upsert_custom_field("PIN".to_string(), "1234".to_string());
+        info.upsert_custom_field("Member ID".to_string(), "42".to_string());
+
+        assert!(info.remove_custom_field("pin"));
+        assert!(!info.remove_custom_field("pin"));
+        assert_eq!(info.custom_fields.len(), 1);
+        assert_eq!(info.custom_fields[0].key, "Member ID");
+    }
+
+    #[test]
+    fn test_templates_without_login_are_not_incomplete() {
+        let empty = Credentials {
+            username: String::new(),
+            password: String::new(),
+            email: None,
+            alias: None,
+            recovery_codes: Vec::new(),
+        };
+        let mut identity = Identity::new("visa".to_string(), empty);
+        assert!(identity.is_incomplete());
+
+        identity.template = Some(Template::CreditCard);
+        assert!(!identity.is_incomplete());
+        identity.template = Some(Template::Bank);
+        assert!(identity.is_incomplete());
+    }
+
+    #[test]
+    fn test_otpauth_uri_labels() {
+        let credentials = test_credentials();
+        let mut identity = Identity::new("github-work".to_string(), credentials);
+        assert!(identity.otpauth_uri().is_none());
+
+        identity.otp = Some(OtpSecret {
+            secret: b"foo".to_vec(),
+            kind: OtpKind::Totp,
+            digits: 6,
+            issuer: None,
+            account: None,
+        });
+        assert!(identity.otpauth_uri().unwrap().starts_with("otpauth://totp/github-work:alice?"));
+
+        let otp = identity.otp.as_mut().unwrap();
+        otp.issuer = Some("GitHub".to_string());
+        otp.account = Some("alice@example.com".to_string());
+        let uri = identity.otpauth_uri().unwrap();
+        assert!(uri.starts_with("otpauth://totp/GitHub:alice%40example.com?"), "{}", uri);
+        assert!(uri.contains("&issuer=GitHub&"), "{}", uri);
+    }
+
+    #[test]
+    fn test_edit_email_keeps_custom_fields() {
+        let mut credentials = test_credentials();
+        credentials.email = Some("old@example.com".to_string());
+        let mut identity = Identity::new("bank".to_string(), credentials);
+        let mut info = PersonalInfo::new();
+        info.first_name = Some("Alice".to_string());
+        info.upsert_custom_field("PIN".to_string(), "1234".to_string());
+        info.upsert_custom_field("Member ID".to_string(), "42".to_string());
+        identity.personal_info = Some(info);
+
+        let mut edit = IdentityEdit::default();
+        edit.email = Some("new@example.com".to_string());
+        assert!(identity.apply_edit(&edit).is_empty());
+        assert_eq!(identity.credentials.email.as_deref(), Some("new@example.com"));
+        assert_eq!(identity.credentials.username, "alice");
+        let info = identity.personal_info.as_ref().unwrap();
+        assert_eq!(info.first_name.as_deref(), Some("Alice"));
+        assert_eq!(info.custom_fields.len(), 2);
+
+        let mut edit = IdentityEdit::default();
+        edit.email = Some(String::new());
+        edit.set_fields = vec![("pin".to_string(), "5678".to_string())];
+        edit.remove_fields = vec!["member id".to_string(), "Nope".to_string()];
+        assert_eq!(identity.apply_edit(&edit), vec!["Nope".to_string()]);
+        assert!(identity.credentials.email.is_none());
+        let info = identity.personal_info.as_ref().unwrap();
+        assert_eq!(info.custom_fields.len(), 1);
+        assert_eq!(info.custom_fields[0].key, "pin");
+        assert_eq!(info.custom_fields[0].value, "5678");
+    }
+
+    #[test]
+    fn test_edit_sets_and_clears_color_and_icon() {
+        let credentials = test_credentials();
+        let mut identity = Identity::new("bank".to_string(), credentials);
+
+        let mut edit = IdentityEdit::default();
+        edit.color = Some("bright-blue".to_string());
+        edit.icon = Some("🏦".to_string());
+        assert!(identity.apply_edit(&edit).is_empty());
+        assert_eq!(identity.color.as_deref(), Some("bright-blue"));
+        assert_eq!(identity.icon.as_deref(), Some("🏦"));
+
+        let mut edit = IdentityEdit::default();
+        edit.icon = Some(String::new());
+        identity.apply_edit(&edit);
+        assert_eq!(identity.color.as_deref(), Some("bright-blue"));
+        assert!(identity.icon.is_none());
+
+        // Identities saved before these existed have neither
+        let mut json = serde_json::to_value(&identity).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("color");
+        fields.remove("icon");
+        let loaded: Identity = serde_json::from_value(json).unwrap();
+        assert!(loaded.color.is_none() && loaded.icon.is_none());
+    }
+
+    #[test]
+    fn test_changed_fields_ignore_timestamps() {
+        let credentials = test_credentials();
+        let original = Identity::new("github".to_string(), credentials);
+
+        let mut touched = original.clone();
+        touched.updated_at += Duration::days(1);
+        touched.last_rotated = Some(touched.updated_at);
+        touched.last_accessed = Some(touched.updated_at);
+        assert!(original.changed_fields(&touched).is_empty());
+        assert_eq!(original.content_hash(), touched.content_hash());
+
+        let mut changed = touched.clone();
+        changed.credentials.password = "correct horse".to_string();
+        changed.notes = Some("moved to SSO".to_string());
+        let mut info = PersonalInfo::new();
+        info.upsert_custom_field("PIN".to_string(), "1234".to_string());
+        changed.personal_info = Some(info);
+        assert_eq!(original.changed_fields(&changed), vec!["password", "personal info", "notes"]);
+        assert_eq!(changed.changed_fields(&original), vec!["password", "personal info", "notes"]);
+        assert_ne!(original.content_hash(), changed.content_hash());
+
+        let mut recolored = original.clone();
+        recolored.color = Some("red".to_string());
+        recolored.favorite = true;
+        assert_eq!(original.changed_fields(&recolored), vec!["favorite", "color"]);
+    }
+
+    #[test]
+    fn test_identity_without_security_questions_loads() {
+        let credentials = test_credentials();
+        let mut json = serde_json::to_value(Identity::new("github".to_string(), credentials)).unwrap();
+        json.as_object_mut().unwrap().remove("security_questions");
+
+        let identity: Identity = serde_json::from_value(json).unwrap();
+        assert!(identity.security_questions.is_empty());
+    }
+}
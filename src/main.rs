@@ -1,44 +1,230 @@
+mod audit;
 mod cli;
+mod clipboard;
+mod config;
 mod crypto;
+mod generator;
 mod identity;
+mod memlock;
+#[cfg(feature = "sync")]
+mod ssh;
 mod storage;
+#[cfg(any(feature = "sync", feature = "webdav"))]
+mod sync;
+mod templates;
+mod totp;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "webdav")]
+mod webdav;
 
 use anyhow::Result;
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, Context};
+use std::process;
+use storage::VaultError;
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {:?}", err);
+        process::exit(exit_code(&err));
+    }
+}
+
+/// Maps the first typed vault error in the chain to its documented exit
+/// status; everything else exits with 1
+fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<VaultError>())
+        .map_or(1, VaultError::exit_code)
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
-    
+    init_logging(cli.verbose);
+    // doctor reports on a broken settings file rather than failing to start
+    if let Commands::Doctor = cli.command {
+        return cli::doctor(&cli);
+    }
+    let ctx = Context::from_cli(&cli)?;
+
     match cli.command {
-        Commands::Init => {
-            cli::init()?;
+        Commands::Init {
+            kdf,
+            kdf_profile,
+            cipher,
+            salt_size,
+        } => {
+            cli::init(&ctx, kdf, kdf_profile, cipher, salt_size)?;
+        }
+        Commands::Add { force, template, color, icon } => {
+            cli::add_identity(&ctx, force, template, color, icon)?;
         }
-        Commands::Add => {
-            cli::add_identity()?;
+        Commands::List { sort, reverse, long, favorites, older_than, page } => {
+            cli::list_identities(&ctx, sort, reverse, long, favorites, older_than, &page)?;
         }
-        Commands::List => {
-            cli::list_identities()?;
+        Commands::Get {
+            service,
+            show,
+            force,
+            reveal_seconds,
+            copy,
+            field,
+        } => {
+            cli::get_identity(&ctx, &service, show, force, reveal_seconds, copy, field)?;
         }
-        Commands::Get { service } => {
-            cli::get_identity(&service)?;
+        Commands::Update { service, generate, policy, edit } => {
+            cli::update_identity(&ctx, &service, generate, &policy, &edit)?;
         }
-        Commands::Update { service } => {
-            cli::update_identity(&service)?;
+        Commands::Touch { service, rotated } => {
+            cli::touch(&ctx, &service, rotated)?;
+        }
+        Commands::Favorite { service } => {
+            cli::set_favorite(&ctx, &service, true)?;
+        }
+        Commands::Unfavorite { service } => {
+            cli::set_favorite(&ctx, &service, false)?;
         }
         Commands::Delete { service } => {
-            cli::delete_identity(&service)?;
+            cli::delete_identity(&ctx, &service)?;
+        }
+        Commands::DeleteMany { pattern } => {
+            cli::delete_many(&ctx, &pattern)?;
+        }
+        Commands::Prune { dry_run } => {
+            cli::prune(&ctx, dry_run)?;
+        }
+        Commands::Export { path, self_contained } => {
+            cli::export_data(&ctx, &path, self_contained)?;
+        }
+        Commands::ExportOne { service, path } => {
+            cli::export_one(&ctx, &service, &path)?;
+        }
+        Commands::ImportOne { path, overwrite } => {
+            cli::import_one(&ctx, &path, overwrite)?;
+        }
+        Commands::Import { path, dry_run, map } => {
+            cli::import_data(&ctx, &path, dry_run, &map)?;
+        }
+        Commands::Expiring { within_days } => {
+            cli::list_expiring(&ctx, within_days)?;
+        }
+        Commands::Log => {
+            cli::audit_log(&ctx)?;
+        }
+        Commands::AccessLog { service } => {
+            cli::access_log(&ctx, &service)?;
+        }
+        Commands::TrackAccess { enabled } => {
+            cli::track_access(&ctx, enabled)?;
+        }
+        Commands::Lockout { free_attempts, max_delay, after, minutes } => {
+            cli::lockout(&ctx, free_attempts, max_delay, after, minutes)?;
+        }
+        Commands::RecoveryCode { service, consume } => {
+            cli::recovery_code(&ctx, &service, consume)?;
+        }
+        Commands::SetOtp { service, hotp, counter, digits, issuer, account, remove } => {
+            let options = cli::OtpOptions { hotp, counter, digits, issuer, account };
+            cli::set_otp(&ctx, &service, options, remove)?;
         }
-        Commands::Export { path } => {
-            cli::export_data(&path)?;
+        Commands::Otp { service, uri } => {
+            cli::otp(&ctx, &service, uri)?;
         }
-        Commands::Import { path } => {
-            cli::import_data(&path)?;
+        Commands::Qr { service, png } => {
+            cli::qr(&ctx, &service, png.as_deref())?;
         }
-        Commands::ChangeMaster => {
-            cli::change_master_password()?;
+        Commands::Attach { service, path } => {
+            cli::attach(&ctx, &service, &path)?;
+        }
+        Commands::Attachments { service } => {
+            cli::list_attachments(&ctx, &service)?;
+        }
+        Commands::Extract { service, name, out } => {
+            cli::extract_attachment(&ctx, &service, &name, &out)?;
+        }
+        Commands::EncryptFile { input, output } => {
+            cli::encrypt_file(&ctx, &input, &output)?;
+        }
+        Commands::DecryptFile { input, output } => {
+            cli::decrypt_file(&ctx, &input, &output)?;
+        }
+        Commands::ChangeMaster { reason } => {
+            cli::change_master_password(&ctx, reason.as_deref())?;
+        }
+        Commands::Rekey { kdf_profile, salt_size } => {
+            cli::rekey(&ctx, kdf_profile, salt_size)?;
+        }
+        Commands::Convert { cipher, kdf } => {
+            cli::convert(&ctx, cipher, kdf)?;
+        }
+        Commands::Calibrate { target_ms } => {
+            cli::calibrate(&ctx, target_ms)?;
+        }
+        Commands::Policy {
+            set_generator,
+            clear_generator,
+            policy,
+        } => {
+            cli::generator_policy(&ctx, set_generator, clear_generator, &policy)?;
+        }
+        Commands::Migrate => {
+            cli::migrate(&ctx)?;
+        }
+        Commands::Info => {
+            cli::info(&ctx)?;
+        }
+        #[cfg(feature = "tui")]
+        Commands::Tui => {
+            cli::tui(&ctx)?;
+        }
+        #[cfg(any(feature = "sync", feature = "webdav"))]
+        Commands::Sync { direction, remote, force } => {
+            cli::sync(&ctx, direction, &remote, force)?;
+        }
+        Commands::Backups { keep } => {
+            cli::list_backups(&ctx, keep)?;
+        }
+        Commands::Generate { policy } => {
+            cli::generate_password(&ctx, &policy)?;
+        }
+        Commands::GenerateBatch {
+            count,
+            out,
+            prefix,
+            format,
+            policy,
+        } => {
+            cli::generate_batch(&ctx, count, &out, &prefix, format, &policy)?;
+        }
+        Commands::RestoreBackup { timestamp } => {
+            cli::restore_backup(&ctx, &timestamp)?;
+        }
+        Commands::Path => {
+            cli::show_paths(&ctx)?;
+        }
+        Commands::Doctor => unreachable!("doctor runs before settings are loaded"),
+        Commands::Completions { shell } => {
+            cli::completions(shell);
+        }
+        Commands::CompleteServices => {
+            cli::complete_services(&ctx)?;
         }
     }
     
     Ok(())
 }
+
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_module(env!("CARGO_CRATE_NAME"), level)
+        .format_timestamp(None)
+        .init();
+}
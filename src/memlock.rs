@@ -0,0 +1,114 @@
+use std::ops::Deref;
+use zeroize::Zeroize;
+
+/// A 256-bit key kept at a fixed heap address so it can stay locked in RAM
+///
+/// The key is zeroized and unlocked when dropped.
+pub struct SecretKey(Box<[u8; 32]>);
+
+impl SecretKey {
+    /// Moves `key` onto the locked heap buffer, scrubbing the caller's copy
+    pub fn new(key: &mut [u8; 32]) -> Self {
+        let mut boxed = Box::new([0u8; 32]);
+        lock(boxed.as_ptr(), boxed.len());
+        boxed.copy_from_slice(key);
+        key.zeroize();
+        Self(boxed)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock(self.0.as_ptr(), self.0.len());
+    }
+}
+
+/// Keeps a buffer locked in RAM until the guard is dropped
+///
+/// Drop the guard before the buffer is freed or reallocated, or the lock
+/// lingers on memory the allocator may hand out for something else.
+pub struct LockGuard {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl LockGuard {
+    pub fn new(buf: &[u8]) -> Self {
+        lock(buf.as_ptr(), buf.len());
+        Self {
+            ptr: buf.as_ptr(),
+            len: buf.len(),
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        unlock(self.ptr, self.len);
+    }
+}
+
+/// Pins the `len` bytes at `ptr` in RAM so they can't be paged out to swap
+///
+/// Best effort: if mlock fails (usually a low `ulimit -l`) a warning is logged
+/// once and the program carries on unprotected. Locks are per page and not
+/// counted, so unlocking one buffer may unlock a neighbour on the same page.
+/// Without the `mlock` feature this does nothing.
+#[cfg(all(unix, feature = "mlock"))]
+fn lock(ptr: *const u8, len: usize) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    if len == 0 {
+        return;
+    }
+    // SAFETY: mlock only changes paging for the range; it never reads or writes it
+    let result = unsafe { libc::mlock(ptr.cast(), len) };
+    if result != 0 && !WARNED.swap(true, Ordering::Relaxed) {
+        log::warn!(
+            "Could not lock secrets in memory ({}); they may be written to swap. \
+             Raising the memlock limit (ulimit -l) fixes this.",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(all(unix, feature = "mlock"))]
+fn unlock(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    // SAFETY: as for mlock; unlocking a range that was never locked is harmless
+    unsafe {
+        libc::munlock(ptr.cast(), len);
+    }
+}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn lock(_ptr: *const u8, _len: usize) {}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn unlock(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_key_scrubs_source() {
+        let mut raw = [7u8; 32];
+        let key = SecretKey::new(&mut raw);
+
+        assert_eq!(*key, [7u8; 32]);
+        assert_eq!(raw, [0u8; 32]);
+    }
+}
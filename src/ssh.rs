@@ -0,0 +1,243 @@
+use crate::storage::{Blob, VaultStore};
+use anyhow::{Context, Result};
+use log::debug;
+use ssh2::{CheckResult, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const DEFAULT_PORT: u16 = 22;
+
+/// Key files tried, in order, when the SSH agent has none the server accepts
+const DEFAULT_KEYS: &[&str] = &[".ssh/id_ed25519", ".ssh/id_ecdsa", ".ssh/id_rsa"];
+
+/// An SSH server directory holding a copy of the vault, written
+/// `[user@]host[:port][:dir]`
+///
+/// Without a directory the vault goes in the login directory, under the same
+/// file names as locally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshRemote {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub dir: PathBuf,
+}
+
+impl FromStr for SshRemote {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (user, rest) = match spec.split_once('@') {
+            Some((user, rest)) if !user.is_empty() => (Some(user.to_string()), rest),
+            Some(_) => return Err("empty user name before '@'".to_string()),
+            None => (None, spec),
+        };
+        let mut parts = rest.splitn(3, ':');
+        let host = parts.next().unwrap_or_default();
+        if host.is_empty() {
+            return Err("expected [user@]host[:port][:dir]".to_string());
+        }
+
+        let mut port = DEFAULT_PORT;
+        let mut dir = String::new();
+        match (parts.next(), parts.next()) {
+            (Some(second), Some(third)) => {
+                port = second.parse().map_err(|_| format!("invalid port '{}'", second))?;
+                dir = third.to_string();
+            }
+            // A lone number is a port; anything else is a directory
+            (Some(second), None) => match second.parse() {
+                Ok(number) => port = number,
+                Err(_) => dir = second.to_string(),
+            },
+            _ => {}
+        }
+
+        Ok(Self {
+            user,
+            host: host.to_string(),
+            port,
+            dir: PathBuf::from(dir),
+        })
+    }
+}
+
+impl fmt::Display for SshRemote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.host)
+    }
+}
+
+/// The vault's blobs as files in a directory on an SSH server, reached over SFTP
+///
+/// Only the encrypted vault file, config and audit log are ever stored
+/// there; nothing is decrypted on the server.
+pub struct SftpStore {
+    sftp: Sftp,
+    dir: PathBuf,
+    // Keeps the connection open as long as `sftp` is in use
+    _session: Session,
+}
+
+impl SftpStore {
+    /// Connects, checks the host key against `~/.ssh/known_hosts`, and logs
+    /// in with the SSH agent or else an unencrypted default key
+    pub fn open(remote: &SshRemote) -> Result<Self> {
+        let user = match &remote.user {
+            Some(user) => user.clone(),
+            None => std::env::var("USER")
+                .or_else(|_| std::env::var("LOGNAME"))
+                .context("No user name given; use user@host")?,
+        };
+
+        let tcp = TcpStream::connect((remote.host.as_str(), remote.port))
+            .with_context(|| format!("Failed to connect to {}:{}", remote.host, remote.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake().context("SSH handshake failed")?;
+
+        verify_host_key(&session, remote)?;
+        authenticate(&session, &user)?;
+        debug!("Logged in to {} as {}", remote.host, user);
+
+        let sftp = session.sftp().context("The server doesn't offer SFTP")?;
+        Ok(Self {
+            sftp,
+            dir: remote.dir.clone(),
+            _session: session,
+        })
+    }
+
+    fn file(&self, blob: Blob) -> io::Result<PathBuf> {
+        Ok(self.dir.join(blob.remote_name()?))
+    }
+
+    /// Renames `from` over `to`; servers speaking SFTP v3, such as OpenSSH,
+    /// refuse to rename over an existing file, so it is removed first there
+    fn replace(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let flags = Some(RenameFlags::ATOMIC | RenameFlags::OVERWRITE | RenameFlags::NATIVE);
+        if self.sftp.rename(from, to, flags).is_ok() {
+            return Ok(());
+        }
+        if self.sftp.stat(to).is_ok() {
+            self.sftp.unlink(to)?;
+        }
+        Ok(self.sftp.rename(from, to, Some(RenameFlags::empty()))?)
+    }
+}
+
+impl VaultStore for SftpStore {
+    fn read(&self, blob: Blob) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.sftp.open(&self.file(blob)?)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, blob: Blob, data: &[u8]) -> io::Result<()> {
+        // Written beside the final name and renamed into place, for every
+        // blob, since a dropped connection is likelier than a local crash
+        let path = self.file(blob)?;
+        let mut tmp_name = path.clone().into_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut file = self.sftp.open_mode(
+            &tmp_path,
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            0o600,
+            OpenType::File,
+        )?;
+        file.write_all(data)?;
+        drop(file);
+        self.replace(&tmp_path, &path)
+    }
+
+    fn exists(&self, blob: Blob) -> bool {
+        self.file(blob).is_ok_and(|path| self.sftp.stat(&path).is_ok())
+    }
+
+    fn remove(&self, blob: Blob) -> io::Result<()> {
+        Ok(self.sftp.unlink(&self.file(blob)?)?)
+    }
+
+    fn path(&self, _blob: Blob) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Refuses hosts that aren't in `~/.ssh/known_hosts` or whose key changed
+fn verify_host_key(session: &Session, remote: &SshRemote) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to get home directory")?;
+    let known_hosts_path = home.join(".ssh/known_hosts");
+
+    let mut known_hosts = session.known_hosts()?;
+    if known_hosts_path.exists() {
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .with_context(|| format!("Failed to read {}", known_hosts_path.display()))?;
+    }
+
+    let (key, _) = session.host_key().context("The server sent no host key")?;
+    match known_hosts.check_port(&remote.host, remote.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => anyhow::bail!(
+            "{} isn't in {}; connect with `ssh` once to check and add its host key",
+            remote.host,
+            known_hosts_path.display()
+        ),
+        CheckResult::Mismatch => anyhow::bail!(
+            "The host key of {} doesn't match {}; refusing to send the vault",
+            remote.host,
+            known_hosts_path.display()
+        ),
+        CheckResult::Failure => anyhow::bail!("Failed to check the host key of {}", remote.host),
+    }
+}
+
+fn authenticate(session: &Session, user: &str) -> Result<()> {
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+    if let Some(home) = dirs::home_dir() {
+        for key in DEFAULT_KEYS {
+            let key = home.join(key);
+            if key.exists() && session.userauth_pubkey_file(user, None, &key, None).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    anyhow::bail!(
+        "SSH login as {} failed; add a key to the SSH agent or use an unencrypted key in ~/.ssh",
+        user
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_remote() {
+        let remote: SshRemote = "alice@vault.example.com:2222:backups/aliaser".parse().unwrap();
+        assert_eq!(remote.user.as_deref(), Some("alice"));
+        assert_eq!(remote.host, "vault.example.com");
+        assert_eq!(remote.port, 2222);
+        assert_eq!(remote.dir, PathBuf::from("backups/aliaser"));
+
+        let remote: SshRemote = "vault.example.com:/srv/aliaser".parse().unwrap();
+        assert_eq!(remote.user, None);
+        assert_eq!(remote.port, DEFAULT_PORT);
+        assert_eq!(remote.dir, PathBuf::from("/srv/aliaser"));
+
+        let remote: SshRemote = "vault.example.com:2222".parse().unwrap();
+        assert_eq!(remote.port, 2222);
+        assert_eq!(remote.dir, PathBuf::new());
+
+        assert!("".parse::<SshRemote>().is_err());
+        assert!("@host".parse::<SshRemote>().is_err());
+        assert!("host:port:dir".parse::<SshRemote>().is_err());
+    }
+}
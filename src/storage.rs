@@ -1,254 +1,4143 @@
-use crate::crypto::{decrypt, derive_key, encrypt, generate_salt, hash_password, verify_password};
-use crate::identity::Identity;
+use crate::audit::{AuditHead, AuditLog, AuditOp};
+use crate::crypto::{
+    ct_eq, decrypt, decrypt_envelope, derive_key, encrypt, encrypt_envelope, generate_salt, has_envelope,
+    hash_password, verify_password, Cipher, KdfParams, KdfProfile, DEFAULT_SALT_SIZE, ENVELOPE_HEADER_LEN,
+};
+use crate::generator::PasswordPolicy;
+use crate::identity::{Attachment, Identity, OtpKind, OtpSecret, MAX_ATTACHMENT_SIZE};
+use crate::memlock::{LockGuard, SecretKey};
+use crate::templates::Template;
+use crate::totp;
 use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
 const VAULT_FILE: &str = ".aliaser.vault";
 const CONFIG_FILE: &str = ".aliaser.config";
+/// Name of the audit log in a remote copy; locally it sits next to the vault
+#[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+const LOG_FILE: &str = ".aliaser.log";
+const BACKUP_SUFFIX: &str = ".bak.";
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+const DEFAULT_MAX_BACKUPS: usize = 5;
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Vault failures callers may want to handle specifically
+///
+/// Vault methods return `anyhow::Result`; recover these with
+/// `err.downcast_ref::<VaultError>()`.
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("Vault not initialized. Run 'aliaser init' first.")]
+    NotInitialized,
+    #[error("Vault not unlocked")]
+    NotUnlocked,
+    #[error("Invalid master password")]
+    WrongPassword,
+    #[error(
+        "Too many failed unlock attempts; try again after {}",
+        until.format("%Y-%m-%d %H:%M:%S UTC")
+    )]
+    LockedOut { until: DateTime<Utc> },
+    #[error(
+        "The master password is correct but vault file {} appears corrupted or truncated. \
+         Restore it with `aliaser restore-backup` (see `aliaser backups`)",
+        path.display()
+    )]
+    Corrupted {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("This backup was created with a different master password; enter the password it was exported with")]
+    ExportPasswordNeeded,
+    #[error("Identity for service '{service}' not found{}", did_you_mean(suggestions))]
+    ServiceNotFound { service: String, suggestions: Vec<String> },
+}
+
+impl VaultError {
+    /// Process exit status reported for this error, so scripts can tell
+    /// failures apart; anything not listed here exits with 1
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            VaultError::NotInitialized => 3,
+            VaultError::WrongPassword | VaultError::LockedOut { .. } => 4,
+            VaultError::ServiceNotFound { .. } => 5,
+            VaultError::Corrupted { .. } => 6,
+            VaultError::NotUnlocked | VaultError::ExportPasswordNeeded => 1,
+        }
+    }
+
+    fn service_not_found(service: &str) -> Self {
+        VaultError::ServiceNotFound {
+            service: service.to_string(),
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(". Did you mean: {}?", suggestions.join(", "))
+    }
+}
 
 /// Vault metadata stored separately (unencrypted)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VaultConfig {
     pub master_password_hash: String,
     pub salt: Vec<u8>,
+    /// Length of the salts `rekey` and `change-master` generate
+    #[serde(default = "default_salt_size")]
+    pub salt_size: usize,
     pub version: String,
+    /// KDF algorithm and cost parameters; configs written before these existed used the Argon2id defaults
+    #[serde(default)]
+    pub kdf_params: KdfParams,
+    /// Cipher the vault file is encrypted with; older vaults are AES-256-GCM
+    #[serde(default = "Cipher::legacy")]
+    pub cipher: Cipher,
+    /// Record when each password is revealed (makes `get` write to the vault)
+    #[serde(default)]
+    pub track_access: bool,
+    /// Default generator policy for this vault, taking precedence over the
+    /// settings file
+    #[serde(default)]
+    pub generator_policy: Option<PasswordPolicy>,
+    /// Number of automatic backups to keep before pruning the oldest
+    #[serde(default = "default_max_backups")]
+    pub max_backups: usize,
+    /// Whether the vault file is authenticated against this config's version
+    /// and salt; older vaults are bound the next time they are rewrapped
+    #[serde(default)]
+    pub config_bound: bool,
+    /// Delay and lockout thresholds for failed unlock attempts
+    #[serde(default)]
+    pub unlock_policy: UnlockPolicy,
+    /// Consecutive failed unlock attempts since the last success
+    #[serde(default)]
+    pub failed_unlocks: u32,
+    #[serde(default)]
+    pub last_failed_unlock: Option<DateTime<Utc>>,
+    /// When the master password was set; unknown for vaults created before
+    /// this was recorded, until the password is next changed
+    #[serde(default)]
+    pub master_password_changed_at: Option<DateTime<Utc>>,
+    /// Starts at 1 and goes up by one with each master password change
+    #[serde(default)]
+    pub password_version: u32,
+    /// Note given with the last `change-master --reason`
+    #[serde(default)]
+    pub master_password_change_reason: Option<String>,
+    /// Goes up by one with every save of the vault file, so copies of the
+    /// vault kept elsewhere can tell which is newer
+    #[serde(default)]
+    pub revision: u64,
 }
 
-/// Encrypted vault data
+/// Non-secret facts about a vault, readable without unlocking it
+#[derive(Debug, Clone)]
+pub struct VaultInfo {
+    pub version: String,
+    pub cipher: Cipher,
+    pub kdf_params: KdfParams,
+    pub salt_size: usize,
+    pub master_password_changed_at: Option<DateTime<Utc>>,
+    pub password_version: u32,
+    pub master_password_change_reason: Option<String>,
+}
+
+/// How `unlock` slows down repeated failures
+///
+/// This only throttles guessing through aliaser itself; someone who can
+/// copy the config can attack the password hash offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnlockPolicy {
+    /// Failures allowed before further attempts are delayed
+    pub free_attempts: u32,
+    /// Longest delay before an attempt, in seconds; delays double from 1 s
+    pub max_delay_secs: u64,
+    /// Consecutive failures after which unlocking is refused for a while (0 = never)
+    pub lockout_after: u32,
+    /// How long a lockout lasts, counted from the last failure
+    pub lockout_minutes: u32,
+}
+
+impl Default for UnlockPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            max_delay_secs: 30,
+            lockout_after: 10,
+            lockout_minutes: 15,
+        }
+    }
+}
+
+impl UnlockPolicy {
+    /// How long to wait before the attempt that follows `failures` failures
+    pub fn delay(&self, failures: u32) -> std::time::Duration {
+        let Some(excess) = failures.checked_sub(self.free_attempts).filter(|n| *n > 0) else {
+            return std::time::Duration::ZERO;
+        };
+        let seconds = 1u64.checked_shl(excess - 1).unwrap_or(u64::MAX);
+        std::time::Duration::from_secs(seconds.min(self.max_delay_secs))
+    }
+
+    /// When a lockout caused by `failures` failures ends, if there is one
+    pub fn locked_until(
+        &self,
+        failures: u32,
+        last_failure: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        if self.lockout_after == 0 || failures < self.lockout_after {
+            return None;
+        }
+        last_failure.map(|at| at + Duration::minutes(self.lockout_minutes.into()))
+    }
+}
+
+impl VaultConfig {
+    /// Associated data for the vault file, so pairing it with a different
+    /// config (e.g. an older one after a rollback) fails to decrypt
+    fn aad(&self) -> Vec<u8> {
+        if !self.config_bound {
+            return Vec::new();
+        }
+        let mut aad = b"aliaser-vault\0".to_vec();
+        aad.extend_from_slice(self.version.as_bytes());
+        aad.push(0);
+        aad.extend_from_slice(&self.salt);
+        aad
+    }
+}
+
+fn default_max_backups() -> usize {
+    DEFAULT_MAX_BACKUPS
+}
+
+fn default_salt_size() -> usize {
+    DEFAULT_SALT_SIZE
+}
+
+const SHARED_IDENTITY_FORMAT: &str = "aliaser-identity";
+const SHARED_IDENTITY_VERSION: u32 = 1;
+const VAULT_BACKUP_FORMAT: &str = "aliaser-vault";
+const VAULT_BACKUP_VERSION: u32 = 1;
+/// A self-contained export whose data doesn't decrypt under its own config
+const DAMAGED_BACKUP: &str = "The export's vault data doesn't match its config; the file is damaged";
+
+/// A vault export that carries its config, so it can be restored on a
+/// machine with no vault yet
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultBackup {
+    format: String,
+    version: u32,
+    config: VaultConfig,
+    data: Vec<u8>,
+}
+
+impl VaultBackup {
+    /// Parses a self-contained export, or returns `None` for any other file
+    /// (a plain export is raw ciphertext, never JSON)
+    fn parse(contents: &[u8]) -> Result<Option<Self>> {
+        let backup: VaultBackup = match serde_json::from_slice(contents) {
+            Ok(backup) => backup,
+            Err(_) => return Ok(None),
+        };
+        if backup.format != VAULT_BACKUP_FORMAT {
+            return Ok(None);
+        }
+        if backup.version > VAULT_BACKUP_VERSION {
+            anyhow::bail!(
+                "Vault export format version {} is newer than this version of aliaser supports",
+                backup.version
+            );
+        }
+        // Before anything derives a key or checks a password with them
+        backup.config.kdf_params.check_limits()?;
+        Ok(Some(backup))
+    }
+}
+
+/// A single identity encrypted under its own passphrase for sharing
 #[derive(Debug, Serialize, Deserialize)]
+struct SharedIdentity {
+    format: String,
+    version: u32,
+    kdf_params: KdfParams,
+    #[serde(default = "Cipher::legacy")]
+    cipher: Cipher,
+    salt: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// What would change if incoming vault data replaced the current vault
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VaultDiff {
+    /// Services only in the incoming data
+    pub added: Vec<String>,
+    /// Services in both whose contents differ, with the fields that changed
+    pub updated: Vec<(String, Vec<&'static str>)>,
+    /// Services only in the current vault
+    pub removed: Vec<String>,
+    /// Services in both with the same contents, whatever their timestamps
+    pub unchanged: Vec<String>,
+}
+
+impl VaultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The vault file, config and audit log exactly as stored, for copying a
+/// vault to another machine without decrypting it
+#[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+#[derive(Clone)]
+pub struct VaultSnapshot {
+    pub revision: u64,
+    pub vault: Vec<u8>,
+    pub config: Vec<u8>,
+    /// The audit log, which is encrypted under the same key as the vault
+    /// and has to travel with it
+    pub log: Option<Vec<u8>>,
+}
+
+/// How one copy of a vault relates to another
+#[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotOrder {
+    /// Same vault file and revision; config-only changes such as
+    /// `track-access` don't count
+    Same,
+    /// Saved more times than the other copy
+    Newer,
+    /// Saved fewer times than the other copy
+    Older,
+    /// Saved as often as the other copy but with different contents, as
+    /// when both were changed since they last matched
+    Diverged,
+}
+
+#[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+impl VaultSnapshot {
+    /// Pairs a vault file with its config, reading the revision from the config
+    pub fn new(vault: Vec<u8>, config: Vec<u8>) -> Result<Self> {
+        let parsed: VaultConfig = serde_json::from_slice(&config).context("Vault config appears corrupted")?;
+        Ok(Self {
+            revision: parsed.revision,
+            vault,
+            config,
+            log: None,
+        })
+    }
+
+    /// Reads the vault file, config and any audit log from `store`, or
+    /// `None` if it holds no vault
+    pub fn read_from(store: &dyn VaultStore) -> Result<Option<Self>> {
+        if !store.exists(Blob::Config) {
+            return Ok(None);
+        }
+        let config = store.read(Blob::Config).context("Failed to read vault config")?;
+        let vault = store.read(Blob::Vault).context("Failed to read vault file")?;
+        let log = if store.exists(Blob::Log) {
+            Some(store.read(Blob::Log).context("Failed to read audit log")?)
+        } else {
+            None
+        };
+        Ok(Some(Self { log, ..Self::new(vault, config)? }))
+    }
+
+    /// Writes the vault file, the audit log and then the config to `store`,
+    /// so an interrupted write leaves the old revision to compare against.
+    /// A snapshot without a log removes the one in `store`.
+    pub fn write_to(&self, store: &dyn VaultStore) -> Result<()> {
+        store.write(Blob::Vault, &self.vault).context("Failed to write vault file")?;
+        match &self.log {
+            Some(log) => store.write(Blob::Log, log).context("Failed to write audit log")?,
+            None if store.exists(Blob::Log) => store.remove(Blob::Log).context("Failed to remove audit log")?,
+            None => {}
+        }
+        store.write(Blob::Config, &self.config).context("Failed to write vault config")?;
+        Ok(())
+    }
+
+    /// How this copy relates to `other`
+    pub fn compare(&self, other: &VaultSnapshot) -> SnapshotOrder {
+        if self.vault == other.vault && self.revision == other.revision {
+            return SnapshotOrder::Same;
+        }
+        match self.revision.cmp(&other.revision) {
+            std::cmp::Ordering::Greater => SnapshotOrder::Newer,
+            std::cmp::Ordering::Less => SnapshotOrder::Older,
+            std::cmp::Ordering::Equal => SnapshotOrder::Diverged,
+        }
+    }
+}
+
+/// One of the pieces a vault is stored as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Blob {
+    /// The encrypted identities
+    Vault,
+    /// Password hash, salt, KDF and cipher settings
+    Config,
+    /// Encrypted listing index, rebuilt from the vault when missing
+    Index,
+    /// Encrypted order of the last `list`
+    Listing,
+    /// Encrypted, hash-chained record of changes
+    Log,
+}
+
+impl fmt::Display for Blob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Blob::Vault => "vault file",
+            Blob::Config => "vault config",
+            Blob::Index => "index file",
+            Blob::Listing => "listing file",
+            Blob::Log => "audit log",
+        })
+    }
+}
+
+#[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+impl Blob {
+    /// File name for `blob` in a remote copy of the vault, which only ever
+    /// holds the vault file, config and audit log
+    pub fn remote_name(self) -> io::Result<&'static str> {
+        match self {
+            Blob::Vault => Ok(VAULT_FILE),
+            Blob::Config => Ok(CONFIG_FILE),
+            Blob::Log => Ok(LOG_FILE),
+            Blob::Index | Blob::Listing => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("A remote vault has no {}", self),
+            )),
+        }
+    }
+}
+
+/// Where a [`Vault`] keeps its blobs
+///
+/// Backups, permission checks and [`Vault::paths`] work on files and fail for
+/// stores whose [`VaultStore::path`] is `None`.
+pub trait VaultStore {
+    fn read(&self, blob: Blob) -> io::Result<Vec<u8>>;
+
+    /// Replaces `blob`, which must never be left half-written for the config
+    fn write(&self, blob: Blob, data: &[u8]) -> io::Result<()>;
+
+    fn exists(&self, blob: Blob) -> bool;
+
+    fn remove(&self, blob: Blob) -> io::Result<()>;
+
+    /// The first `len` bytes of `blob`
+    fn read_prefix(&self, blob: Blob, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = self.read(blob)?;
+        if data.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// The file holding `blob`, for stores kept on disk
+    fn path(&self, blob: Blob) -> Option<PathBuf>;
+}
+
+/// Blobs as owner-only files: the vault and config where given, the index
+/// and listing next to the vault
+pub struct FileStore {
+    vault_path: PathBuf,
+    config_path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(vault_path: PathBuf, config_path: PathBuf) -> Self {
+        Self { vault_path, config_path }
+    }
+
+    fn file(&self, blob: Blob) -> PathBuf {
+        match blob {
+            Blob::Vault => self.vault_path.clone(),
+            Blob::Config => self.config_path.clone(),
+            Blob::Index => self.vault_path.with_extension("index"),
+            Blob::Listing => self.vault_path.with_extension("last-list"),
+            Blob::Log => self.vault_path.with_extension("log"),
+        }
+    }
+}
+
+impl VaultStore for FileStore {
+    fn read(&self, blob: Blob) -> io::Result<Vec<u8>> {
+        fs::read(self.file(blob))
+    }
+
+    fn write(&self, blob: Blob, data: &[u8]) -> io::Result<()> {
+        let path = self.file(blob);
+        if blob == Blob::Config {
+            // Write then rename so a crash never leaves a half-written config
+            let tmp_path = path.with_extension("config.tmp");
+            write_private_file(&tmp_path, data)?;
+            return fs::rename(&tmp_path, &path);
+        }
+        write_private_file(&path, data)
+    }
+
+    fn exists(&self, blob: Blob) -> bool {
+        self.file(blob).exists()
+    }
+
+    fn remove(&self, blob: Blob) -> io::Result<()> {
+        fs::remove_file(self.file(blob))
+    }
+
+    fn read_prefix(&self, blob: Blob, len: usize) -> io::Result<Vec<u8>> {
+        let mut prefix = vec![0u8; len];
+        fs::File::open(self.file(blob))?.read_exact(&mut prefix)?;
+        Ok(prefix)
+    }
+
+    fn path(&self, blob: Blob) -> Option<PathBuf> {
+        Some(self.file(blob))
+    }
+}
+
+/// Where a vault's files live on disk
+#[derive(Debug, Clone)]
+pub struct VaultPaths {
+    pub vault: PathBuf,
+    pub config: PathBuf,
+    pub index: PathBuf,
+    pub log: PathBuf,
+}
+
+/// Non-secret facts about an identity, for listings and front-ends that
+/// shouldn't need to decrypt or hold any secrets to show one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentitySummary {
+    pub service: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub attachments: usize,
+    pub rotation_due: Option<DateTime<Utc>>,
+    // Deliberately not defaulted: an index written before these existed
+    // fails to parse and is rebuilt, rather than reporting `false`
+    pub has_otp: bool,
+    pub has_personal_info: bool,
+    pub favorite: bool,
+    // Options would default to `None` when missing, so require them the same way
+    #[serde(deserialize_with = "Option::deserialize")]
+    pub color: Option<String>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    pub icon: Option<String>,
+}
+
+impl IdentitySummary {
+    fn new(service: &str, identity: &Identity) -> Self {
+        Self {
+            service: service.to_string(),
+            created_at: identity.created_at,
+            updated_at: identity.updated_at,
+            attachments: identity.attachments.len(),
+            rotation_due: identity.rotation_due(),
+            has_otp: identity.otp.is_some(),
+            has_personal_info: identity.personal_info.is_some(),
+            favorite: identity.favorite,
+            color: identity.color.clone(),
+            icon: identity.icon.clone(),
+        }
+    }
+
+    /// Whether the rotation due date has passed
+    pub fn rotation_overdue(&self) -> bool {
+        self.rotation_due.is_some_and(|due| due <= Utc::now())
+    }
+}
+
+/// Listing metadata kept beside the vault so `list` needn't decrypt and
+/// parse every identity (attachments included)
+///
+/// Encrypted with the vault key. It records the header and nonce of the vault
+/// file it was built from; each save picks a fresh nonce, so comparing them
+/// with the first bytes of the vault file detects a stale index without
+/// decrypting.
+#[derive(Serialize, Deserialize)]
+struct VaultIndex {
+    vault_nonce: Vec<u8>,
+    identities: Vec<IdentitySummary>,
+}
+
+/// The order services were shown in by the last `list`, so `get 3` can
+/// refer to the third one
+///
+/// Encrypted with the vault key like the index, and tied to the vault file
+/// the same way, so any change to the vault invalidates it.
+#[derive(Serialize, Deserialize)]
+struct LastListing {
+    vault_nonce: Vec<u8>,
+    services: Vec<String>,
+}
+
+/// A timestamped safety copy of the vault and config files
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub timestamp: String,
+    pub vault_path: PathBuf,
+    pub config_path: PathBuf,
+    /// The audit log at the time, if there was one
+    pub log_path: Option<PathBuf>,
+}
+
+/// Encrypted vault data
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VaultData {
     pub identities: HashMap<String, Identity>,
+    /// Where the audit log ended after the last change it recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_head: Option<AuditHead>,
+}
+
+impl VaultData {
+    /// Makes data from an untrusted file consistent before it is used
+    ///
+    /// Map keys are authoritative, as everywhere else: each is trimmed, and
+    /// each identity's `service` field is set to its key. An empty key is
+    /// an error. Keys that become equal after trimming keep only the most
+    /// recently updated identity. Returns a description of each change.
+    pub fn validate_and_normalize(&mut self) -> Result<Vec<String>> {
+        let mut changes = Vec::new();
+        let mut normalized: HashMap<String, Identity> = HashMap::with_capacity(self.identities.len());
+
+        let mut entries: Vec<(String, Identity)> = self.identities.drain().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (key, mut identity) in entries {
+            let service = key.trim().to_string();
+            if service.is_empty() {
+                anyhow::bail!("Vault data contains an identity with an empty service name");
+            }
+            if service != key {
+                changes.push(format!("Renamed '{}' to '{}'", key, service));
+            }
+            if identity.service != service {
+                changes.push(format!("Set the service name of '{}' (was '{}')", service, identity.service));
+                identity.service = service.clone();
+            }
+
+            match normalized.get(&service) {
+                Some(kept) if kept.updated_at >= identity.updated_at => {
+                    changes.push(format!("Dropped an older duplicate of '{}'", service));
+                }
+                Some(_) => {
+                    changes.push(format!("Dropped an older duplicate of '{}'", service));
+                    normalized.insert(service, identity);
+                }
+                None => {
+                    normalized.insert(service, identity);
+                }
+            }
+        }
+
+        self.identities = normalized;
+        Ok(changes)
+    }
+
+    /// Renames services as `mapping` says, keeping only the most recently
+    /// updated identity where two names end up the same. Returns each
+    /// rename as `(from, to)`, sorted by the old name.
+    pub fn remap_services(&mut self, mapping: &ServiceMapping) -> Vec<(String, String)> {
+        let mut renames = Vec::new();
+        let mut entries: Vec<(String, Identity)> = self.identities.drain().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (key, mut identity) in entries {
+            let service = mapping.apply(&key);
+            if service != key {
+                renames.push((key, service.clone()));
+                identity.service = service.clone();
+            }
+            match self.identities.get(&service) {
+                Some(kept) if kept.updated_at >= identity.updated_at => {}
+                _ => {
+                    self.identities.insert(service, identity);
+                }
+            }
+        }
+        renames
+    }
+}
+
+/// How `import` rewrites incoming service names
+#[derive(Debug, Clone, Default)]
+pub struct ServiceMapping {
+    /// Exact names to replace; a mapped name is used as given
+    pub renames: Vec<(String, String)>,
+    /// Drop a leading `www.` from names that aren't mapped
+    pub strip_www: bool,
+    /// Lowercase names that aren't mapped
+    pub lowercase: bool,
+}
+
+impl ServiceMapping {
+    pub fn is_empty(&self) -> bool {
+        self.renames.is_empty() && !self.strip_www && !self.lowercase
+    }
+
+    /// The name `service` is stored under; never empty
+    pub fn apply(&self, service: &str) -> String {
+        if let Some((_, to)) = self.renames.iter().find(|(from, _)| from == service) {
+            return to.clone();
+        }
+        let mut name = service;
+        if self.strip_www {
+            match name.get(..4) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("www.") && name.len() > 4 => name = &name[4..],
+                _ => {}
+            }
+        }
+        if self.lowercase {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
 }
 
 pub struct Vault {
-    vault_path: PathBuf,
-    config_path: PathBuf,
-    key: Option<[u8; 32]>,
+    store: Box<dyn VaultStore>,
+    key: Option<SecretKey>,
+    cipher: Cipher,
+    aad: Vec<u8>,
+    read_only: bool,
+    /// Decrypted vault data, kept so repeated reads within one command don't
+    /// decrypt the file again; cleared whenever the file or key changes
+    cache: RefCell<Option<VaultData>>,
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        self.lock();
+    }
 }
 
 impl Vault {
-    /// Creates a new vault instance
-    pub fn new() -> Result<Self> {
-        let home = dirs::home_dir().context("Failed to get home directory")?;
-        let vault_path = home.join(VAULT_FILE);
-        let config_path = home.join(CONFIG_FILE);
+    /// Creates a vault instance for the files in the home directory, reading
+    /// the config from `config_path` instead when given
+    pub fn new(config_path: Option<PathBuf>) -> Result<Self> {
+        let home = dirs::home_dir().context("Cannot find a home directory to keep the vault in; set HOME")?;
+        let config_path = config_path.unwrap_or_else(|| home.join(CONFIG_FILE));
+        Ok(Self::with_paths(home.join(VAULT_FILE), config_path))
+    }
 
-        Ok(Self {
-            vault_path,
-            config_path,
+    fn with_paths(vault_path: PathBuf, config_path: PathBuf) -> Self {
+        Self::with_store(Box::new(FileStore::new(vault_path, config_path)))
+    }
+
+    /// Creates a vault instance backed by `store`
+    pub fn with_store(store: Box<dyn VaultStore>) -> Self {
+        Self {
+            store,
             key: None,
-        })
+            cipher: Cipher::default(),
+            aad: Vec::new(),
+            read_only: false,
+            cache: RefCell::new(None),
+        }
+    }
+
+    /// Forgets the key and any decrypted data, scrubbing both from memory
+    pub fn lock(&mut self) {
+        // Dropping the key zeroizes it
+        self.key = None;
+        self.invalidate_cache();
+    }
+
+    /// Puts the vault in read-only mode, in which every write fails before
+    /// touching the disk
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
     }
 
     /// Checks if vault is initialized
     pub fn is_initialized(&self) -> bool {
-        self.config_path.exists() && self.vault_path.exists()
+        self.store.exists(Blob::Config) && self.store.exists(Blob::Vault)
+    }
+
+    /// Returns the resolved vault and config file locations
+    pub fn paths(&self) -> Result<VaultPaths> {
+        Ok(VaultPaths {
+            vault: self.file_path(Blob::Vault)?,
+            config: self.file_path(Blob::Config)?,
+            index: self.file_path(Blob::Index)?,
+            log: self.file_path(Blob::Log)?,
+        })
+    }
+
+    /// Checks whether a key has been derived by `unlock` or `initialize`
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
     }
 
     /// Initializes a new vault with a master password
-    pub fn initialize(&mut self, master_password: &str) -> Result<()> {
+    pub fn initialize(
+        &mut self,
+        master_password: &str,
+        kdf_params: KdfParams,
+        cipher: Cipher,
+        salt_size: usize,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+
         if self.is_initialized() {
             anyhow::bail!("Vault already initialized");
         }
+        // Fail before writing anything, rather than with a bare IO error
+        // after the config is already in place
+        self.check_writable()?;
 
-        // Generate salt and hash password
-        let salt = generate_salt();
-        let password_hash = hash_password(master_password)?;
+        // Generate salt, hash password and derive the key before writing
+        // anything, so a failure here leaves no half-made vault behind
+        let salt = generate_salt(salt_size)?;
+        let password_hash = hash_password(master_password, &kdf_params)?;
+        let mut key = derive_key(master_password, &salt, &kdf_params)?;
 
         // Create config
         let config = VaultConfig {
             master_password_hash: password_hash,
-            salt: salt.to_vec(),
+            salt: salt.clone(),
+            salt_size,
             version: env!("CARGO_PKG_VERSION").to_string(),
+            kdf_params,
+            cipher,
+            track_access: false,
+            generator_policy: None,
+            max_backups: DEFAULT_MAX_BACKUPS,
+            config_bound: true,
+            unlock_policy: UnlockPolicy::default(),
+            failed_unlocks: 0,
+            last_failed_unlock: None,
+            master_password_changed_at: Some(Utc::now()),
+            password_version: 1,
+            master_password_change_reason: None,
+            revision: 0,
         };
 
+        // A log left by an earlier vault here can't be read with the new key
+        self.remove_stale_log();
+
         // Save config
-        let config_json = serde_json::to_string_pretty(&config)?;
-        fs::write(&self.config_path, config_json)?;
+        self.save_config(&config)?;
 
-        // Derive encryption key
-        let key = derive_key(master_password, &salt)?;
-        self.key = Some(key);
+        self.key = Some(SecretKey::new(&mut key));
+        self.invalidate_cache();
+        self.cipher = cipher;
+        self.aad = config.aad();
 
-        // Create empty vault
+        // Create empty vault, taking the config back out if that fails
         let vault_data = VaultData {
             identities: HashMap::new(),
+            audit_head: None,
         };
-        self.save_vault_data(&vault_data)?;
+        if let Err(e) = self.save_vault_data(&vault_data) {
+            self.key = None;
+            if let Err(remove) = self.store.remove(Blob::Config) {
+                warn!("Failed to remove the config of the unfinished vault: {}", remove);
+            }
+            return Err(e);
+        }
 
         Ok(())
     }
 
+    /// Checks that the vault and config files can be created, with an error
+    /// saying how to pick somewhere else if not
+    pub fn check_writable(&self) -> Result<()> {
+        self.ensure_writable()?;
+        for (blob, fix) in [
+            (Blob::Vault, "set HOME to a writable location"),
+            (Blob::Config, "pass --config a writable path"),
+        ] {
+            if let Some(path) = self.store.path(blob) {
+                check_writable_dir(&path, fix)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Unlocks the vault with the master password
     pub fn unlock(&mut self, master_password: &str) -> Result<()> {
         if !self.is_initialized() {
-            anyhow::bail!("Vault not initialized. Run 'init' first.");
+            return Err(VaultError::NotInitialized.into());
         }
 
         // Load config
-        let config = self.load_config()?;
+        let mut config = self.load_config()?;
+        let policy = config.unlock_policy;
+
+        if let Some(until) = policy.locked_until(config.failed_unlocks, config.last_failed_unlock) {
+            if until > Utc::now() {
+                return Err(VaultError::LockedOut { until }.into());
+            }
+        }
+        let delay = policy.delay(config.failed_unlocks);
+        if !delay.is_zero() {
+            warn!(
+                "{} failed unlock attempts; waiting {} s",
+                config.failed_unlocks,
+                delay.as_secs()
+            );
+            std::thread::sleep(delay);
+        }
 
-        // Verify password
+        // Count the attempt as failed before checking it, so killing the
+        // process mid-check can't skip the bookkeeping. Read-only mode still
+        // enforces the delay and lockout but cannot record new failures.
+        if !self.read_only {
+            config.failed_unlocks += 1;
+            config.last_failed_unlock = Some(Utc::now());
+            self.save_config(&config)?;
+        }
+
+        // Verify password. The hash comparison is constant-time, and nothing
+        // else on this path branches on the password
+        debug!("Verifying master password");
         if !verify_password(master_password, &config.master_password_hash)? {
-            anyhow::bail!("Invalid master password");
+            warn!("Master password verification failed");
+            return Err(VaultError::WrongPassword.into());
+        }
+
+        if !self.read_only {
+            config.failed_unlocks = 0;
+            config.last_failed_unlock = None;
+            self.save_config(&config)?;
         }
 
         // Derive key
-        let key = derive_key(master_password, &config.salt)?;
-        self.key = Some(key);
+        let mut key = derive_key(master_password, &config.salt, &config.kdf_params)?;
+        self.key = Some(SecretKey::new(&mut key));
+        self.invalidate_cache();
+        self.cipher = config.cipher;
+        self.aad = config.aad();
+        info!("Vault unlocked");
 
         Ok(())
     }
 
     /// Adds a new identity to the vault
     pub fn add_identity(&self, identity: Identity) -> Result<()> {
+        self.insert_identity(identity, false).map(|_| ())
+    }
+
+    /// Adds an identity, replacing any existing one for the same service.
+    /// A replaced identity's creation time and password history carry over,
+    /// with its password added to the history; everything else is discarded.
+    /// Returns whether an identity was replaced.
+    pub fn add_or_replace_identity(&self, identity: Identity) -> Result<bool> {
+        self.insert_identity(identity, true)
+    }
+
+    /// Checks if an identity exists for a service
+    pub fn contains(&self, service: &str) -> Result<bool> {
+        Ok(self.load_vault_data()?.identities.contains_key(service))
+    }
+
+    fn insert_identity(&self, mut identity: Identity, overwrite: bool) -> Result<bool> {
+        check_identity(&identity)?;
+
         let mut data = self.load_vault_data()?;
 
-        if data.identities.contains_key(&identity.service) {
-            anyhow::bail!("Identity for service '{}' already exists", identity.service);
-        }
+        let replaced = match data.identities.get(&identity.service) {
+            Some(_) if !overwrite => {
+                anyhow::bail!("Identity for service '{}' already exists", identity.service);
+            }
+            Some(existing) => {
+                identity.created_at = existing.created_at;
+                identity.password_history = existing.password_history.clone();
+                identity.last_rotated = existing.last_rotated;
+                if !ct_eq(
+                    existing.credentials.password.as_bytes(),
+                    identity.credentials.password.as_bytes(),
+                ) {
+                    identity.last_rotated = Some(Utc::now());
+                    if !existing.credentials.password.is_empty() {
+                        identity.remember_password(existing.credentials.password.clone());
+                    }
+                }
+                true
+            }
+            None => false,
+        };
 
-        data.identities.insert(identity.service.clone(), identity);
+        let service = identity.service.clone();
+        data.identities.insert(service.clone(), identity);
+        let op = if replaced { AuditOp::Replace } else { AuditOp::Add };
+        self.audit(&mut data, &[(op, Some(&service))]);
         self.save_vault_data(&data)?;
 
-        Ok(())
+        Ok(replaced)
     }
 
     /// Gets an identity by service name
     pub fn get_identity(&self, service: &str) -> Result<Identity> {
         let data = self.load_vault_data()?;
-        data.identities
-            .get(service)
-            .cloned()
-            .context(format!("Identity for service '{}' not found", service))
+        if let Some(identity) = data.identities.get(service) {
+            return Ok(identity.clone());
+        }
+
+        Err(VaultError::ServiceNotFound {
+            service: service.to_string(),
+            suggestions: suggest(service, data.identities.keys()),
+        }
+        .into())
+    }
+
+    /// Lists the services whose current password is `password`, sorted
+    ///
+    /// Compares in constant time; the plaintexts never leave the vault.
+    pub fn services_using_password(&self, password: &str) -> Result<Vec<String>> {
+        if password.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.find_matching(|identity| ct_eq(identity.credentials.password.as_bytes(), password.as_bytes()))
+    }
+
+    /// Returns every identity, sorted by service name
+    ///
+    /// The vault is decrypted once, however many identities it holds. Each
+    /// identity zeroizes its secrets when dropped.
+    pub fn all_identities(&self) -> Result<Vec<Identity>> {
+        let mut identities: Vec<Identity> = self.iter_identities()?.collect();
+        identities.sort_by(|a, b| a.service.cmp(&b.service));
+        Ok(identities)
+    }
+
+    /// Iterates over every identity in no particular order, after decrypting
+    /// the vault once; identities not yet consumed are zeroized if the
+    /// iterator is dropped early
+    pub fn iter_identities(&self) -> Result<impl Iterator<Item = Identity>> {
+        Ok(self.load_vault_data()?.identities.into_values())
     }
 
-    /// Lists all service names
+    /// Lists all service names, sorted
     pub fn list_services(&self) -> Result<Vec<String>> {
+        Ok(self.load_index()?.into_iter().map(|summary| summary.service).collect())
+    }
+
+    /// Summarizes every identity, sorted by service name
+    pub fn list_detailed(&self) -> Result<Vec<IdentitySummary>> {
+        self.load_index()
+    }
+
+    /// Summarizes the identities marked as favorites, sorted by service name
+    pub fn list_favorites(&self) -> Result<Vec<IdentitySummary>> {
+        Ok(self.load_index()?.into_iter().filter(|summary| summary.favorite).collect())
+    }
+
+    /// Summarizes the identities last updated before `cutoff`, sorted by
+    /// service name
+    pub fn list_older_than(&self, cutoff: DateTime<Utc>) -> Result<Vec<IdentitySummary>> {
+        Ok(self.load_index()?.into_iter().filter(|summary| summary.updated_at < cutoff).collect())
+    }
+
+    /// Summarizes every identity from the index file, sorted by service name
+    ///
+    /// Falls back to the vault itself when the index is missing, stale or
+    /// unreadable, and rewrites it unless the vault is read-only.
+    pub fn load_index(&self) -> Result<Vec<IdentitySummary>> {
+        let key = self.key()?;
+
+        if let Some(data) = self.cache.borrow().as_ref() {
+            return Ok(summarize(data));
+        }
+        match self.read_index(key) {
+            Ok(Some(identities)) => return Ok(identities),
+            Ok(None) => debug!("Index is missing or stale; reading the vault"),
+            // e.g. left over from before a restore under a different key
+            Err(e) => debug!("Ignoring unreadable index {}: {:#}", self.location(Blob::Index).display(), e),
+        }
+
+        let data = self.load_vault_data()?;
+        let identities = summarize(&data);
+        if !self.read_only {
+            if let Err(e) = self.write_index(&identities) {
+                warn!("Failed to update index: {:#}", e);
+            }
+        }
+        Ok(identities)
+    }
+
+    /// Records the order `services` were just listed in, for
+    /// [`Vault::listed_service`]; does nothing in read-only mode
+    pub fn remember_listing(&self, services: &[String]) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let listing = LastListing {
+            vault_nonce: self.vault_nonce()?,
+            services: services.to_vec(),
+        };
+        let json = Zeroizing::new(serde_json::to_vec(&listing)?);
+        let encrypted = encrypt(&json, self.key()?, self.cipher, &self.listing_aad())?;
+        self.store.write(Blob::Listing, &encrypted).context("Failed to write listing file")?;
+        Ok(())
+    }
+
+    /// Returns the service shown at `position` (counting from 1) by the last
+    /// `list`, provided the vault hasn't changed since
+    pub fn listed_service(&self, position: usize) -> Result<String> {
+        let key = self.key()?;
+        if !self.store.exists(Blob::Listing) {
+            anyhow::bail!("No listing to pick #{} from; run `list` first", position);
+        }
+
+        let encrypted = self.store.read(Blob::Listing).context("Failed to read listing file")?;
+        let listing: Option<LastListing> = decrypt(&encrypted, key, self.cipher, &self.listing_aad())
+            .ok()
+            .and_then(|json| serde_json::from_slice(&Zeroizing::new(json)).ok());
+        let listing = match listing {
+            Some(listing) if listing.vault_nonce == self.vault_nonce()? => listing,
+            _ => anyhow::bail!("The vault changed since the last `list`; run it again to pick by number"),
+        };
+
+        match position.checked_sub(1).and_then(|i| listing.services.get(i)) {
+            Some(service) => Ok(service.clone()),
+            None => anyhow::bail!(
+                "The last `list` showed {} identities; there is no #{}",
+                listing.services.len(),
+                position
+            ),
+        }
+    }
+
+    /// Returns the record of changes made to the vault, oldest first
+    pub fn audit_log(&self) -> Result<AuditLog> {
+        self.key()?;
+        self.read_audit_log()
+    }
+
+    /// Lists identities whose password rotation is due within `within_days`
+    /// days (including overdue ones), soonest first
+    pub fn expiring(&self, within_days: u32) -> Result<Vec<(String, DateTime<Utc>)>> {
         let data = self.load_vault_data()?;
-        let mut services: Vec<String> = data.identities.keys().cloned().collect();
-        services.sort();
-        Ok(services)
+        let cutoff = Utc::now() + Duration::days(within_days.into());
+
+        let mut expiring: Vec<(String, DateTime<Utc>)> = data
+            .identities
+            .iter()
+            .filter_map(|(service, identity)| {
+                identity
+                    .rotation_due()
+                    .filter(|due| *due <= cutoff)
+                    .map(|due| (service.clone(), due))
+            })
+            .collect();
+        expiring.sort_by_key(|(_, due)| *due);
+
+        Ok(expiring)
     }
 
     /// Updates an existing identity
     pub fn update_identity(&self, service: &str, mut identity: Identity) -> Result<()> {
         let mut data = self.load_vault_data()?;
 
-        if !data.identities.contains_key(service) {
-            anyhow::bail!("Identity for service '{}' not found", service);
+        let existing = data
+            .identities
+            .get(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        if !ct_eq(
+            existing.credentials.password.as_bytes(),
+            identity.credentials.password.as_bytes(),
+        ) {
+            identity.last_rotated = Some(Utc::now());
+            if !existing.credentials.password.is_empty() {
+                identity.remember_password(existing.credentials.password.clone());
+            }
         }
 
+        // The map key is authoritative; keep the struct's copy in sync with it
+        identity.service = service.to_string();
+        check_identity(&identity)?;
         identity.update_timestamp();
         data.identities.insert(service.to_string(), identity);
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
         self.save_vault_data(&data)?;
 
         Ok(())
     }
 
-    /// Deletes an identity
+    /// Deletes the identity stored under exactly `service`
+    ///
+    /// Never matches loosely: without an exact match nothing is removed, and
+    /// the error only suggests close names for the caller to retry with.
     pub fn delete_identity(&self, service: &str) -> Result<()> {
         let mut data = self.load_vault_data()?;
 
         if data.identities.remove(service).is_none() {
-            anyhow::bail!("Identity for service '{}' not found", service);
+            return Err(VaultError::ServiceNotFound {
+                service: service.to_string(),
+                suggestions: suggest(service, data.identities.keys()),
+            }
+            .into());
         }
 
+        self.audit(&mut data, &[(AuditOp::Delete, Some(service))]);
         self.save_vault_data(&data)?;
         Ok(())
     }
 
-    /// Changes the master password
-    pub fn change_master_password(&mut self, old_password: &str, new_password: &str) -> Result<()> {
-        // Verify old password and load data
-        self.unlock(old_password)?;
-        let data = self.load_vault_data()?;
+    /// Stamps the identity's `last_accessed` time if access tracking is enabled.
+    /// Does nothing (and writes nothing) when tracking is off or the vault is read-only.
+    pub fn record_access(&self, service: &str) -> Result<()> {
+        if self.read_only || !self.load_config()?.track_access {
+            return Ok(());
+        }
 
-        // Generate new salt and hash
-        let new_salt = generate_salt();
-        let new_hash = hash_password(new_password)?;
+        let mut data = self.load_vault_data()?;
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
 
-        // Derive new key
-        let new_key = derive_key(new_password, &new_salt)?;
+        identity.last_accessed = Some(Utc::now());
+        // A read rather than a change, so left out of the audit log
+        self.save_vault_data(&data)
+    }
 
-        // Update config
-        let config = VaultConfig {
-            master_password_hash: new_hash,
-            salt: new_salt.to_vec(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
-        };
+    /// Whether password reveals are being recorded
+    pub fn tracks_access(&self) -> Result<bool> {
+        Ok(self.load_config()?.track_access)
+    }
+
+    /// Turns access tracking on or off
+    pub fn set_track_access(&self, enabled: bool) -> Result<()> {
+        let mut config = self.load_config()?;
+        config.track_access = enabled;
+        self.save_config(&config)
+    }
+
+    /// Returns the vault's stored generator policy, if one was set; readable
+    /// without unlocking, and `None` when there is no vault yet
+    pub fn generator_policy(&self) -> Result<Option<PasswordPolicy>> {
+        if !self.is_initialized() {
+            return Ok(None);
+        }
+        Ok(self.load_config()?.generator_policy)
+    }
+
+    /// Stores or (with `None`) clears the vault's default generator policy
+    pub fn set_generator_policy(&self, policy: Option<PasswordPolicy>) -> Result<()> {
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+        let mut config = self.load_config()?;
+        config.generator_policy = policy;
+        self.save_config(&config)
+    }
+
+    /// Marks an identity as reviewed by bumping `updated_at`, and with
+    /// `rotated` also restarts its rotation period, without changing any data
+    pub fn touch(&self, service: &str, rotated: bool) -> Result<()> {
+        let mut data = self.load_vault_data()?;
+
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        identity.update_timestamp();
+        if rotated {
+            identity.last_rotated = Some(identity.updated_at);
+        }
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
+        self.save_vault_data(&data)?;
+        Ok(())
+    }
+
+    /// Marks or unmarks an identity as a favorite, returning whether that
+    /// changed anything; the identity's timestamps are left alone
+    pub fn set_favorite(&self, service: &str, favorite: bool) -> Result<bool> {
+        let mut data = self.load_vault_data()?;
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        if identity.favorite == favorite {
+            return Ok(false);
+        }
+        identity.favorite = favorite;
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
+        self.save_vault_data(&data)?;
+        Ok(true)
+    }
+
+    /// Returns the failed-unlock delay and lockout thresholds
+    pub fn unlock_policy(&self) -> Result<UnlockPolicy> {
+        Ok(self.load_config()?.unlock_policy)
+    }
+
+    /// Changes the failed-unlock delay and lockout thresholds
+    pub fn set_unlock_policy(&self, policy: UnlockPolicy) -> Result<()> {
+        let mut config = self.load_config()?;
+        config.unlock_policy = policy;
+        self.save_config(&config)
+    }
+
+    /// Removes and returns the next unused recovery code for a service
+    pub fn consume_recovery_code(&self, service: &str) -> Result<Zeroizing<String>> {
+        let mut data = self.load_vault_data()?;
+
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        if identity.credentials.recovery_codes.is_empty() {
+            anyhow::bail!("No recovery codes left for service '{}'", service);
+        }
 
-        let config_json = serde_json::to_string_pretty(&config)?;
-        fs::write(&self.config_path, config_json)?;
+        let code = Zeroizing::new(identity.credentials.recovery_codes.remove(0));
+        identity.update_timestamp();
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
+        self.save_vault_data(&data)?;
+
+        Ok(code)
+    }
 
-        // Re-encrypt vault with new key
-        self.key = Some(new_key);
+    /// Sets up or (with `None`) removes an identity's one-time password secret
+    pub fn set_otp(&self, service: &str, otp: Option<OtpSecret>) -> Result<()> {
+        if let Some(otp) = &otp {
+            totp::check_digits(otp.digits)?;
+        }
+        let mut data = self.load_vault_data()?;
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        identity.otp = otp;
+        identity.update_timestamp();
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
         self.save_vault_data(&data)?;
+        Ok(())
+    }
+
+    /// Generates the identity's current one-time password
+    ///
+    /// HOTP codes are only valid once, so the counter is advanced and saved
+    /// before the code is returned; that makes them unavailable read-only.
+    pub fn next_otp(&self, service: &str) -> Result<Zeroizing<String>> {
+        let mut data = self.load_vault_data()?;
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+        let otp = identity
+            .otp
+            .as_mut()
+            .with_context(|| format!("No one-time password set up for service '{}'", service))?;
+        // Imports bring in secrets `set_otp` never checked
+        totp::check_digits(otp.digits)?;
+
+        match &mut otp.kind {
+            OtpKind::Totp => {
+                let now = Utc::now().timestamp().try_into().context("System clock is before 1970")?;
+                Ok(Zeroizing::new(totp::totp(&otp.secret, now, otp.digits)))
+            }
+            OtpKind::Hotp { counter } => {
+                self.ensure_writable()
+                    .context("HOTP codes advance a counter stored in the vault")?;
+                let code = Zeroizing::new(totp::hotp(&otp.secret, *counter, otp.digits));
+                *counter += 1;
+                // Bookkeeping for a read, so left out of the audit log
+                self.save_vault_data(&data)?;
+                Ok(code)
+            }
+        }
+    }
+
+    /// Adds a file to an identity; names must be unique within the identity
+    pub fn attach(&self, service: &str, attachment: Attachment) -> Result<()> {
+        if attachment.data.len() > MAX_ATTACHMENT_SIZE {
+            anyhow::bail!(
+                "Attachment '{}' is {} bytes; the limit is {} KiB",
+                attachment.name,
+                attachment.data.len(),
+                MAX_ATTACHMENT_SIZE / 1024
+            );
+        }
+
+        let mut data = self.load_vault_data()?;
+        let identity = data
+            .identities
+            .get_mut(service)
+            .ok_or_else(|| VaultError::service_not_found(service))?;
+
+        if identity.attachments.iter().any(|a| a.name == attachment.name) {
+            anyhow::bail!("Service '{}' already has an attachment named '{}'", service, attachment.name);
+        }
 
+        identity.attachments.push(attachment);
+        identity.update_timestamp();
+        self.audit(&mut data, &[(AuditOp::Update, Some(service))]);
+        self.save_vault_data(&data)?;
         Ok(())
     }
 
+    /// Lists the services whose identities match `predicate`, sorted
+    pub fn find_matching<F>(&self, predicate: F) -> Result<Vec<String>>
+    where
+        F: Fn(&Identity) -> bool,
+    {
+        Ok(self
+            .all_identities()?
+            .iter()
+            .filter(|identity| predicate(identity))
+            .map(|identity| identity.service.clone())
+            .collect())
+    }
+
+    /// Deletes every identity matching `predicate` in a single vault write,
+    /// returning the removed service names
+    pub fn delete_matching<F>(&self, predicate: F) -> Result<Vec<String>>
+    where
+        F: Fn(&Identity) -> bool,
+    {
+        let mut data = self.load_vault_data()?;
+
+        let mut removed: Vec<String> = data
+            .identities
+            .iter()
+            .filter(|(_, identity)| predicate(identity))
+            .map(|(service, _)| service.clone())
+            .collect();
+        removed.sort();
+
+        if !removed.is_empty() {
+            for service in &removed {
+                data.identities.remove(service);
+            }
+            let changes: Vec<_> = removed.iter().map(|service| (AuditOp::Delete, Some(service.as_str()))).collect();
+            self.audit(&mut data, &changes);
+            self.save_vault_data(&data)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Lists services whose identities have no username and no password
+    pub fn find_incomplete(&self) -> Result<Vec<String>> {
+        self.find_matching(Identity::is_incomplete)
+    }
+
+    /// Changes the master password, recording when and, optionally, why
+    pub fn change_master_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+
+        // Verify old password and load data
+        self.unlock(old_password)?;
+        let data = self.load_vault_data()?;
+        let config = self.load_config()?;
+
+        self.rewrap(&data, new_password, config.cipher, config.kdf_params, config.salt_size)?;
+
+        let mut config = self.load_config()?;
+        config.master_password_changed_at = Some(Utc::now());
+        config.password_version += 1;
+        config.master_password_change_reason = reason.map(str::to_string);
+        self.save_config(&config)
+    }
+
+    /// Returns the vault's format, encryption and master password metadata
+    pub fn info(&self) -> Result<VaultInfo> {
+        if !self.is_initialized() {
+            return Err(VaultError::NotInitialized.into());
+        }
+        let config = self.load_config()?;
+        Ok(VaultInfo {
+            version: config.version,
+            cipher: config.cipher,
+            kdf_params: config.kdf_params,
+            salt_size: config.salt_size,
+            master_password_changed_at: config.master_password_changed_at,
+            password_version: config.password_version,
+            master_password_change_reason: config.master_password_change_reason,
+        })
+    }
+
+    /// Re-encrypts the vault under a fresh salt and derived key while keeping
+    /// the same master password, optionally switching to another hardening
+    /// profile of the vault's KDF. Returns the parameters now in use.
+    pub fn rekey(
+        &mut self,
+        master_password: &str,
+        profile: Option<KdfProfile>,
+        salt_size: Option<usize>,
+    ) -> Result<KdfParams> {
+        self.ensure_writable()?;
+
+        self.unlock(master_password)?;
+        let data = self.load_vault_data()?;
+        let config = self.load_config()?;
+        let kdf_params = match profile {
+            Some(profile) => profile.params(config.kdf_params.algorithm),
+            None => config.kdf_params,
+        };
+        let salt_size = salt_size.unwrap_or(config.salt_size);
+
+        self.rewrap(&data, master_password, config.cipher, kdf_params, salt_size)?;
+        Ok(kdf_params)
+    }
+
+    /// Re-encrypts the vault under another cipher and/or key derivation
+    /// function, keeping the master password and salt length and backing up
+    /// the old files first. Returns the cipher and parameters now in use.
+    pub fn convert(
+        &mut self,
+        master_password: &str,
+        cipher: Option<Cipher>,
+        kdf_params: Option<KdfParams>,
+    ) -> Result<(Cipher, KdfParams)> {
+        self.ensure_writable()?;
+
+        self.unlock(master_password)?;
+        let data = self.load_vault_data()?;
+        let config = self.load_config()?;
+        let cipher = cipher.unwrap_or(config.cipher);
+        let kdf_params = kdf_params.unwrap_or(config.kdf_params);
+
+        self.rewrap(&data, master_password, cipher, kdf_params, config.salt_size)?;
+        Ok((cipher, kdf_params))
+    }
+
+    /// Describes what [`Vault::migrate`] would change to bring the config and
+    /// vault file up to this version's format; empty when already current
+    pub fn pending_migrations(&self) -> Result<Vec<String>> {
+        let raw: serde_json::Value =
+            serde_json::from_str(&self.read_config_json()?).context("Vault config appears corrupted")?;
+        let config = self.load_config()?;
+        let current = env!("CARGO_PKG_VERSION");
+
+        if compare_versions(&config.version, current).is_gt() {
+            anyhow::bail!(
+                "Vault was written by aliaser {}, which is newer than this version ({}); upgrade aliaser instead",
+                config.version,
+                current
+            );
+        }
+
+        let mut steps = Vec::new();
+        // Settings added since the vault was created are only defaults at
+        // load time; write them out so the config describes itself
+        if let serde_json::Value::Object(full) = serde_json::to_value(&config)? {
+            for (field, value) in full {
+                if raw.get(&field).is_none() && !value.is_null() {
+                    steps.push(format!("Record default {} in the config", field));
+                }
+            }
+        }
+        if !config.config_bound {
+            steps.push("Bind the vault file to its config".to_string());
+        }
+        let prefix = self
+            .store
+            .read_prefix(Blob::Vault, ENVELOPE_HEADER_LEN)
+            .context("Failed to read vault file")?;
+        if !has_envelope(&prefix) {
+            steps.push("Add a format header to the vault file".to_string());
+        }
+        if config.version != current {
+            steps.push(format!("Update format version {} -> {}", config.version, current));
+        }
+        Ok(steps)
+    }
+
+    /// Upgrades an older vault to the current format, backing it up first
+    /// and re-encrypting it with its existing KDF, cipher and salt size.
+    /// Returns the steps applied; running it on a current vault does nothing.
+    pub fn migrate(&mut self, master_password: &str) -> Result<Vec<String>> {
+        self.ensure_writable()?;
+
+        // Before unlocking, since recording the attempt rewrites the config
+        let steps = self.pending_migrations()?;
+        if steps.is_empty() {
+            return Ok(steps);
+        }
+        self.unlock(master_password)?;
+
+        let data = self.load_vault_data()?;
+        let config = self.load_config()?;
+        self.rewrap(&data, master_password, config.cipher, config.kdf_params, config.salt_size)?;
+
+        Ok(steps)
+    }
+
     /// Exports vault data to a file (encrypted)
-    pub fn export(&self, path: &Path) -> Result<()> {
-        let encrypted_data = fs::read(&self.vault_path)?;
-        fs::write(path, encrypted_data)?;
+    ///
+    /// A self-contained export also includes the config (password hash, salt,
+    /// KDF and cipher settings), so [`Vault::restore`] can set up a new vault
+    /// from it.
+    pub fn export(&self, out: &mut impl Write, self_contained: bool) -> Result<()> {
+        self.ensure_writable()?;
+
+        let encrypted_data = Zeroizing::new(self.store.read(Blob::Vault).context("Failed to read vault file")?);
+        if self_contained {
+            let mut config = self.load_config()?;
+            // Failed attempts here shouldn't follow the backup to a new machine
+            config.failed_unlocks = 0;
+            config.last_failed_unlock = None;
+
+            let backup = VaultBackup {
+                format: VAULT_BACKUP_FORMAT.to_string(),
+                version: VAULT_BACKUP_VERSION,
+                config,
+                data: encrypted_data.to_vec(),
+            };
+            out.write_all(&serde_json::to_vec(&backup)?)?;
+        } else {
+            out.write_all(&encrypted_data)?;
+        }
+        out.flush()?;
+        info!("Exported vault");
+        self.audit_export(None);
+        Ok(())
+    }
+
+    /// Sets up the config and vault from a self-contained export when no
+    /// vault exists yet, leaving the vault unlocked; returns the number of
+    /// identities restored
+    pub fn restore(&mut self, mut input: impl Read, master_password: &str) -> Result<usize> {
+        self.ensure_writable()?;
+
+        for blob in [Blob::Config, Blob::Vault] {
+            if self.store.exists(blob) {
+                anyhow::bail!(
+                    "{} already exists; use import to replace the contents of an existing vault",
+                    self.location(blob).display()
+                );
+            }
+        }
+
+        let mut contents = Vec::new();
+        input.read_to_end(&mut contents).context("Failed to read export file")?;
+        let backup = VaultBackup::parse(&contents)?.context(
+            "This export doesn't include the vault config. Only exports made with \
+             `export --self-contained` can be restored where there is no vault",
+        )?;
+        let config = backup.config;
+
+        if !verify_password(master_password, &config.master_password_hash)? {
+            return Err(VaultError::WrongPassword.into());
+        }
+
+        let mut key = derive_key(master_password, &config.salt, &config.kdf_params)?;
+        self.key = Some(SecretKey::new(&mut key));
+        self.cipher = config.cipher;
+        self.aad = config.aad();
+
+        let mut data = match decrypt_import(&backup.data, self.key()?, self.cipher, &self.aad, DAMAGED_BACKUP) {
+            Ok(data) => data,
+            Err(e) => {
+                self.lock();
+                return Err(e);
+            }
+        };
+
+        // The export's log stayed behind, so this vault starts a new one
+        data.audit_head = None;
+        self.remove_stale_log();
+        self.save_config(&config)?;
+        if let Err(e) = self.save_vault_data(&data) {
+            // A config without a vault would make a retry refuse to overwrite it
+            if let Err(remove_err) = self.store.remove(Blob::Config) {
+                warn!("Failed to remove the restored config: {}", remove_err);
+            }
+            return Err(e);
+        }
+        info!("Restored vault from export");
+        Ok(data.identities.len())
+    }
+
+    /// Replaces the vault's contents with `data` from [`Vault::load_import`],
+    /// backing up the current files first
+    pub fn replace_data(&self, mut data: VaultData) -> Result<()> {
+        self.ensure_writable()?;
+
+        // Keep a safety copy of what we're about to overwrite
+        self.backup()?;
+
+        // The log stays this vault's, whatever the export recorded
+        data.audit_head = self.load_vault_data()?.audit_head;
+        self.audit(&mut data, &[(AuditOp::Import, None)]);
+        // Re-encrypted rather than copied, since decrypting may have normalized it
+        self.save_vault_data(&data)?;
+        info!("Imported vault with {} identities", data.identities.len());
+        Ok(())
+    }
+
+    /// Writes one identity to `path`, encrypted under `passphrase` rather than
+    /// the vault key so it can be handed to someone else
+    pub fn export_identity(&self, service: &str, path: &Path, passphrase: &str) -> Result<()> {
+        self.ensure_writable()?;
+
+        let mut identity = self.get_identity(service)?;
+        identity.clear_history();
+        let json = Zeroizing::new(serde_json::to_vec(&identity)?);
+
+        let kdf_params = KdfParams::default();
+        let cipher = Cipher::default();
+        let salt = generate_salt(DEFAULT_SALT_SIZE)?;
+        let key = Zeroizing::new(derive_key(passphrase, &salt, &kdf_params)?);
+
+        let shared = SharedIdentity {
+            format: SHARED_IDENTITY_FORMAT.to_string(),
+            version: SHARED_IDENTITY_VERSION,
+            kdf_params,
+            cipher,
+            salt,
+            data: encrypt(&json, &key, cipher, &[])?,
+        };
+
+        write_private_file(path, &serde_json::to_vec_pretty(&shared)?)
+            .context("Failed to write shared identity file")?;
+        info!("Exported identity '{}' to {}", service, path.display());
+        self.audit_export(Some(service));
+        Ok(())
+    }
+
+    /// Decrypts an identity written by [`Vault::export_identity`] and adds it
+    /// to the vault, returning its service name
+    pub fn import_identity(&self, path: &Path, passphrase: &str, overwrite: bool) -> Result<String> {
+        self.ensure_writable()?;
+
+        let contents = fs::read(path).context("Failed to read shared identity file")?;
+        let shared: SharedIdentity = serde_json::from_slice(&contents)
+            .context("Not a shared identity file")?;
+
+        if shared.format != SHARED_IDENTITY_FORMAT {
+            anyhow::bail!("Not a shared identity file");
+        }
+        if shared.version > SHARED_IDENTITY_VERSION {
+            anyhow::bail!(
+                "Shared identity format version {} is newer than this version of aliaser supports",
+                shared.version
+            );
+        }
+
+        shared.kdf_params.check_limits()?;
+        let key = Zeroizing::new(derive_key(passphrase, &shared.salt, &shared.kdf_params)?);
+        let json = Zeroizing::new(
+            decrypt(&shared.data, &key, shared.cipher, &[]).context("Wrong passphrase or damaged file")?,
+        );
+        let mut identity: Identity = serde_json::from_slice(&json)?;
+        let service = identity.service.trim().to_string();
+        identity.service = service.clone();
+        check_identity(&identity)?;
+        // Files from older versions still carry the sender's history
+        identity.clear_history();
+
+        let mut data = self.load_vault_data()?;
+        if data.identities.contains_key(&service) && !overwrite {
+            anyhow::bail!("Identity for service '{}' already exists", service);
+        }
+
+        data.identities.insert(service.clone(), identity);
+        self.audit(&mut data, &[(AuditOp::Import, Some(&service))]);
+        self.save_vault_data(&data)?;
+
+        Ok(service)
+    }
+
+    /// Copies the current vault and config to timestamped backup files,
+    /// pruning the oldest backups beyond the configured limit
+    pub fn backup(&self) -> Result<Backup> {
+        self.ensure_writable()?;
+        let paths = self.paths()?;
+
+        let timestamp = Utc::now().format(BACKUP_TIMESTAMP_FORMAT).to_string();
+        let backup = Backup {
+            vault_path: backup_path(&paths.vault, &timestamp),
+            config_path: backup_path(&paths.config, &timestamp),
+            log_path: paths.log.exists().then(|| backup_path(&paths.log, &timestamp)),
+            timestamp,
+        };
+
+        fs::copy(&paths.vault, &backup.vault_path)
+            .context("Failed to back up vault file")?;
+        if let Some(log_path) = &backup.log_path {
+            fs::copy(&paths.log, log_path).context("Failed to back up audit log")?;
+        }
+        fs::copy(&paths.config, &backup.config_path)
+            .context("Failed to back up vault config")?;
+
+        info!("Backed up vault to {}", backup.vault_path.display());
+
+        let max_backups = self.load_config()?.max_backups;
+        self.prune_backups(max_backups)?;
+
+        Ok(backup)
+    }
+
+    /// Lists available backups, oldest first
+    pub fn list_backups(&self) -> Result<Vec<Backup>> {
+        let paths = self.paths()?;
+        let dir = paths.vault.parent().context("Vault path has no parent directory")?;
+        let prefix = format!("{}{}", file_name(&paths.vault), BACKUP_SUFFIX);
+
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(dir).context("Failed to read vault directory")? {
+            let name = entry?.file_name();
+            let Some(timestamp) = name.to_str().and_then(|n| n.strip_prefix(&prefix)) else {
+                continue;
+            };
+
+            let config_path = backup_path(&paths.config, timestamp);
+            if !config_path.exists() {
+                continue;
+            }
+
+            let log_path = backup_path(&paths.log, timestamp);
+            backups.push(Backup {
+                timestamp: timestamp.to_string(),
+                vault_path: backup_path(&paths.vault, timestamp),
+                config_path,
+                log_path: log_path.exists().then_some(log_path),
+            });
+        }
+
+        backups.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(backups)
+    }
+
+    /// Lists vault files (including backups) that users other than the
+    /// owner can access, with their current Unix mode bits
+    pub fn loose_permissions(&self) -> Result<Vec<(PathBuf, u32)>> {
+        let vault_path = self.file_path(Blob::Vault)?;
+        let mut paths = vec![
+            vault_path.clone(),
+            self.file_path(Blob::Config)?,
+            self.file_path(Blob::Index)?,
+            self.file_path(Blob::Listing)?,
+            self.file_path(Blob::Log)?,
+        ];
+        if vault_path.parent().is_some_and(Path::exists) {
+            for backup in self.list_backups()? {
+                paths.push(backup.vault_path);
+                paths.push(backup.config_path);
+                paths.extend(backup.log_path);
+            }
+        }
+
+        let mut loose = Vec::new();
+        #[cfg(unix)]
+        for path in paths.into_iter().filter(|path| path.exists()) {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path)
+                .with_context(|| format!("Failed to read permissions of {}", path.display()))?
+                .permissions()
+                .mode()
+                & 0o777;
+            if mode & 0o077 != 0 {
+                loose.push((path, mode));
+            }
+        }
+        #[cfg(not(unix))]
+        drop(paths);
+        Ok(loose)
+    }
+
+    /// Makes every file from [`Vault::loose_permissions`] owner-only (0600)
+    pub fn restrict_permissions(&self) -> Result<usize> {
+        self.ensure_writable()?;
+
+        let loose = self.loose_permissions()?;
+        for (path, _) in &loose {
+            set_owner_only(path).with_context(|| format!("Failed to change permissions of {}", path.display()))?;
+        }
+        Ok(loose.len())
+    }
+
+    /// Checks that new files can be created next to the vault, as every
+    /// write does (it writes a temporary file and renames it into place)
+    pub fn check_directory_writable(&self) -> Result<()> {
+        let vault_path = self.file_path(Blob::Vault)?;
+        let dir = vault_path.parent().context("Vault path has no parent directory")?;
+        let probe = dir.join(format!(".aliaser-probe-{}", std::process::id()));
+        fs::write(&probe, b"").with_context(|| format!("Cannot create files in {}", dir.display()))?;
+        fs::remove_file(&probe).with_context(|| format!("Cannot remove files in {}", dir.display()))?;
+        Ok(())
+    }
+
+    /// Restores the vault and config from a backup, backing up the current
+    /// files first so the restore itself can be undone
+    pub fn restore_backup(&self, timestamp: &str) -> Result<()> {
+        self.ensure_writable()?;
+
+        let backup = self
+            .list_backups()?
+            .into_iter()
+            .find(|b| b.timestamp == timestamp)
+            .context(format!("Backup '{}' not found", timestamp))?;
+
+        let vault = fs::read(&backup.vault_path).context("Failed to read backed up vault file")?;
+        let config = fs::read(&backup.config_path).context("Failed to read backed up vault config")?;
+        // The current log may be under another key, and would no longer
+        // match the head the restored vault recorded
+        let log = match &backup.log_path {
+            Some(path) => Some(fs::read(path).context("Failed to read backed up audit log")?),
+            None => None,
+        };
+
+        // Read first: backing up prunes, which may delete the backup being
+        // restored when it is the oldest
+        if self.is_initialized() {
+            self.backup()?;
+        }
+        // Config last, as for a snapshot, so an interrupted restore leaves
+        // the old config to be restored over again
+        self.invalidate_cache();
+        let snapshot = VaultSnapshot {
+            log,
+            ..VaultSnapshot::new(vault, config)?
+        };
+        snapshot.write_to(self.store.as_ref())?;
+
         Ok(())
     }
 
-    /// Imports vault data from a file
-    pub fn import(&self, path: &Path) -> Result<()> {
-        let encrypted_data = fs::read(path)?;
-        
-        // Verify it can be decrypted
-        let key = self.key.as_ref().context("Vault not unlocked")?;
-        let decrypted = decrypt(&encrypted_data, key)?;
-        let _: VaultData = serde_json::from_slice(&decrypted)?;
+    /// The vault file, config and audit log as stored, readable without
+    /// unlocking
+    #[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+    pub fn snapshot(&self) -> Result<VaultSnapshot> {
+        VaultSnapshot::read_from(self.store.as_ref())?.ok_or_else(|| VaultError::NotInitialized.into())
+    }
+
+    /// Replaces the vault file, config and audit log with `snapshot`'s,
+    /// backing up the current ones first; the vault stays locked until
+    /// unlocked again against the new config
+    #[cfg_attr(not(any(feature = "sync", feature = "webdav")), allow(dead_code))]
+    pub fn replace_with_snapshot(&mut self, snapshot: &VaultSnapshot) -> Result<()> {
+        self.ensure_writable()?;
+
+        if self.is_initialized() {
+            self.backup()?;
+        }
+        self.lock();
 
-        // Save to vault
-        fs::write(&self.vault_path, encrypted_data)?;
+        snapshot.write_to(self.store.as_ref())?;
+        info!("Replaced vault with revision {}", snapshot.revision);
         Ok(())
     }
 
+    /// Sets how many automatic backups are kept
+    pub fn set_max_backups(&self, max_backups: usize) -> Result<()> {
+        if max_backups == 0 {
+            anyhow::bail!("At least one backup must be kept");
+        }
+        let mut config = self.load_config()?;
+        config.max_backups = max_backups;
+        self.save_config(&config)?;
+        self.prune_backups(max_backups)
+    }
+
+    /// Counts the identities currently stored in the vault
+    pub fn identity_count(&self) -> Result<usize> {
+        Ok(self.load_vault_data()?.identities.len())
+    }
+
+    /// Decrypts an export without importing it
+    ///
+    /// A plain export only decrypts with this vault's key. A self-contained
+    /// export made under this vault's key needs nothing more; one made
+    /// under another master password needs `export_password`, the password
+    /// it was exported with, and fails with
+    /// [`VaultError::ExportPasswordNeeded`] without it.
+    pub fn load_import(&self, mut input: impl Read, export_password: Option<&str>) -> Result<VaultData> {
+        let mut contents = Vec::new();
+        input.read_to_end(&mut contents).context("Failed to read export file")?;
+
+        if is_shared_identity(&contents) {
+            anyhow::bail!("This file holds a single identity from `export-one`; import it with `aliaser import-one`");
+        }
+        let Some(backup) = VaultBackup::parse(&contents)? else {
+            return decrypt_import(
+                &contents,
+                self.key()?,
+                self.cipher,
+                &self.aad,
+                "This export doesn't decrypt with this vault's key. A plain export only imports into \
+                 the vault it was made from, under the same master password; to move data between \
+                 vaults, export with --self-contained",
+            );
+        };
+
+        let config = backup.config;
+        let current = self.load_config()?;
+        if config.salt == current.salt && config.kdf_params == current.kdf_params {
+            // Made under this vault's key, unless the password has changed since
+            if let Ok(data) = decrypt_import(&backup.data, self.key()?, config.cipher, &config.aad(), DAMAGED_BACKUP) {
+                return Ok(data);
+            }
+        }
+
+        let password = export_password.ok_or(VaultError::ExportPasswordNeeded)?;
+        if !verify_password(password, &config.master_password_hash)? {
+            return Err(VaultError::WrongPassword.into());
+        }
+        let key = Zeroizing::new(derive_key(password, &config.salt, &config.kdf_params)?);
+        decrypt_import(&backup.data, &key, config.cipher, &config.aad(), DAMAGED_BACKUP)
+    }
+
+    /// Compares the current vault against `incoming`, by service name and
+    /// content
+    pub fn diff(&self, incoming: &VaultData) -> Result<VaultDiff> {
+        let current = self.load_vault_data()?;
+        let mut diff = VaultDiff::default();
+
+        for (service, identity) in &incoming.identities {
+            match current.identities.get(service) {
+                None => diff.added.push(service.clone()),
+                Some(existing) if existing.content_hash() != identity.content_hash() => {
+                    diff.updated.push((service.clone(), existing.changed_fields(identity)))
+                }
+                Some(_) => diff.unchanged.push(service.clone()),
+            }
+        }
+        diff.removed = current
+            .identities
+            .keys()
+            .filter(|service| !incoming.identities.contains_key(*service))
+            .cloned()
+            .collect();
+
+        diff.added.sort();
+        diff.updated.sort();
+        diff.removed.sort();
+        diff.unchanged.sort();
+
+        Ok(diff)
+    }
+
     // Private helper methods
 
     fn load_config(&self) -> Result<VaultConfig> {
-        let config_json = fs::read_to_string(&self.config_path)
-            .context("Failed to read vault config")?;
-        let config: VaultConfig = serde_json::from_str(&config_json)
-            .context("Failed to parse vault config")?;
+        debug!("Loading vault config from {}", self.location(Blob::Config).display());
+        let config_json = self.read_config_json()?;
+        let config: VaultConfig = serde_json::from_str(&config_json).context(format!(
+            "Vault config {} appears corrupted. Restore it with `aliaser restore-backup` (see `aliaser backups`)",
+            self.location(Blob::Config).display()
+        ))?;
         Ok(config)
     }
 
-    fn load_vault_data(&self) -> Result<VaultData> {
-        let key = self.key.as_ref().context("Vault not unlocked")?;
-
-        let encrypted_data = fs::read(&self.vault_path)
-            .context("Failed to read vault file")?;
+    fn read_config_json(&self) -> Result<Zeroizing<String>> {
+        let bytes = self.store.read(Blob::Config).context("Failed to read vault config")?;
+        let json = String::from_utf8(bytes).map_err(|e| Zeroizing::new(e.into_bytes()));
+        Ok(Zeroizing::new(json.ok().context("Vault config appears corrupted")?))
+    }
 
-        let decrypted = decrypt(&encrypted_data, key)
-            .context("Failed to decrypt vault")?;
+    /// The file holding `blob`; fails for stores that don't use files
+    fn file_path(&self, blob: Blob) -> Result<PathBuf> {
+        self.store.path(blob).with_context(|| format!("The {} isn't stored in a file", blob))
+    }
 
-        let vault_data: VaultData = serde_json::from_slice(&decrypted)
-            .context("Failed to parse vault data")?;
+    /// Where `blob` is kept, for messages
+    fn location(&self, blob: Blob) -> PathBuf {
+        self.store.path(blob).unwrap_or_else(|| PathBuf::from(format!("<{}>", blob)))
+    }
 
-        Ok(vault_data)
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            anyhow::bail!("Vault is in read-only mode; refusing to write");
+        }
+        Ok(())
     }
 
-    fn save_vault_data(&self, data: &VaultData) -> Result<()> {
-        let key = self.key.as_ref().context("Vault not unlocked")?;
+    /// Writes a new salt, password hash and KDF parameters to the config and
+    /// re-encrypts `data` under the key derived from them, backing up the
+    /// old files first
+    fn rewrap(
+        &mut self,
+        data: &VaultData,
+        password: &str,
+        cipher: Cipher,
+        kdf_params: KdfParams,
+        salt_size: usize,
+    ) -> Result<()> {
+        // Stores without files can't be backed up; the write order below
+        // still leaves the old vault usable if re-encrypting it fails
+        let backup = match self.store.path(Blob::Vault) {
+            Some(_) => Some(self.backup()?),
+            None => None,
+        };
+        if let Some(backup) = &backup {
+            info!("Re-encrypting vault; previous files kept as backup {}", backup.timestamp);
+        }
 
-        let json = serde_json::to_string(data)
-            .context("Failed to serialize vault data")?;
+        let mut config = self.load_config()?;
+        // Encrypted with the key about to be replaced, so carried over below
+        let log = self.read_audit_log();
 
-        let encrypted = encrypt(json.as_bytes(), key)
-            .context("Failed to encrypt vault data")?;
+        // Generate new salt and hash
+        let new_salt = generate_salt(salt_size)?;
+        let new_hash = hash_password(password, &kdf_params)?;
+
+        // Derive new key
+        let mut new_key = derive_key(password, &new_salt, &kdf_params)?;
+
+        // Update config
+        config.master_password_hash = new_hash;
+        config.salt = new_salt.clone();
+        config.salt_size = salt_size;
+        config.kdf_params = kdf_params;
+        config.cipher = cipher;
+        config.version = env!("CARGO_PKG_VERSION").to_string();
+        config.config_bound = true;
+        config.revision += 1;
+
+        // Re-encrypt the vault before saving the config, so a failed write
+        // leaves the old config still matching the old vault
+        let old_key = self.key.replace(SecretKey::new(&mut new_key));
+        let old_cipher = std::mem::replace(&mut self.cipher, cipher);
+        let old_aad = std::mem::replace(&mut self.aad, config.aad());
+        if let Err(e) = self.write_vault_data(data) {
+            self.key = old_key;
+            self.cipher = old_cipher;
+            self.aad = old_aad;
+            return Err(e);
+        }
+        self.save_config(&config).with_context(|| match &backup {
+            Some(backup) => format!(
+                "The vault was re-encrypted but its config wasn't saved; restore backup {}",
+                backup.timestamp
+            ),
+            None => "The vault was re-encrypted but its config wasn't saved".to_string(),
+        })?;
+
+        match log {
+            Ok(log) => {
+                if let Err(e) = self.write_audit_log(&log) {
+                    warn!("Failed to re-encrypt the audit log: {:#}", e);
+                }
+            }
+            Err(e) => warn!("Leaving the audit log as it was: {:#}", e),
+        }
+
+        Ok(())
+    }
 
-        fs::write(&self.vault_path, encrypted)
-            .context("Failed to write vault file")?;
+    fn save_config(&self, config: &VaultConfig) -> Result<()> {
+        self.ensure_writable()?;
 
+        let config_json = Zeroizing::new(serde_json::to_string_pretty(config)?);
+        self.store
+            .write(Blob::Config, config_json.as_bytes())
+            .context("Failed to write vault config")?;
         Ok(())
     }
+
+    /// Adds one to the config's revision without otherwise rewriting it, so
+    /// fields an older config lacks stay missing for `migrate` to report
+    fn bump_revision(&self) -> Result<()> {
+        let json = self.read_config_json()?;
+        let mut config: serde_json::Value = serde_json::from_str(&json).context("Vault config appears corrupted")?;
+        let fields = config.as_object_mut().context("Vault config appears corrupted")?;
+        let revision = fields.get("revision").and_then(serde_json::Value::as_u64).unwrap_or(0);
+        fields.insert("revision".to_string(), (revision + 1).into());
+
+        let config_json = Zeroizing::new(serde_json::to_string_pretty(&config)?);
+        self.store
+            .write(Blob::Config, config_json.as_bytes())
+            .context("Failed to write vault config")?;
+        Ok(())
+    }
+
+    fn prune_backups(&self, keep: usize) -> Result<()> {
+        self.ensure_writable()?;
+
+        let backups = self.list_backups()?;
+        let excess = backups.len().saturating_sub(keep);
+
+        for backup in &backups[..excess] {
+            debug!("Pruning backup {}", backup.timestamp);
+            fs::remove_file(&backup.vault_path)?;
+            fs::remove_file(&backup.config_path)?;
+            if let Some(log_path) = &backup.log_path {
+                fs::remove_file(log_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key(&self) -> Result<&[u8; 32]> {
+        Ok(self.key.as_deref().ok_or(VaultError::NotUnlocked)?)
+    }
+
+    /// Additional data for the index, distinct from the vault's so neither
+    /// file can be passed off as the other
+    fn index_aad(&self) -> Vec<u8> {
+        [self.aad.as_slice(), b"index"].concat()
+    }
+
+    fn listing_aad(&self) -> Vec<u8> {
+        [self.aad.as_slice(), b"last-list"].concat()
+    }
+
+    fn log_aad(&self) -> Vec<u8> {
+        [self.aad.as_slice(), b"audit-log"].concat()
+    }
+
+    /// Reads the audit log, failing if it doesn't reach the head the vault
+    /// recorded for it
+    fn read_audit_log(&self) -> Result<AuditLog> {
+        let head = self.load_vault_data()?.audit_head;
+        if !self.store.exists(Blob::Log) {
+            if let Some(head) = head {
+                anyhow::bail!(
+                    "The audit log {} is missing, but the vault recorded {} entries in it",
+                    self.location(Blob::Log).display(),
+                    head.count
+                );
+            }
+            return Ok(AuditLog::default());
+        }
+        let encrypted = self.store.read(Blob::Log).context("Failed to read audit log")?;
+        let json = Zeroizing::new(
+            decrypt(&encrypted, self.key()?, self.cipher, &self.log_aad())
+                .context("Audit log can't be decrypted with this vault's key")?,
+        );
+        let log: AuditLog = serde_json::from_slice(&json).context("Audit log appears corrupted")?;
+        if let Some(head) = head.filter(|head| !log.reaches(head)) {
+            anyhow::bail!(
+                "The audit log has {} entries and doesn't match the {} the vault recorded; \
+                 it has been cut short, rolled back or replaced",
+                log.entries().len(),
+                head.count
+            );
+        }
+        Ok(log)
+    }
+
+    fn write_audit_log(&self, log: &AuditLog) -> Result<()> {
+        let json = Zeroizing::new(serde_json::to_vec(log)?);
+        let encrypted = encrypt(&json, self.key()?, self.cipher, &self.log_aad())?;
+        self.store.write(Blob::Log, &encrypted).context("Failed to write audit log")
+    }
+
+    /// Appends `changes` to the audit log and records its new head in
+    /// `data`, to be saved with the change in one write. The log goes first,
+    /// since one running past the saved head still verifies and one short of
+    /// it doesn't. A log that can't be updated only warns, leaving the head
+    /// as it was; an unreadable one is left alone rather than replaced.
+    fn audit(&self, data: &mut VaultData, changes: &[(AuditOp, Option<&str>)]) {
+        let result = self.read_audit_log().and_then(|mut log| {
+            for (op, service) in changes {
+                log.append(*op, *service);
+            }
+            self.write_audit_log(&log)?;
+            data.audit_head = log.head();
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Failed to update the audit log: {:#}", e);
+        }
+    }
+
+    /// Records an export, which changes nothing else, so saves the vault
+    /// for the log's new head itself
+    fn audit_export(&self, service: Option<&str>) {
+        let result = self.load_vault_data().and_then(|mut data| {
+            self.audit(&mut data, &[(AuditOp::Export, service)]);
+            self.save_vault_data(&data)
+        });
+        if let Err(e) = result {
+            warn!("Failed to record the export in the vault: {:#}", e);
+        }
+    }
+
+    fn remove_stale_log(&self) {
+        if self.store.exists(Blob::Log) {
+            info!("Removing the audit log of a previous vault at {}", self.location(Blob::Log).display());
+            if let Err(e) = self.store.remove(Blob::Log) {
+                warn!("Failed to remove the old audit log: {}", e);
+            }
+        }
+    }
+
+    /// Header and nonce at the start of the current vault file, which
+    /// identify this version of it
+    fn vault_nonce(&self) -> Result<Vec<u8>> {
+        self.store
+            .read_prefix(Blob::Vault, ENVELOPE_HEADER_LEN + self.cipher.nonce_len())
+            .context("Failed to read vault file")
+    }
+
+    /// Returns the indexed summaries, or `None` if there is no index or it
+    /// was built from a different version of the vault file
+    fn read_index(&self, key: &[u8; 32]) -> Result<Option<Vec<IdentitySummary>>> {
+        if !self.store.exists(Blob::Index) {
+            return Ok(None);
+        }
+
+        let encrypted = self.store.read(Blob::Index).context("Failed to read index file")?;
+        let decrypted = Zeroizing::new(decrypt(&encrypted, key, self.cipher, &self.index_aad())?);
+        let index: VaultIndex = serde_json::from_slice(&decrypted)?;
+
+        if index.vault_nonce != self.vault_nonce()? {
+            return Ok(None);
+        }
+        debug!("Loaded index with {} identities", index.identities.len());
+        Ok(Some(index.identities))
+    }
+
+    fn write_index(&self, identities: &[IdentitySummary]) -> Result<()> {
+        let key = self.key()?;
+        let index = VaultIndex {
+            vault_nonce: self.vault_nonce()?,
+            identities: identities.to_vec(),
+        };
+
+        let json = Zeroizing::new(serde_json::to_vec(&index)?);
+        let encrypted = encrypt(&json, key, self.cipher, &self.index_aad())?;
+        self.store.write(Blob::Index, &encrypted).context("Failed to write index file")?;
+        Ok(())
+    }
+
+    fn invalidate_cache(&self) {
+        // Dropping the identities zeroizes them
+        self.cache.borrow_mut().take();
+    }
+
+    fn load_vault_data(&self) -> Result<VaultData> {
+        let key = self.key()?;
+
+        if let Some(data) = self.cache.borrow().as_ref() {
+            return Ok(data.clone());
+        }
+
+        debug!("Reading vault file {}", self.location(Blob::Vault).display());
+        let encrypted_data = self.store.read(Blob::Vault)
+            .context("Failed to read vault file")?;
+
+        // The key is only set after the password hash verified, so a failure
+        // from here on means the file is damaged rather than the password wrong.
+        // Plaintext buffers are scrubbed on drop so secrets don't linger in freed memory
+        let decrypted = Zeroizing::new(
+            decrypt_envelope(&encrypted_data, key, self.cipher, &self.aad)
+                .map_err(|e| corrupted_vault(&self.location(Blob::Vault), e))?,
+        );
+        let _locked = LockGuard::new(&decrypted);
+
+        let vault_data: VaultData = serde_json::from_slice(&decrypted)
+            .map_err(|e| corrupted_vault(&self.location(Blob::Vault), e.into()))?;
+        debug!("Loaded vault with {} identities", vault_data.identities.len());
+
+        *self.cache.borrow_mut() = Some(vault_data.clone());
+        Ok(vault_data)
+    }
+
+    fn save_vault_data(&self, data: &VaultData) -> Result<()> {
+        self.write_vault_data(data)?;
+        self.bump_revision()
+    }
+
+    /// Encrypts and writes `data` without counting a new revision, for
+    /// rewrites that belong to a change already counted
+    fn write_vault_data(&self, data: &VaultData) -> Result<()> {
+        self.ensure_writable()?;
+
+        let key = self.key()?;
+        self.invalidate_cache();
+
+        let json = Zeroizing::new(
+            serde_json::to_vec(data).context("Failed to serialize vault data")?,
+        );
+        let _locked = LockGuard::new(&json);
+
+        let encrypted = encrypt_envelope(&json, key, self.cipher, &self.aad)
+            .context("Failed to encrypt vault data")?;
+
+        self.store.write(Blob::Vault, &encrypted).context("Failed to write vault file")?;
+        debug!("Saved vault with {} identities", data.identities.len());
+
+        // A stale index is detected and rebuilt on the next listing, so this
+        // failing shouldn't fail the save
+        if let Err(e) = self.write_index(&summarize(data)) {
+            warn!("Failed to update index: {:#}", e);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks what every stored identity needs: a service name and, unless its
+/// template has no login, a username
+fn check_identity(identity: &Identity) -> Result<()> {
+    if identity.service.trim().is_empty() {
+        anyhow::bail!("Service name cannot be empty");
+    }
+    // Cards and secure notes have no login to fill in
+    let needs_login = identity.template.is_none_or(Template::has_login);
+    if needs_login && identity.credentials.username.trim().is_empty() {
+        anyhow::bail!("Username cannot be empty");
+    }
+    Ok(())
+}
+
+/// Writes `data` to `path`, readable by the owner only; an existing file is
+/// made owner-only before anything is written to it
+pub fn write_private_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(data)
+}
+
+/// Fails with `fix` in the message unless a file can be created next to `path`
+fn check_writable_dir(path: &Path, fix: &str) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let probe = dir.join(format!(".aliaser-write-test.{}", std::process::id()));
+    if let Err(e) = write_private_file(&probe, &[]) {
+        anyhow::bail!("Cannot write to {} ({}); {}", dir.display(), e, fix);
+    }
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Sets `path` to mode 0600, so a file that already existed with looser
+/// permissions is tightened too; does nothing off Unix
+fn set_owner_only(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Orders two dotted version strings numerically, treating missing or
+/// non-numeric parts as 0 (so "0.3" == "0.3.0")
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parts = |v: &str| -> Vec<u64> {
+        v.split(['.', '-', '+']).take(3).map(|part| part.parse().unwrap_or(0)).collect()
+    };
+    let (mut a, mut b) = (parts(a), parts(b));
+    a.resize(3, 0);
+    b.resize(3, 0);
+    a.cmp(&b)
+}
+
+/// Decrypts and checks exported vault data, explaining a failure to
+/// decrypt with `mismatch` since the usual cause is the wrong key
+fn decrypt_import(encrypted_data: &[u8], key: &[u8; 32], cipher: Cipher, aad: &[u8], mismatch: &str) -> Result<VaultData> {
+    let decrypted = Zeroizing::new(decrypt_envelope(encrypted_data, key, cipher, aad).context(mismatch.to_string())?);
+    let _locked = LockGuard::new(&decrypted);
+    let mut vault_data: VaultData = serde_json::from_slice(&decrypted)?;
+    for change in vault_data.validate_and_normalize()? {
+        warn!("Import: {}", change);
+    }
+    Ok(vault_data)
+}
+
+/// Whether `contents` is a single identity from [`Vault::export_identity`]
+fn is_shared_identity(contents: &[u8]) -> bool {
+    serde_json::from_slice::<SharedIdentity>(contents).is_ok_and(|shared| shared.format == SHARED_IDENTITY_FORMAT)
+}
+
+/// Summaries of every identity in `data`, sorted by service name
+fn summarize(data: &VaultData) -> Vec<IdentitySummary> {
+    let mut summaries: Vec<IdentitySummary> = data
+        .identities
+        .iter()
+        .map(|(service, identity)| IdentitySummary::new(service, identity))
+        .collect();
+    summaries.sort_by(|a, b| a.service.cmp(&b.service));
+    summaries
+}
+
+/// Returns up to [`MAX_SUGGESTIONS`] service names close to `query`, closest
+/// first. Comparison ignores case; a name qualifies if it is within a third
+/// of the query's length in edits (at least 1, so typos in short names count).
+fn suggest<'a>(query: &str, services: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let query = query.to_lowercase();
+    let threshold = (query.chars().count() / 3).max(1);
+
+    let mut candidates: Vec<(usize, &String)> = services
+        .map(|service| (levenshtein(&query, &service.to_lowercase()), service))
+        .filter(|(distance, _)| *distance <= threshold)
+        .collect();
+    candidates.sort();
+
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, service)| service.clone())
+        .collect()
+}
+
+/// Edit distance between two strings, counted in characters
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Matches a service name against a pattern. Patterns containing `*` or `?`
+/// are globs over the whole name; anything else is a substring match.
+pub fn matches_pattern(pattern: &str, service: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return service.contains(pattern);
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = service.chars().collect();
+
+    // Two pointers, backtracking only to just after the latest `*`: a later
+    // star can match anything an earlier one could, so earlier ones never
+    // need another try and the match stays linear in practice
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    // Let the star take one more character and try again
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn corrupted_vault(path: &Path, cause: anyhow::Error) -> anyhow::Error {
+    VaultError::Corrupted {
+        path: path.to_path_buf(),
+        source: cause.into(),
+    }
+    .into()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn backup_path(path: &Path, timestamp: &str) -> PathBuf {
+    path.with_file_name(format!("{}{}{}", file_name(path), BACKUP_SUFFIX, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KdfAlgorithm;
+    use crate::generator;
+    use crate::identity::{Credentials, IdentityEdit, MAX_PASSWORD_HISTORY};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const PASSWORD: &str = "correct horse battery";
+
+    /// A scratch directory removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "aliaser-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Keeps a vault's blobs in memory; clones share them, so a clone can
+    /// reopen the same vault
+    #[derive(Clone, Default)]
+    struct MemoryStore(Rc<RefCell<HashMap<Blob, Vec<u8>>>>);
+
+    impl VaultStore for MemoryStore {
+        fn read(&self, blob: Blob) -> io::Result<Vec<u8>> {
+            self.0.borrow().get(&blob).cloned().ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        fn write(&self, blob: Blob, data: &[u8]) -> io::Result<()> {
+            self.0.borrow_mut().insert(blob, data.to_vec());
+            Ok(())
+        }
+
+        fn exists(&self, blob: Blob) -> bool {
+            self.0.borrow().contains_key(&blob)
+        }
+
+        fn remove(&self, blob: Blob) -> io::Result<()> {
+            self.0.borrow_mut().remove(&blob).map(drop).ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        fn path(&self, _blob: Blob) -> Option<PathBuf> {
+            None
+        }
+    }
+
+    fn test_vault(dir: &TempDir) -> Vault {
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap();
+        vault
+    }
+
+    fn test_identity(service: &str) -> Identity {
+        Identity::new(
+            service.to_string(),
+            Credentials {
+                username: "alice".to_string(),
+                password: "hunter22".to_string(),
+                email: None,
+                alias: None,
+                recovery_codes: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_rekey_changes_ciphertext_and_keeps_data() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let old_vault = fs::read(&vault.paths().unwrap().vault).unwrap();
+        let old_salt = vault.load_config().unwrap().salt;
+
+        vault.rekey(PASSWORD, None, None).unwrap();
+
+        assert_ne!(fs::read(&vault.paths().unwrap().vault).unwrap(), old_vault);
+        assert_ne!(vault.load_config().unwrap().salt, old_salt);
+
+        vault.unlock(PASSWORD).unwrap();
+        let identity = vault.get_identity("github").unwrap();
+        assert_eq!(identity.credentials.password, "hunter22");
+    }
+
+    /// Files on disk, except that vault writes fail once `fail` is set
+    struct FailingVaultStore {
+        inner: FileStore,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl VaultStore for FailingVaultStore {
+        fn read(&self, blob: Blob) -> io::Result<Vec<u8>> {
+            self.inner.read(blob)
+        }
+
+        fn write(&self, blob: Blob, data: &[u8]) -> io::Result<()> {
+            if blob == Blob::Vault && self.fail.get() {
+                return Err(io::Error::other("disk full"));
+            }
+            self.inner.write(blob, data)
+        }
+
+        fn exists(&self, blob: Blob) -> bool {
+            self.inner.exists(blob)
+        }
+
+        fn remove(&self, blob: Blob) -> io::Result<()> {
+            self.inner.remove(blob)
+        }
+
+        fn path(&self, blob: Blob) -> Option<PathBuf> {
+            self.inner.path(blob)
+        }
+    }
+
+    #[test]
+    fn test_failed_password_change_keeps_old_password() {
+        let dir = TempDir::new();
+        let fail = Rc::new(Cell::new(false));
+        let store = FailingVaultStore {
+            inner: FileStore::new(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE)),
+            fail: Rc::clone(&fail),
+        };
+        let mut vault = Vault::with_store(Box::new(store));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        fail.set(true);
+        assert!(vault.change_master_password(PASSWORD, "new password", None).is_err());
+        assert_eq!(vault.list_backups().unwrap().len(), 1);
+        assert_eq!(vault.get_identity("github").unwrap().credentials.password, "hunter22");
+
+        let mut reopened = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        assert!(reopened.unlock("new password").is_err());
+        reopened.unlock(PASSWORD).unwrap();
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.password, "hunter22");
+    }
+
+    #[test]
+    fn test_convert_switches_cipher_and_kdf() {
+        let dir = TempDir::new();
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::Aes256Gcm, DEFAULT_SALT_SIZE).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        // Far below any profile, to keep the test fast
+        let scrypt = KdfParams {
+            algorithm: KdfAlgorithm::Scrypt,
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let converted = vault.convert(PASSWORD, Some(Cipher::ChaCha20Poly1305), Some(scrypt)).unwrap();
+        assert_eq!(converted, (Cipher::ChaCha20Poly1305, scrypt));
+        assert_eq!(vault.list_backups().unwrap().len(), 1);
+
+        let paths = vault.paths().unwrap();
+        let mut reopened = Vault::with_paths(paths.vault.clone(), paths.config.clone());
+        reopened.unlock(PASSWORD).unwrap();
+        let config = reopened.load_config().unwrap();
+        assert_eq!(config.cipher, Cipher::ChaCha20Poly1305);
+        assert_eq!(config.kdf_params, scrypt);
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.password, "hunter22");
+
+        // Leaving one choice out keeps what the vault already uses
+        reopened.convert(PASSWORD, Some(Cipher::Aes256Gcm), None).unwrap();
+        reopened.convert(PASSWORD, None, Some(KdfParams::default())).unwrap();
+
+        let mut reopened = Vault::with_paths(paths.vault, paths.config);
+        reopened.unlock(PASSWORD).unwrap();
+        let config = reopened.load_config().unwrap();
+        assert_eq!(config.cipher, Cipher::Aes256Gcm);
+        assert_eq!(config.kdf_params, KdfParams::default());
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.password, "hunter22");
+        assert!(reopened.convert("wrong password", Some(Cipher::XChaCha20Poly1305), None).is_err());
+    }
+
+    #[test]
+    fn test_unlock_uses_cipher_from_config() {
+        let dir = TempDir::new();
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::ChaCha20Poly1305, DEFAULT_SALT_SIZE).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let mut reopened = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        reopened.unlock(PASSWORD).unwrap();
+
+        assert_eq!(reopened.cipher, Cipher::ChaCha20Poly1305);
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.username, "alice");
+    }
+
+    #[test]
+    fn test_salt_size_from_config() {
+        let dir = TempDir::new();
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), 64).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let mut reopened = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        reopened.unlock(PASSWORD).unwrap();
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.username, "alice");
+
+        let config = reopened.load_config().unwrap();
+        assert_eq!((config.salt.len(), config.salt_size), (64, 64));
+
+        // Password changes keep the configured size; rekey can switch it
+        reopened.change_master_password(PASSWORD, "correct horse 2", None).unwrap();
+        assert_eq!(reopened.load_config().unwrap().salt.len(), 64);
+        reopened.rekey("correct horse 2", None, Some(16)).unwrap();
+        let config = reopened.load_config().unwrap();
+        assert_eq!((config.salt.len(), config.salt_size), (16, 16));
+
+        let mut short = Vault::with_paths(dir.0.join("short.enc"), dir.0.join("short.json"));
+        assert!(short.initialize(PASSWORD, KdfParams::default(), Cipher::default(), 8).is_err());
+    }
+
+    #[test]
+    fn test_initialize_into_missing_directory() {
+        let dir = TempDir::new();
+        let missing = dir.0.join("missing");
+
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), missing.join(CONFIG_FILE));
+        let err = vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap_err();
+        assert!(err.to_string().contains(&format!("Cannot write to {}", missing.display())), "{}", err);
+        assert!(err.to_string().contains("--config"), "{}", err);
+
+        let mut vault = Vault::with_paths(missing.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        assert!(vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).is_err());
+        assert!(!dir.0.join(CONFIG_FILE).exists());
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_config_kept_apart_from_vault() {
+        let dir = TempDir::new();
+        let config_dir = dir.0.join("elsewhere");
+        fs::create_dir(&config_dir).unwrap();
+        let config_path = config_dir.join("custom.config");
+        let vault_path = dir.0.join("custom.vault");
+
+        let mut vault = Vault::with_paths(vault_path.clone(), config_path.clone());
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+        assert!(config_path.exists());
+        assert!(!dir.0.join(CONFIG_FILE).exists());
+
+        let mut reopened = Vault::with_paths(vault_path.clone(), config_path.clone());
+        reopened.unlock(PASSWORD).unwrap();
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.username, "alice");
+        reopened.change_master_password(PASSWORD, "correct horse 2", None).unwrap();
+
+        let mut reopened = Vault::with_paths(vault_path.clone(), config_path);
+        assert!(reopened.unlock(PASSWORD).is_err());
+        reopened.unlock("correct horse 2").unwrap();
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.username, "alice");
+
+        // The vault is useless with the default config location
+        assert!(!Vault::with_paths(vault_path, dir.0.join(CONFIG_FILE)).is_initialized());
+    }
+
+    #[test]
+    fn test_config_without_cipher_is_aes_gcm() {
+        let config: VaultConfig = serde_json::from_str(
+            r#"{"master_password_hash":"","salt":[],"version":"0.1.0"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cipher, Cipher::Aes256Gcm);
+    }
+
+    #[test]
+    fn test_vault_paired_with_other_config_fails_to_decrypt() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let mut config = vault.load_config().unwrap();
+        config.version = "0.0.1".to_string();
+        vault.save_config(&config).unwrap();
+
+        vault.unlock(PASSWORD).unwrap();
+        assert!(vault.get_identity("github").is_err());
+    }
+
+    #[test]
+    fn test_unbound_vault_is_bound_on_rekey() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        // Rewrite as a vault from before configs were bound
+        let data = vault.load_vault_data().unwrap();
+        let mut config = vault.load_config().unwrap();
+        config.config_bound = false;
+        vault.save_config(&config).unwrap();
+        vault.aad = Vec::new();
+        vault.save_vault_data(&data).unwrap();
+
+        vault.unlock(PASSWORD).unwrap();
+        assert!(vault.get_identity("github").is_ok());
+
+        vault.rekey(PASSWORD, None, None).unwrap();
+        assert!(vault.load_config().unwrap().config_bound);
+        vault.unlock(PASSWORD).unwrap();
+        assert!(vault.get_identity("github").is_ok());
+    }
+
+    #[test]
+    fn test_attach_enforces_size_and_unique_names() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let attachment = |name: &str, size: usize| Attachment {
+            name: name.to_string(),
+            mime: "text/plain".to_string(),
+            data: vec![b'k'; size],
+        };
+
+        vault.attach("github", attachment("id_ed25519", 400)).unwrap();
+        assert!(vault.attach("github", attachment("id_ed25519", 10)).is_err());
+        assert!(vault.attach("github", attachment("big.pem", MAX_ATTACHMENT_SIZE + 1)).is_err());
+
+        let identity = vault.get_identity("github").unwrap();
+        assert_eq!(identity.attachments.len(), 1);
+        assert_eq!(identity.attachments[0].data.len(), 400);
+    }
+
+    #[test]
+    fn test_find_incomplete() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        // Adding and updating reject blank usernames, so import them the way
+        // a partial export would have left them
+        let mut data = vault.load_vault_data().unwrap();
+        for (service, username, password) in [("empty", "", ""), ("no-password", "bob", "")] {
+            let mut identity = test_identity(service);
+            identity.credentials.username = username.to_string();
+            identity.credentials.password = password.to_string();
+            data.identities.insert(service.to_string(), identity);
+        }
+        vault.replace_data(data).unwrap();
+
+        assert_eq!(vault.find_incomplete().unwrap(), vec!["empty".to_string()]);
+    }
+
+    #[test]
+    fn test_unlock_policy_delays_double_up_to_the_cap() {
+        let policy = UnlockPolicy::default();
+        let delays: Vec<u64> = (0..12).map(|n| policy.delay(n).as_secs()).collect();
+
+        assert_eq!(delays, [0, 0, 0, 0, 1, 2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(policy.delay(u32::MAX).as_secs(), 30);
+    }
+
+    #[test]
+    fn test_failed_unlocks_lock_out_and_success_resets() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault
+            .set_unlock_policy(UnlockPolicy {
+                free_attempts: 10,
+                max_delay_secs: 0,
+                lockout_after: 2,
+                lockout_minutes: 15,
+            })
+            .unwrap();
+
+        assert!(vault.unlock("wrong password").is_err());
+        assert_eq!(vault.load_config().unwrap().failed_unlocks, 1);
+        vault.unlock(PASSWORD).unwrap();
+        assert_eq!(vault.load_config().unwrap().failed_unlocks, 0);
+
+        assert!(vault.unlock("wrong password").is_err());
+        assert!(vault.unlock("wrong password").is_err());
+        let err = vault.unlock(PASSWORD).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::LockedOut { .. })));
+
+        // Once the lockout has passed, the right password works again
+        let mut config = vault.load_config().unwrap();
+        config.last_failed_unlock = Some(Utc::now() - Duration::minutes(16));
+        vault.save_config(&config).unwrap();
+        vault.unlock(PASSWORD).unwrap();
+    }
+
+    #[test]
+    fn test_reads_are_cached_until_the_vault_is_saved() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        assert!(vault.get_identity("github").is_ok());
+
+        // Served from the cache, so the damaged file isn't read again
+        let encrypted = fs::read(&vault.paths().unwrap().vault).unwrap();
+        fs::write(&vault.paths().unwrap().vault, b"garbage").unwrap();
+        assert!(vault.get_identity("github").is_ok());
+
+        // Saving writes the file and clears the cache
+        fs::write(&vault.paths().unwrap().vault, &encrypted).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        fs::write(&vault.paths().unwrap().vault, b"garbage").unwrap();
+        assert!(vault.get_identity("github").is_err());
+    }
+
+    #[test]
+    fn test_lock_forgets_key_and_cache() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.list_services().unwrap();
+
+        vault.lock();
+
+        assert!(!vault.is_unlocked());
+        assert!(vault.cache.borrow().is_none());
+        assert!(vault.list_services().is_err());
+    }
+
+    #[test]
+    fn test_touch_bumps_timestamps_only() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let mut identity = test_identity("github");
+        identity.rotate_after_days = Some(30);
+        identity.last_rotated = Some(Utc::now() - Duration::days(40));
+        vault.add_identity(identity).unwrap();
+        let before = vault.get_identity("github").unwrap();
+
+        vault.touch("github", false).unwrap();
+        let touched = vault.get_identity("github").unwrap();
+        assert!(touched.updated_at > before.updated_at);
+        assert_eq!(touched.last_rotated, before.last_rotated);
+        assert_eq!(touched.credentials.password, before.credentials.password);
+
+        vault.touch("github", true).unwrap();
+        assert!(!vault.get_identity("github").unwrap().rotation_overdue());
+        assert!(vault.touch("missing", false).is_err());
+    }
+
+    #[test]
+    fn test_list_detailed() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+        vault
+            .attach(
+                "gitlab",
+                Attachment {
+                    name: "id_ed25519".to_string(),
+                    mime: "text/plain".to_string(),
+                    data: b"key".to_vec(),
+                },
+            )
+            .unwrap();
+
+        let summaries = vault.list_detailed().unwrap();
+        let services: Vec<&str> = summaries.iter().map(|s| s.service.as_str()).collect();
+        assert_eq!(services, ["github", "gitlab"]);
+        assert_eq!(summaries[0].attachments, 0);
+        assert_eq!(summaries[1].attachments, 1);
+        assert!(summaries[1].updated_at > summaries[1].created_at);
+        assert!(!summaries[0].has_otp);
+        assert!(!summaries[0].has_personal_info);
+        assert!(!summaries[0].favorite);
+    }
+
+    #[test]
+    fn test_favorites() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        let before = vault.get_identity("gitlab").unwrap();
+        assert!(vault.list_favorites().unwrap().is_empty());
+
+        assert!(vault.set_favorite("gitlab", true).unwrap());
+        assert!(!vault.set_favorite("gitlab", true).unwrap());
+        let favorites = vault.list_favorites().unwrap();
+        assert_eq!(favorites.len(), 1);
+        assert_eq!(favorites[0].service, "gitlab");
+        assert_eq!(vault.get_identity("gitlab").unwrap().updated_at, before.updated_at);
+
+        assert!(vault.set_favorite("gitlab", false).unwrap());
+        assert!(vault.list_favorites().unwrap().is_empty());
+        assert!(vault.set_favorite("missing", true).is_err());
+    }
+
+    #[test]
+    fn test_failed_initialize_writes_nothing() {
+        let dir = TempDir::new();
+        let vault_path = dir.0.join(VAULT_FILE);
+        let config_path = dir.0.join(CONFIG_FILE);
+
+        // scrypt needs a power-of-two memory cost
+        let mut vault = Vault::with_paths(vault_path.clone(), config_path.clone());
+        let params = KdfParams {
+            algorithm: KdfAlgorithm::Scrypt,
+            memory_kib: 1000,
+            ..KdfParams::default()
+        };
+        assert!(vault.initialize(PASSWORD, params, Cipher::default(), DEFAULT_SALT_SIZE).is_err());
+        assert!(!config_path.exists() && !vault_path.exists());
+
+        // The config is written first; it must not outlive a failed vault write
+        fs::create_dir(&vault_path).unwrap();
+        assert!(vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).is_err());
+        assert!(!config_path.exists());
+        assert!(!vault.is_unlocked());
+    }
+
+    #[test]
+    fn test_audit_log_records_changes() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        assert!(vault.audit_log().unwrap().entries().is_empty());
+        let revision = vault.snapshot().unwrap().revision;
+
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.credentials.password = "correct horse".to_string();
+        vault.update_identity("github", identity).unwrap();
+        vault.set_favorite("github", true).unwrap();
+        vault.delete_identity("gitlab").unwrap();
+        vault.export(&mut Vec::new(), false).unwrap();
+        // One save each, the log's head included
+        assert_eq!(vault.snapshot().unwrap().revision, revision + 6);
+        // Survives the key changing
+        vault.rekey(PASSWORD, None, None).unwrap();
+
+        let log = vault.audit_log().unwrap();
+        let recorded: Vec<(AuditOp, Option<&str>)> =
+            log.entries().iter().map(|entry| (entry.op, entry.service.as_deref())).collect();
+        assert_eq!(
+            recorded,
+            vec![
+                (AuditOp::Add, Some("github")),
+                (AuditOp::Add, Some("gitlab")),
+                (AuditOp::Update, Some("github")),
+                (AuditOp::Update, Some("github")),
+                (AuditOp::Delete, Some("gitlab")),
+                (AuditOp::Export, None),
+            ]
+        );
+        assert_eq!(log.first_broken(), None);
+
+        let json = String::from_utf8(serde_json::to_vec(&log).unwrap()).unwrap();
+        assert!(!json.contains("hunter22") && !json.contains("correct horse"));
+        let on_disk = fs::read(dir.0.join(VAULT_FILE).with_extension("log")).unwrap();
+        assert!(!on_disk.windows(6).any(|window| window == b"github"));
+    }
+
+    #[test]
+    fn test_audit_log_tampering_is_detected() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let log_path = dir.0.join(VAULT_FILE).with_extension("log");
+
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        let earlier = fs::read(&log_path).unwrap();
+        vault.delete_identity("gitlab").unwrap();
+        let complete = fs::read(&log_path).unwrap();
+        assert_eq!(vault.audit_log().unwrap().entries().len(), 3);
+
+        // Rolled back to an earlier copy, which is a prefix of the chain
+        fs::write(&log_path, &earlier).unwrap();
+        let err = vault.audit_log().unwrap_err();
+        assert!(err.to_string().contains("cut short"));
+
+        fs::remove_file(&log_path).unwrap();
+        let err = vault.audit_log().unwrap_err();
+        assert!(err.to_string().contains("missing"));
+
+        fs::write(&log_path, &complete).unwrap();
+        assert_eq!(vault.audit_log().unwrap().entries().len(), 3);
+    }
+
+    #[test]
+    fn test_list_older_than() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let mut stale = test_identity("forum");
+        stale.updated_at = Utc::now() - Duration::days(200);
+        vault.add_identity(stale).unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let older = vault.list_older_than(Utc::now() - Duration::days(90)).unwrap();
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].service, "forum");
+        assert_eq!(vault.list_older_than(Utc::now() + Duration::days(1)).unwrap().len(), 2);
+        assert!(vault.list_older_than(Utc::now() - Duration::days(365)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshots_order_by_revision() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let first = vault.snapshot().unwrap();
+        assert_eq!(first.revision, 1);
+        assert_eq!(first.compare(&vault.snapshot().unwrap()), SnapshotOrder::Same);
+
+        vault.add_identity(test_identity("github")).unwrap();
+        let second = vault.snapshot().unwrap();
+        assert_eq!(second.revision, 2);
+        assert_eq!(second.compare(&first), SnapshotOrder::Newer);
+        assert_eq!(first.compare(&second), SnapshotOrder::Older);
+
+        // Another copy changed from the same starting point
+        let other_dir = TempDir::new();
+        let mut other = Vault::with_paths(other_dir.0.join(VAULT_FILE), other_dir.0.join(CONFIG_FILE));
+        other.replace_with_snapshot(&first).unwrap();
+        other.unlock(PASSWORD).unwrap();
+        other.add_identity(test_identity("gitlab")).unwrap();
+        assert_eq!(other.snapshot().unwrap().compare(&second), SnapshotOrder::Diverged);
+    }
+
+    #[test]
+    fn test_audit_log_follows_restores() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let backup = vault.backup().unwrap();
+        assert!(backup.log_path.is_some());
+
+        // A new salt puts the current log under a key the backup doesn't use
+        vault.rekey(PASSWORD, None, None).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        let snapshot = vault.snapshot().unwrap();
+
+        vault.restore_backup(&backup.timestamp).unwrap();
+        let mut restored = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        restored.unlock(PASSWORD).unwrap();
+        restored.add_identity(test_identity("bitbucket")).unwrap();
+        let services: Vec<Option<String>> =
+            restored.audit_log().unwrap().entries().iter().map(|entry| entry.service.clone()).collect();
+        assert_eq!(services, vec![Some("github".to_string()), Some("bitbucket".to_string())]);
+
+        let other_dir = TempDir::new();
+        let mut other = Vault::with_paths(other_dir.0.join(VAULT_FILE), other_dir.0.join(CONFIG_FILE));
+        other.replace_with_snapshot(&snapshot).unwrap();
+        other.unlock(PASSWORD).unwrap();
+        other.delete_identity("github").unwrap();
+        assert_eq!(other.audit_log().unwrap().entries().len(), 3);
+    }
+
+    #[test]
+    fn test_restore_oldest_backup_at_limit() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        assert!(vault.set_max_backups(0).is_err());
+        vault.set_max_backups(2).unwrap();
+
+        let oldest = vault.backup().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.backup().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        // Backing up the current files prunes `oldest` before it is written
+        vault.restore_backup(&oldest.timestamp).unwrap();
+        assert_eq!(vault.list_backups().unwrap().len(), 2);
+
+        let mut restored = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        restored.unlock(PASSWORD).unwrap();
+        assert_eq!(restored.identity_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_replace_with_snapshot_backs_up_and_locks() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        let empty = vault.snapshot().unwrap();
+        vault.add_identity(test_identity("github")).unwrap();
+
+        vault.replace_with_snapshot(&empty).unwrap();
+        assert!(!vault.is_unlocked());
+        assert_eq!(vault.list_backups().unwrap().len(), 1);
+
+        vault.unlock(PASSWORD).unwrap();
+        assert_eq!(vault.identity_count().unwrap(), 0);
+        assert_eq!(vault.snapshot().unwrap().compare(&empty), SnapshotOrder::Same);
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("github", "github"), 0);
+        assert_eq!(levenshtein("gihub", "github"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_get_identity_suggests_close_names() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        for service in ["github", "gitlab", "GitHub-Enterprise", "netflix"] {
+            vault.add_identity(test_identity(service)).unwrap();
+        }
+
+        let err = vault.get_identity("gihub").unwrap_err().to_string();
+        assert!(err.ends_with("Did you mean: github?"), "{}", err);
+
+        let err = vault.get_identity("GitLub").unwrap_err().to_string();
+        assert!(err.ends_with("Did you mean: github, gitlab?"), "{}", err);
+
+        let err = vault.get_identity("amazon").unwrap_err().to_string();
+        assert_eq!(err, "Identity for service 'amazon' not found");
+    }
+
+    #[test]
+    fn test_errors_map_to_exit_codes() {
+        let dir = TempDir::new();
+        let code = |err: anyhow::Error| err.downcast_ref::<VaultError>().map(VaultError::exit_code);
+
+        let mut vault = Vault::with_paths(dir.0.join(VAULT_FILE), dir.0.join(CONFIG_FILE));
+        assert_eq!(code(vault.unlock(PASSWORD).unwrap_err()), Some(3));
+
+        let mut vault = test_vault(&dir);
+        vault.lock();
+        assert_eq!(code(vault.unlock("wrong").unwrap_err()), Some(4));
+
+        vault.unlock(PASSWORD).unwrap();
+        assert_eq!(code(vault.get_identity("amazon").unwrap_err()), Some(5));
+        assert_eq!(code(vault.delete_identity("amazon").unwrap_err()), Some(5));
+    }
+
+    #[test]
+    fn test_index_tracks_vault_file() {
+        let dir = TempDir::new();
+        let vault_path = dir.0.join(VAULT_FILE);
+        let reopen = || {
+            let mut vault = Vault::with_paths(vault_path.clone(), dir.0.join(CONFIG_FILE));
+            vault.unlock(PASSWORD).unwrap();
+            vault
+        };
+
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let old_vault = fs::read(&vault_path).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        assert!(vault.paths().unwrap().index.exists());
+
+        // Listing is served from the index alone: damage everything in the
+        // vault file but its header and nonce
+        let current = fs::read(&vault_path).unwrap();
+        let prefix_len = ENVELOPE_HEADER_LEN + Cipher::default().nonce_len();
+        fs::write(&vault_path, &current[..prefix_len + 1]).unwrap();
+        let vault = reopen();
+        assert_eq!(vault.list_services().unwrap(), vec!["github", "gitlab"]);
+        assert!(vault.get_identity("github").is_err());
+
+        // A different vault file makes the index stale, so it is rebuilt
+        fs::write(&vault_path, old_vault).unwrap();
+        let vault = reopen();
+        assert_eq!(vault.list_services().unwrap(), vec!["github"]);
+        assert_eq!(reopen().list_detailed().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_self_contained_export() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let (mut plain, mut full) = (Vec::new(), Vec::new());
+        vault.export(&mut plain, false).unwrap();
+        vault.export(&mut full, true).unwrap();
+
+        let target = TempDir::new();
+        let target_vault = || Vault::with_paths(target.0.join(VAULT_FILE), target.0.join(CONFIG_FILE));
+        let mut restored = target_vault();
+        assert!(restored.restore(plain.as_slice(), PASSWORD).is_err());
+        let err = restored.restore(full.as_slice(), "wrong password").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::WrongPassword)));
+        assert!(!restored.is_initialized());
+
+        assert_eq!(restored.restore(full.as_slice(), PASSWORD).unwrap(), 1);
+        assert_eq!(restored.get_identity("github").unwrap().service, "github");
+        assert!(restored.restore(full.as_slice(), PASSWORD).is_err());
+
+        let mut reopened = target_vault();
+        reopened.unlock(PASSWORD).unwrap();
+        assert_eq!(reopened.list_services().unwrap(), vec!["github"]);
+
+        // Both kinds of export still import into the vault they came from
+        for export in [plain, full] {
+            let data = vault.load_import(export.as_slice(), None).unwrap();
+            vault.replace_data(data).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_import_detects_format() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let other_dir = TempDir::new();
+        let mut other = Vault::with_paths(other_dir.0.join(VAULT_FILE), other_dir.0.join(CONFIG_FILE));
+        other.initialize("other password", KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap();
+        other.add_identity(test_identity("github")).unwrap();
+
+        let (mut plain, mut full) = (Vec::new(), Vec::new());
+        other.export(&mut plain, false).unwrap();
+        other.export(&mut full, true).unwrap();
+
+        // A plain export from another vault can't be decrypted at all
+        let err = vault.load_import(plain.as_slice(), Some("other password")).unwrap_err();
+        assert!(format!("{:#}", err).contains("--self-contained"));
+
+        // A self-contained one asks for its own password, and checks it
+        let err = vault.load_import(full.as_slice(), None).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::ExportPasswordNeeded)));
+        let err = vault.load_import(full.as_slice(), Some(PASSWORD)).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::WrongPassword)));
+        let data = vault.load_import(full.as_slice(), Some("other password")).unwrap();
+        assert!(data.identities.contains_key("github"));
+
+        // Costs no vault uses are refused before deriving anything
+        let mut oversized: serde_json::Value = serde_json::from_slice(&full).unwrap();
+        oversized["config"]["kdf_params"]["memory_kib"] = u32::MAX.into();
+        let oversized = serde_json::to_vec(&oversized).unwrap();
+        let err = vault.load_import(oversized.as_slice(), Some("other password")).unwrap_err();
+        assert!(err.to_string().starts_with("Key derivation parameters are beyond"));
+
+        // A single shared identity is pointed at import-one
+        let path = other_dir.0.join("github.share");
+        other.export_identity("github", &path, "share-pass").unwrap();
+        let err = vault.load_import(fs::read(&path).unwrap().as_slice(), None).unwrap_err();
+        assert!(err.to_string().contains("import-one"));
+    }
+
+    #[test]
+    fn test_password_changes_are_kept_in_history() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let original = vault.get_identity("github").unwrap().credentials.password.clone();
+
+        // Saving other changes leaves the history alone
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.notes = Some("note".to_string());
+        vault.update_identity("github", identity).unwrap();
+        assert!(vault.get_identity("github").unwrap().password_history.is_empty());
+
+        for i in 0..=MAX_PASSWORD_HISTORY {
+            let mut identity = vault.get_identity("github").unwrap();
+            identity.credentials.password = format!("password-{}", i);
+            vault.update_identity("github", identity).unwrap();
+        }
+
+        let history = vault.get_identity("github").unwrap().password_history.clone();
+        assert_eq!(history.len(), MAX_PASSWORD_HISTORY);
+        assert_eq!(history[0].password, format!("password-{}", MAX_PASSWORD_HISTORY - 1));
+        assert!(history.iter().all(|previous| previous.password != original));
+    }
+
+    #[test]
+    fn test_services_using_password() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        for service in ["netflix", "github", "gitlab"] {
+            vault.add_identity(test_identity(service)).unwrap();
+        }
+        let mut unique = test_identity("bank");
+        unique.credentials.password = "something else".to_string();
+        vault.add_identity(unique).unwrap();
+
+        let password = vault.get_identity("github").unwrap().credentials.password.clone();
+        assert_eq!(
+            vault.services_using_password(&password).unwrap(),
+            vec!["github", "gitlab", "netflix"]
+        );
+        assert_eq!(vault.services_using_password("something else").unwrap(), vec!["bank"]);
+        assert!(vault.services_using_password("unused").unwrap().is_empty());
+        assert!(vault.services_using_password("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hotp_counter_is_persisted() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        assert!(vault.next_otp("github").is_err());
+
+        let secret = b"12345678901234567890".to_vec();
+        let otp = OtpSecret {
+            secret,
+            kind: OtpKind::Hotp { counter: 0 },
+            digits: 6,
+            issuer: None,
+            account: None,
+        };
+        let mut too_long = otp.clone();
+        too_long.digits = 10;
+        assert!(vault.set_otp("github", Some(too_long.clone())).is_err());
+        vault.set_otp("github", Some(otp)).unwrap();
+        assert!(vault.list_detailed().unwrap()[0].has_otp);
+
+        assert_eq!(*vault.next_otp("github").unwrap(), "755224");
+        assert_eq!(*vault.next_otp("github").unwrap(), "287082");
+        let otp = vault.get_identity("github").unwrap().otp.clone().unwrap();
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 2 });
+
+        // Handing out a code without recording it would allow reuse
+        vault.set_read_only(true);
+        assert!(vault.next_otp("github").is_err());
+        vault.set_read_only(false);
+
+        // Nor can an import slip in a length the code arithmetic overflows on
+        let mut data = vault.load_vault_data().unwrap();
+        data.identities.get_mut("github").unwrap().otp = Some(too_long);
+        vault.replace_data(data).unwrap();
+        assert!(vault.next_otp("github").is_err());
+
+        vault.set_otp("github", None).unwrap();
+        assert!(vault.get_identity("github").unwrap().otp.is_none());
+    }
+
+    #[test]
+    fn test_migrate_legacy_vault() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        assert!(vault.pending_migrations().unwrap().is_empty());
+
+        // Rewrite as an unbound 0.0.1 vault whose config predates most fields
+        let data = vault.load_vault_data().unwrap();
+        let mut config = vault.load_config().unwrap();
+        config.version = "0.0.1".to_string();
+        config.config_bound = false;
+        let legacy = serde_json::json!({
+            "master_password_hash": config.master_password_hash,
+            "salt": config.salt,
+            "version": config.version,
+            "kdf_params": config.kdf_params,
+            "cipher": config.cipher,
+        });
+        fs::write(&vault.paths().unwrap().config, legacy.to_string()).unwrap();
+        vault.aad = Vec::new();
+        // ...and whose vault file predates the format header
+        let json = serde_json::to_vec(&data).unwrap();
+        let headerless = encrypt(&json, vault.key().unwrap(), config.cipher, &[]).unwrap();
+        fs::write(&vault.paths().unwrap().vault, headerless).unwrap();
+        vault.invalidate_cache();
+        assert_eq!(vault.load_vault_data().unwrap().identities.len(), 1);
+
+        let pending = vault.pending_migrations().unwrap();
+        assert!(pending.iter().any(|step| step.contains("max_backups")));
+        assert!(pending.iter().any(|step| step.contains("Bind")));
+        assert!(pending.iter().any(|step| step.contains("format header")));
+
+        let applied = vault.migrate(PASSWORD).unwrap();
+        assert_eq!(applied, pending);
+        assert_eq!(vault.list_backups().unwrap().len(), 1);
+
+        let config = vault.load_config().unwrap();
+        assert!(config.config_bound);
+        assert_eq!(config.version, env!("CARGO_PKG_VERSION"));
+        assert!(vault.migrate(PASSWORD).unwrap().is_empty());
+        assert!(has_envelope(&fs::read(&vault.paths().unwrap().vault).unwrap()));
+
+        let paths = vault.paths().unwrap();
+        let mut reopened = Vault::with_paths(paths.vault, paths.config);
+        reopened.unlock(PASSWORD).unwrap();
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.password, "hunter22");
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_vault() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let mut config = vault.load_config().unwrap();
+        config.version = "999.0.0".to_string();
+        vault.save_config(&config).unwrap();
+
+        assert!(vault.pending_migrations().is_err());
+        assert!(compare_versions("0.3", "0.3.0").is_eq());
+        assert!(compare_versions("0.10.0", "0.9.1").is_gt());
+    }
+
+    #[test]
+    fn test_stored_generator_policy() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        assert_eq!(vault.generator_policy().unwrap(), None);
+
+        let policy = PasswordPolicy {
+            length: 24,
+            exclude_ambiguous: true,
+            ..PasswordPolicy::default()
+        };
+        vault.set_generator_policy(Some(policy.clone())).unwrap();
+
+        // Read back without unlocking, as `generate` does
+        let paths = vault.paths().unwrap();
+        let reopened = Vault::with_paths(paths.vault, paths.config);
+        let stored = reopened.generator_policy().unwrap().unwrap();
+        assert_eq!(stored, policy);
+        let password = generator::generate(&stored).unwrap();
+        assert_eq!(password.chars().count(), 24);
+        assert!(!password.chars().any(|c| generator::AMBIGUOUS.contains(c)));
+
+        let invalid = PasswordPolicy { length: 0, ..policy };
+        assert!(vault.set_generator_policy(Some(invalid)).is_err());
+        vault.set_generator_policy(None).unwrap();
+        assert_eq!(vault.generator_policy().unwrap(), None);
+    }
+
+    #[test]
+    fn test_add_or_replace_identity() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let created_at = vault.get_identity("github").unwrap().created_at;
+
+        let mut replacement = test_identity("github");
+        replacement.credentials.username = "bob".to_string();
+        replacement.credentials.password = "new-password".to_string();
+        assert!(vault.add_identity(replacement.clone()).is_err());
+
+        assert!(vault.add_or_replace_identity(replacement).unwrap());
+        let identity = vault.get_identity("github").unwrap();
+        assert_eq!(identity.credentials.username, "bob");
+        assert_eq!(identity.credentials.password, "new-password");
+        assert_eq!(identity.created_at, created_at);
+        assert_eq!(identity.password_history[0].password, "hunter22");
+
+        assert!(!vault.add_or_replace_identity(test_identity("gitlab")).unwrap());
+        assert!(vault.contains("gitlab").unwrap());
+    }
+
+    #[test]
+    fn test_validate_and_normalize() {
+        let older = test_identity("github");
+        let mut newer = test_identity("GitHub");
+        newer.updated_at = older.updated_at + Duration::seconds(1);
+        newer.credentials.password = "newer".to_string();
+
+        let mut data = VaultData {
+            identities: HashMap::from([
+                ("github".to_string(), older),
+                (" github ".to_string(), newer),
+                ("gitlab".to_string(), test_identity("something-else")),
+            ]),
+            audit_head: None,
+        };
+        let changes = data.validate_and_normalize().unwrap();
+
+        assert_eq!(data.identities.len(), 2);
+        let github = &data.identities["github"];
+        assert_eq!(github.service, "github");
+        assert_eq!(github.credentials.password, "newer");
+        assert_eq!(data.identities["gitlab"].service, "gitlab");
+        assert!(changes.iter().any(|change| change.contains("duplicate")));
+
+        let mut empty = VaultData {
+            identities: HashMap::from([("  ".to_string(), test_identity("github"))]),
+            audit_head: None,
+        };
+        assert!(empty.validate_and_normalize().is_err());
+    }
+
+    #[test]
+    fn test_remap_services() {
+        let older = test_identity("www.GitHub.com");
+        let mut newer = test_identity("github.com");
+        newer.updated_at = older.updated_at + Duration::seconds(1);
+        newer.credentials.password = "newer".to_string();
+
+        let mut data = VaultData {
+            identities: HashMap::from([
+                ("www.GitHub.com".to_string(), older),
+                ("github.com".to_string(), newer),
+                ("https://mail.example.com/".to_string(), test_identity("https://mail.example.com/")),
+                ("www.".to_string(), test_identity("www.")),
+            ]),
+            audit_head: None,
+        };
+        let mapping = ServiceMapping {
+            renames: vec![("https://mail.example.com/".to_string(), "Mail".to_string())],
+            strip_www: true,
+            lowercase: true,
+        };
+        let renames = data.remap_services(&mapping);
+
+        assert_eq!(
+            renames,
+            vec![
+                ("https://mail.example.com/".to_string(), "Mail".to_string()),
+                ("www.GitHub.com".to_string(), "github.com".to_string()),
+            ]
+        );
+        assert_eq!(data.identities.len(), 3);
+        assert_eq!(data.identities["github.com"].credentials.password, "newer");
+        assert_eq!(data.identities["Mail"].service, "Mail");
+        assert!(data.identities.contains_key("www."));
+    }
+
+    #[test]
+    fn test_master_password_change_is_recorded() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+
+        let info = vault.info().unwrap();
+        assert_eq!(info.password_version, 1);
+        let created = info.master_password_changed_at.unwrap();
+
+        vault.change_master_password(PASSWORD, "correct horse 2", Some("annual rotation")).unwrap();
+        let info = vault.info().unwrap();
+        assert_eq!(info.password_version, 2);
+        assert!(info.master_password_changed_at.unwrap() >= created);
+        assert_eq!(info.master_password_change_reason.as_deref(), Some("annual rotation"));
+
+        // Rekeying keeps the password, so it is not a change
+        vault.rekey("correct horse 2", None, None).unwrap();
+        assert_eq!(vault.info().unwrap().password_version, 2);
+
+        // Configs from before this was tracked load with nothing recorded
+        let legacy: VaultConfig = serde_json::from_str(
+            r#"{"master_password_hash":"","salt":[],"version":"0.1.0"}"#,
+        )
+        .unwrap();
+        assert_eq!((legacy.master_password_changed_at, legacy.password_version), (None, 0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_saved_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let loosen = |path: &Path| fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        let paths = vault.paths().unwrap();
+        assert_eq!((mode(&paths.vault), mode(&paths.config), mode(&paths.index)), (0o600, 0o600, 0o600));
+
+        // Files that were already loose are tightened on the next write
+        loosen(&paths.vault);
+        loosen(&paths.index);
+        vault.add_identity(test_identity("github")).unwrap();
+        assert_eq!((mode(&paths.vault), mode(&paths.index)), (0o600, 0o600));
+
+        loosen(&paths.vault);
+        loosen(&paths.config);
+        vault.change_master_password(PASSWORD, "correct horse 2", None).unwrap();
+        assert_eq!((mode(&paths.vault), mode(&paths.config)), (0o600, 0o600));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restrict_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        assert!(vault.loose_permissions().unwrap().is_empty());
+
+        let paths = vault.paths().unwrap();
+        fs::set_permissions(&paths.config, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let loose = vault.loose_permissions().unwrap();
+        assert!(loose.contains(&(paths.config.clone(), 0o644)));
+
+        assert!(vault.restrict_permissions().unwrap() >= 1);
+        assert!(vault.loose_permissions().unwrap().is_empty());
+        let mode = fs::metadata(&paths.config).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        vault.check_directory_writable().unwrap();
+    }
+
+    #[test]
+    fn test_add_identity_without_login() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+
+        let mut note = test_identity("wifi");
+        note.credentials.username.clear();
+        note.credentials.password.clear();
+        assert!(vault.add_identity(note.clone()).is_err());
+
+        note.template = Some(Template::SecureNote);
+        vault.add_identity(note).unwrap();
+        assert_eq!(vault.get_identity("wifi").unwrap().template, Some(Template::SecureNote));
+        assert!(vault.find_incomplete().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_listed_service() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        assert!(vault.listed_service(1).is_err());
+
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        vault.remember_listing(&["gitlab".to_string(), "github".to_string()]).unwrap();
+        assert_eq!(vault.listed_service(1).unwrap(), "gitlab");
+        assert_eq!(vault.listed_service(2).unwrap(), "github");
+        assert!(vault.listed_service(0).is_err());
+        assert!(vault.listed_service(3).is_err());
+
+        // Any save makes the listing stale
+        vault.touch("github", false).unwrap();
+        let err = vault.listed_service(1).unwrap_err();
+        assert!(err.to_string().contains("changed"));
+    }
+
+    #[test]
+    fn test_delete_requires_exact_name() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        for near_miss in ["gihub", "GitHub", "github "] {
+            let err = vault.delete_identity(near_miss).unwrap_err().to_string();
+            assert!(err.ends_with("Did you mean: github?"), "{}", err);
+        }
+        assert!(vault.delete_identity("git").is_err());
+        assert!(vault.contains("github").unwrap());
+
+        vault.delete_identity("github").unwrap();
+        assert!(!vault.contains("github").unwrap());
+    }
+
+    #[test]
+    fn test_all_identities() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        assert!(vault.all_identities().unwrap().is_empty());
+
+        for service in ["gitlab", "amazon", "github"] {
+            vault.add_identity(test_identity(service)).unwrap();
+        }
+        let services: Vec<String> = vault.all_identities().unwrap().iter().map(|i| i.service.clone()).collect();
+        assert_eq!(services, vec!["amazon", "github", "gitlab"]);
+        assert_eq!(vault.iter_identities().unwrap().filter(|i| i.credentials.username == "alice").count(), 3);
+
+        vault.lock();
+        assert!(vault.all_identities().is_err());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        let mut vault = Vault::with_store(Box::new(store.clone()));
+        vault.initialize(PASSWORD, KdfParams::default(), Cipher::default(), DEFAULT_SALT_SIZE).unwrap();
+
+        vault.add_identity(test_identity("github")).unwrap();
+        vault.add_identity(test_identity("gitlab")).unwrap();
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.credentials.password = "hunter23".to_string();
+        vault.update_identity("github", identity).unwrap();
+        vault.delete_identity("gitlab").unwrap();
+        vault.change_master_password(PASSWORD, "correct horse 2", None).unwrap();
+
+        let mut reopened = Vault::with_store(Box::new(store.clone()));
+        assert!(reopened.unlock(PASSWORD).is_err());
+        reopened.unlock("correct horse 2").unwrap();
+        assert_eq!(reopened.list_services().unwrap(), vec!["github"]);
+        assert_eq!(reopened.get_identity("github").unwrap().credentials.password, "hunter23");
+        assert!(store.exists(Blob::Index));
+
+        // Backups and paths need files
+        assert!(reopened.paths().is_err());
+        assert!(reopened.backup().is_err());
+    }
+
+    #[test]
+    fn test_consume_recovery_code() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let mut identity = test_identity("github");
+        identity.credentials.recovery_codes = vec!["aaaa-1111".to_string(), "bbbb-2222".to_string()];
+        vault.add_identity(identity).unwrap();
+
+        assert_eq!(*vault.consume_recovery_code("github").unwrap(), "aaaa-1111");
+        assert_eq!(*vault.consume_recovery_code("github").unwrap(), "bbbb-2222");
+        assert!(vault.consume_recovery_code("github").is_err());
+        assert!(vault.get_identity("github").unwrap().credentials.recovery_codes.is_empty());
+    }
+
+    #[test]
+    fn test_expiring_and_rotation_on_password_change() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+
+        let mut overdue = test_identity("github");
+        overdue.rotate_after_days = Some(30);
+        overdue.last_rotated = Some(Utc::now() - Duration::days(45));
+        vault.add_identity(overdue).unwrap();
+
+        let mut fresh = test_identity("gitlab");
+        fresh.rotate_after_days = Some(90);
+        vault.add_identity(fresh).unwrap();
+        vault.add_identity(test_identity("email")).unwrap();
+
+        let services: Vec<String> = vault.expiring(14).unwrap().into_iter().map(|(s, _)| s).collect();
+        assert_eq!(services, vec!["github".to_string()]);
+
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.credentials.password = "rotated!".to_string();
+        vault.update_identity("github", identity).unwrap();
+
+        assert!(vault.expiring(14).unwrap().is_empty());
+        assert!(!vault.get_identity("github").unwrap().rotation_overdue());
+    }
+
+    #[test]
+    fn test_record_access_is_opt_in() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        vault.record_access("github").unwrap();
+        assert!(vault.get_identity("github").unwrap().last_accessed.is_none());
+
+        vault.set_track_access(true).unwrap();
+        vault.set_read_only(true);
+        vault.record_access("github").unwrap();
+        assert!(vault.get_identity("github").unwrap().last_accessed.is_none());
+
+        vault.set_read_only(false);
+        vault.record_access("github").unwrap();
+        assert!(vault.get_identity("github").unwrap().last_accessed.is_some());
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("test", "my-test-account"));
+        assert!(!matches_pattern("test", "github"));
+        assert!(matches_pattern("test-*", "test-1"));
+        assert!(!matches_pattern("test-*", "my-test-1"));
+        assert!(matches_pattern("test-?", "test-1"));
+        assert!(!matches_pattern("test-?", "test-10"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*b?d*", "aXXbcdYY"));
+        assert!(!matches_pattern("a*b?d", "aXXbcdYY"));
+
+        // Would take exponential time with naive backtracking
+        let long = "a".repeat(200);
+        assert!(!matches_pattern("a*a*a*a*a*a*a*a*a*a*b", &long));
+        assert!(matches_pattern("a*a*a*a*a*a*a*a*a*a*", &long));
+    }
+
+    #[test]
+    fn test_delete_matching() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        for service in ["test-1", "test-2", "github"] {
+            vault.add_identity(test_identity(service)).unwrap();
+        }
+
+        let removed = vault.delete_matching(|i| matches_pattern("test-*", &i.service)).unwrap();
+
+        assert_eq!(removed, vec!["test-1".to_string(), "test-2".to_string()]);
+        assert_eq!(vault.list_services().unwrap(), vec!["github".to_string()]);
+    }
+
+    #[test]
+    fn test_export_and_import_single_identity() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+        let path = dir.0.join("github.share");
+
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.credentials.password = "correct horse".to_string();
+        vault.update_identity("github", identity).unwrap();
+        vault.export_identity("github", &path, "share-pass").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        assert!(vault.import_identity(&path, "wrong-pass", false).is_err());
+        assert!(vault.import_identity(&path, "share-pass", false).is_err());
+
+        vault.delete_identity("github").unwrap();
+        let service = vault.import_identity(&path, "share-pass", false).unwrap();
+
+        assert_eq!(service, "github");
+        let imported = vault.get_identity("github").unwrap();
+        assert_eq!(imported.credentials.password, "correct horse");
+        assert!(imported.password_history.is_empty());
+        vault.import_identity(&path, "share-pass", true).unwrap();
+    }
+
+    #[test]
+    fn test_import_identity_checks_file() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        let path = dir.0.join("shared.share");
+        let kdf_params = KdfParams {
+            algorithm: KdfAlgorithm::Scrypt,
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let salt = generate_salt(DEFAULT_SALT_SIZE).unwrap();
+        let key = derive_key("share-pass", &salt, &kdf_params).unwrap();
+        let mut identity = test_identity("github");
+        identity.credentials.username = " ".to_string();
+        let shared = |kdf_params| SharedIdentity {
+            format: SHARED_IDENTITY_FORMAT.to_string(),
+            version: SHARED_IDENTITY_VERSION,
+            kdf_params,
+            cipher: Cipher::default(),
+            salt: salt.clone(),
+            data: encrypt(&serde_json::to_vec(&identity).unwrap(), &key, Cipher::default(), &[]).unwrap(),
+        };
+
+        fs::write(&path, serde_json::to_vec(&shared(kdf_params)).unwrap()).unwrap();
+        let err = vault.import_identity(&path, "share-pass", false).unwrap_err();
+        assert_eq!(err.to_string(), "Username cannot be empty");
+
+        let oversized = KdfParams { memory_kib: u32::MAX, ..kdf_params };
+        fs::write(&path, serde_json::to_vec(&shared(oversized)).unwrap()).unwrap();
+        let err = vault.import_identity(&path, "share-pass", false).unwrap_err();
+        assert!(err.to_string().starts_with("Key derivation parameters are beyond"));
+    }
+
+    #[test]
+    fn test_diff_against_incoming() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        for service in ["kept", "changed", "dropped"] {
+            vault.add_identity(test_identity(service)).unwrap();
+        }
+
+        let mut incoming = vault.load_vault_data().unwrap();
+        incoming.identities.remove("dropped");
+        let changed = incoming.identities.get_mut("changed").unwrap();
+        changed.credentials.password = "correct horse".to_string();
+        changed.updated_at += Duration::seconds(5);
+        // A newer timestamp alone is no change
+        incoming.identities.get_mut("kept").unwrap().updated_at += Duration::seconds(5);
+        incoming.identities.insert("new".to_string(), test_identity("new"));
+
+        let diff = vault.diff(&incoming).unwrap();
+
+        assert_eq!(
+            diff,
+            VaultDiff {
+                added: vec!["new".to_string()],
+                updated: vec![("changed".to_string(), vec!["password"])],
+                removed: vec!["dropped".to_string()],
+                unchanged: vec!["kept".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_add_rejects_empty_service() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+
+        assert!(vault.add_identity(test_identity("")).is_err());
+        assert!(vault.add_identity(test_identity("   ")).is_err());
+        assert!(vault.list_services().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_update_corrects_mismatched_service_field() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        vault.update_identity("github", test_identity("gitlab")).unwrap();
+
+        let identity = vault.get_identity("github").unwrap();
+        assert_eq!(identity.service, "github");
+        assert_eq!(vault.list_services().unwrap(), vec!["github".to_string()]);
+    }
+
+    #[test]
+    fn test_update_rejects_empty_username() {
+        let dir = TempDir::new();
+        let vault = test_vault(&dir);
+        vault.add_identity(test_identity("github")).unwrap();
+
+        let mut edit = IdentityEdit::default();
+        edit.username = Some("  ".to_string());
+        let mut identity = vault.get_identity("github").unwrap();
+        identity.apply_edit(&edit);
+        let err = vault.update_identity("github", identity).unwrap_err();
+        assert_eq!(err.to_string(), "Username cannot be empty");
+        assert_eq!(vault.get_identity("github").unwrap().credentials.username, "alice");
+    }
+
+    #[test]
+    fn test_truncated_vault_reports_corruption() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        let encrypted = fs::read(&vault.paths().unwrap().vault).unwrap();
+        fs::write(&vault.paths().unwrap().vault, &encrypted[..encrypted.len() / 2]).unwrap();
+
+        vault.unlock(PASSWORD).unwrap();
+        let err = vault.get_identity("github").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::Corrupted { .. })));
+        assert!(format!("{:#}", err).contains("corrupted"));
+
+        let err = vault.unlock("wrong password").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::WrongPassword)));
+    }
+
+    #[test]
+    fn test_operations_before_unlock_fail_with_not_unlocked() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        assert!(vault.is_unlocked());
+
+        vault.key = None;
+        assert!(!vault.is_unlocked());
+        let err = vault.list_services().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(VaultError::NotUnlocked)));
+    }
+
+    #[test]
+    fn test_read_only_refuses_writes() {
+        let dir = TempDir::new();
+        let mut vault = test_vault(&dir);
+        vault.set_read_only(true);
+        vault.unlock(PASSWORD).unwrap();
+
+        let before = fs::read(&vault.paths().unwrap().vault).unwrap();
+
+        assert!(vault.add_identity(test_identity("github")).is_err());
+        assert!(vault.rekey(PASSWORD, None, None).is_err());
+        assert!(vault.export(&mut Vec::new(), false).is_err());
+        assert!(vault.list_services().unwrap().is_empty());
+
+        assert_eq!(fs::read(&vault.paths().unwrap().vault).unwrap(), before);
+        assert!(!dir.0.join("export.vault").exists());
+    }
 }
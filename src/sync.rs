@@ -0,0 +1,72 @@
+#[cfg(feature = "sync")]
+use crate::ssh::{SftpStore, SshRemote};
+use crate::storage::VaultStore;
+#[cfg(feature = "webdav")]
+use crate::webdav::{WebDavRemote, WebDavStore};
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Somewhere `sync` can keep a copy of the encrypted vault: an `http://` or
+/// `https://` URL for a WebDAV server, anything else for an SSH server
+#[derive(Debug, Clone)]
+pub enum Remote {
+    #[cfg(feature = "sync")]
+    Ssh(SshRemote),
+    #[cfg(feature = "webdav")]
+    WebDav(WebDavRemote),
+}
+
+impl FromStr for Remote {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            #[cfg(feature = "webdav")]
+            return spec.parse().map(Remote::WebDav);
+            #[cfg(not(feature = "webdav"))]
+            return Err("WebDAV remotes need a build with --features webdav".to_string());
+        }
+        #[cfg(feature = "sync")]
+        return spec.parse().map(Remote::Ssh);
+        #[cfg(not(feature = "sync"))]
+        Err("SSH remotes need a build with --features sync".to_string())
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "sync")]
+            Remote::Ssh(remote) => remote.fmt(f),
+            #[cfg(feature = "webdav")]
+            Remote::WebDav(remote) => remote.fmt(f),
+        }
+    }
+}
+
+impl Remote {
+    /// The user a password has to be asked for before connecting, if any;
+    /// SSH logs in with keys
+    pub fn password_user(&self) -> Option<&str> {
+        match self {
+            #[cfg(feature = "sync")]
+            Remote::Ssh(_) => None,
+            #[cfg(feature = "webdav")]
+            Remote::WebDav(remote) => remote.password_user(),
+        }
+    }
+
+    /// Connects to the remote, holding only the encrypted vault file and
+    /// config; `password` is for [`Remote::password_user`]
+    #[cfg_attr(not(feature = "webdav"), allow(unused_variables))]
+    pub fn open(&self, password: Option<Zeroizing<String>>) -> Result<Box<dyn VaultStore>> {
+        match self {
+            #[cfg(feature = "sync")]
+            Remote::Ssh(remote) => Ok(Box::new(SftpStore::open(remote)?)),
+            #[cfg(feature = "webdav")]
+            Remote::WebDav(remote) => Ok(Box::new(WebDavStore::new(remote, password))),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A custom field a template asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateField {
+    pub name: &'static str,
+    /// Read without echo and masked by `get` unless `--show`
+    pub secret: bool,
+}
+
+const fn field(name: &'static str) -> TemplateField {
+    TemplateField { name, secret: false }
+}
+
+const fn secret(name: &'static str) -> TemplateField {
+    TemplateField { name, secret: true }
+}
+
+const LOGIN_FIELDS: &[TemplateField] = &[field("Website")];
+
+const CREDIT_CARD_FIELDS: &[TemplateField] = &[
+    field("Cardholder"),
+    secret("Card Number"),
+    field("Expiry (MM/YY)"),
+    secret("CVV"),
+    secret("PIN"),
+];
+
+const BANK_FIELDS: &[TemplateField] = &[
+    secret("Account Number"),
+    field("Routing Number"),
+    secret("IBAN"),
+    field("SWIFT/BIC"),
+    field("Security Question"),
+    secret("Security Answer"),
+];
+
+/// Kinds of identity whose usual details `add` asks for up front, stored as
+/// custom fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Template {
+    Login,
+    CreditCard,
+    SecureNote,
+    Bank,
+}
+
+impl Template {
+    /// The custom fields to prompt for, in order
+    pub fn fields(self) -> &'static [TemplateField] {
+        match self {
+            Template::Login => LOGIN_FIELDS,
+            Template::CreditCard => CREDIT_CARD_FIELDS,
+            Template::SecureNote => &[],
+            Template::Bank => BANK_FIELDS,
+        }
+    }
+
+    /// Whether identities of this kind have a username and password; cards
+    /// and notes don't, so they aren't incomplete without one
+    pub fn has_login(self) -> bool {
+        matches!(self, Template::Login | Template::Bank)
+    }
+
+    /// Whether the custom field `key` holds one of this template's secrets
+    pub fn is_secret_field(self, key: &str) -> bool {
+        self.fields()
+            .iter()
+            .any(|field| field.secret && field.name.eq_ignore_ascii_case(key.trim()))
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Template::Login => "Login",
+            Template::CreditCard => "Credit Card",
+            Template::SecureNote => "Secure Note",
+            Template::Bank => "Bank Account",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_template_fields() {
+        for template in Template::value_variants() {
+            let fields = template.fields();
+            for (i, field) in fields.iter().enumerate() {
+                assert!(
+                    !fields[..i].iter().any(|other| other.name.eq_ignore_ascii_case(field.name)),
+                    "{} lists '{}' twice",
+                    template,
+                    field.name
+                );
+            }
+        }
+
+        assert!(Template::CreditCard.is_secret_field("cvv"));
+        assert!(Template::Bank.is_secret_field(" Account Number "));
+        assert!(!Template::Bank.is_secret_field("Routing Number"));
+        assert!(!Template::Login.is_secret_field("Website"));
+    }
+}
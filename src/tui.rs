@@ -0,0 +1,348 @@
+use crate::clipboard;
+use crate::config::Settings;
+use crate::identity::Identity;
+use crate::storage::{matches_pattern, IdentitySummary, Vault};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::{Duration, Instant};
+
+/// Idle time after which the browser locks the vault and exits
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often to wake up and check the idle timer while waiting for keys
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Why the browser stopped
+pub enum Exit {
+    Quit,
+    IdleLock,
+}
+
+/// Runs the full-screen browser over an unlocked vault
+///
+/// Only the index summaries are loaded up front; an identity is decrypted
+/// when it is selected, and the password is only ever shown masked. The
+/// vault is locked before returning, however the browser exits.
+pub fn run(vault: &mut Vault, settings: &Settings) -> Result<Exit> {
+    let mut app = App::new(vault.list_detailed()?);
+    app.refresh_selected(vault);
+
+    let mut terminal = ratatui::init();
+    let result = app.event_loop(&mut terminal, vault, settings);
+    ratatui::restore();
+
+    drop(app);
+    vault.lock();
+    result
+}
+
+struct App {
+    summaries: Vec<IdentitySummary>,
+    /// Indexes into `summaries` that match `query`
+    visible: Vec<usize>,
+    list: ListState,
+    query: String,
+    searching: bool,
+    /// The identity under the cursor, decrypted
+    selected: Option<Identity>,
+    status: Option<String>,
+}
+
+impl App {
+    fn new(summaries: Vec<IdentitySummary>) -> Self {
+        let mut app = Self {
+            summaries,
+            visible: Vec::new(),
+            list: ListState::default(),
+            query: String::new(),
+            searching: false,
+            selected: None,
+            status: None,
+        };
+        app.apply_filter();
+        app
+    }
+
+    fn selected_service(&self) -> Option<&str> {
+        let index = *self.visible.get(self.list.selected()?)?;
+        Some(&self.summaries[index].service)
+    }
+
+    /// Recomputes the visible services for the current query, keeping the
+    /// cursor on the same service when it still matches
+    fn apply_filter(&mut self) {
+        let previous = self.selected_service().map(str::to_string);
+        let query = self.query.to_lowercase();
+        self.visible = (0..self.summaries.len())
+            .filter(|&i| matches_pattern(&query, &self.summaries[i].service.to_lowercase()))
+            .collect();
+
+        let position = previous
+            .and_then(|service| self.visible.iter().position(|&i| self.summaries[i].service == service));
+        self.list.select(match position {
+            Some(position) => Some(position),
+            None if self.visible.is_empty() => None,
+            None => Some(0),
+        });
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let last = self.visible.len() as isize - 1;
+        self.list.select(Some((current + delta).clamp(0, last) as usize));
+    }
+
+    fn refresh_selected(&mut self, vault: &Vault) {
+        self.selected = match self.selected_service() {
+            Some(service) => match vault.get_identity(service) {
+                Ok(identity) => Some(identity),
+                Err(e) => {
+                    self.status = Some(format!("{:#}", e));
+                    None
+                }
+            },
+            None => None,
+        };
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal, vault: &Vault, settings: &Settings) -> Result<Exit> {
+        let mut last_input = Instant::now();
+
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if last_input.elapsed() >= IDLE_TIMEOUT {
+                return Ok(Exit::IdleLock);
+            }
+            if !event::poll(POLL_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            last_input = Instant::now();
+
+            let before = self.selected_service().map(str::to_string);
+            if !self.handle_key(key, vault, settings)? {
+                return Ok(Exit::Quit);
+            }
+            if self.selected_service() != before.as_deref() {
+                self.refresh_selected(vault);
+            }
+        }
+    }
+
+    /// Applies one key press; returns false when the browser should close
+    fn handle_key(&mut self, key: KeyEvent, vault: &Vault, settings: &Settings) -> Result<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(false);
+        }
+
+        if self.searching {
+            match key.code {
+                KeyCode::Esc => {
+                    self.searching = false;
+                    self.query.clear();
+                    self.apply_filter();
+                }
+                KeyCode::Enter => self.searching = false,
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.apply_filter();
+                }
+                KeyCode::Up => self.move_cursor(-1),
+                KeyCode::Down => self.move_cursor(1),
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.apply_filter();
+                }
+                _ => {}
+            }
+            return Ok(true);
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('/') => {
+                self.searching = true;
+                self.status = None;
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-10),
+            KeyCode::PageDown => self.move_cursor(10),
+            KeyCode::Home | KeyCode::Char('g') => self.list.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.move_cursor(isize::MAX / 2),
+            KeyCode::Char('c') => self.copy_password(vault, settings),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn copy_password(&mut self, vault: &Vault, settings: &Settings) {
+        let (Some(service), Some(identity)) = (self.selected_service(), &self.selected) else {
+            return;
+        };
+        if identity.credentials.password.is_empty() {
+            self.status = Some(format!("'{}' has no password", service));
+            return;
+        }
+
+        let result = clipboard::copy(&identity.credentials.password).and_then(|tool| {
+            clipboard::clear_after(settings.clipboard_seconds)?;
+            vault.record_access(service)?;
+            Ok(tool)
+        });
+        self.status = Some(match result {
+            Ok(tool) => {
+                let mut message = format!(
+                    "Copied password for '{}'; clearing in {} seconds.",
+                    service, settings.clipboard_seconds
+                );
+                if let Some(caveat) = tool.caveat {
+                    message.push(' ');
+                    message.push_str(caveat);
+                }
+                message
+            }
+            Err(e) => format!("Copy failed: {:#}", e),
+        });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] = Layout::vertical([Constraint::Min(3), Constraint::Length(2)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let summary = &self.summaries[i];
+                match &summary.icon {
+                    Some(icon) => ListItem::new(format!("{} {}", icon, summary.service)),
+                    None => ListItem::new(summary.service.as_str()),
+                }
+            })
+            .collect();
+        let title = format!(" Identities ({}/{}) ", self.visible.len(), self.summaries.len());
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let details = Paragraph::new(self.detail_lines())
+            .block(Block::default().borders(Borders::ALL).title(" Details "))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, right);
+
+        let search = if self.searching || !self.query.is_empty() {
+            let cursor = if self.searching { "_" } else { "" };
+            format!("/{}{}", self.query, cursor)
+        } else {
+            "/ search  ↑↓ move  c copy password  q quit".to_string()
+        };
+        let status = self.status.clone().unwrap_or_default();
+        let footer_lines = vec![
+            Line::from(Span::styled(search, Style::default().fg(Color::DarkGray))),
+            Line::from(Span::styled(status, Style::default().fg(Color::Yellow))),
+        ];
+        frame.render_widget(Paragraph::new(footer_lines), footer);
+    }
+
+    fn detail_lines(&self) -> Vec<Line<'_>> {
+        let Some(identity) = &self.selected else {
+            return vec![Line::from("No identity selected.")];
+        };
+
+        let field = |label: &'static str, value: String| {
+            Line::from(vec![
+                Span::styled(format!("{:<10}", label), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(value),
+            ])
+        };
+        let password = if identity.credentials.password.is_empty() {
+            "(none)".to_string()
+        } else {
+            "••••••••".to_string()
+        };
+
+        let mut lines = vec![
+            Line::from(Span::styled(
+                identity.service.clone(),
+                Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+            )),
+            Line::from(""),
+            field("Username", identity.credentials.username.clone()),
+            field("Password", password),
+        ];
+        if let Some(email) = &identity.credentials.email {
+            lines.push(field("Email", email.clone()));
+        }
+        if identity.otp.is_some() {
+            lines.push(field("OTP", "set (aliaser otp)".to_string()));
+        }
+        if !identity.attachments.is_empty() {
+            lines.push(field("Files", identity.attachments.len().to_string()));
+        }
+        lines.push(Line::from(""));
+        lines.push(field("Created", identity.created_at.format("%Y-%m-%d %H:%M").to_string()));
+        lines.push(field("Updated", identity.updated_at.format("%Y-%m-%d %H:%M").to_string()));
+        if let Some(notes) = &identity.notes {
+            lines.push(Line::from(""));
+            lines.extend(notes.lines().map(|line| Line::from(line.to_string())));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn summary(service: &str) -> IdentitySummary {
+        IdentitySummary {
+            service: service.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            attachments: 0,
+            rotation_due: None,
+            has_otp: false,
+            has_personal_info: false,
+            favorite: false,
+            color: None,
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn test_filter_keeps_selection() {
+        let mut app = App::new(vec![summary("github"), summary("gitlab"), summary("Mail")]);
+        app.list.select(Some(1));
+
+        app.query = "git".to_string();
+        app.apply_filter();
+        assert_eq!(app.visible.len(), 2);
+        assert_eq!(app.selected_service(), Some("gitlab"));
+
+        app.query = "mail".to_string();
+        app.apply_filter();
+        assert_eq!(app.selected_service(), Some("Mail"));
+
+        app.query = "nothing".to_string();
+        app.apply_filter();
+        assert_eq!(app.selected_service(), None);
+        app.move_cursor(1);
+    }
+}